@@ -5,9 +5,10 @@ extern crate tokio;
 
 use dkregistry::reference;
 use dkregistry::v2::manifest::Manifest;
+use dkregistry::v2::{Client, ReferenceOptions};
+use std::env;
 use std::result::Result;
 use std::str::FromStr;
-use std::{env, fs, io};
 
 mod common;
 
@@ -22,30 +23,17 @@ async fn main() {
 
     println!("[{registry}] downloading image {dkr_ref}");
 
-    let mut user = None;
-    let mut password = None;
+    // Credentials from the environment take precedence over the ones
+    // from the docker-client config.
     let home = dirs::home_dir().unwrap();
-    let cfg = fs::File::open(home.join(".docker/config.json"));
-    if let Ok(fp) = cfg {
-        let creds = dkregistry::get_credentials(io::BufReader::new(fp), &registry);
-        if let Ok(user_pass) = creds {
-            user = user_pass.0;
-            password = user_pass.1;
-        } else {
-            println!("[{registry}] no credentials found in config.json");
-        }
-    } else {
-        user = env::var("DKREG_USER").ok();
-        if user.is_none() {
-            println!("[{registry}] no $DKREG_USER for login user");
-        }
-        password = env::var("DKREG_PASSWD").ok();
-        if password.is_none() {
-            println!("[{registry}] no $DKREG_PASSWD for login password");
-        }
-    };
+    let config = Client::configure()
+        .username(env::var("DKREG_USER").ok())
+        .password(env::var("DKREG_PASSWD").ok());
+    let options = ReferenceOptions::default()
+        .config(config)
+        .credentials_file(home.join(".docker/config.json"));
 
-    let res = run(&dkr_ref, user, password).await;
+    let res = run(&dkr_ref, options).await;
 
     if let Err(e) = res {
         println!("[{registry}] {e}");
@@ -55,22 +43,10 @@ async fn main() {
 
 async fn run(
     dkr_ref: &reference::Reference,
-    user: Option<String>,
-    passwd: Option<String>,
+    options: ReferenceOptions,
 ) -> Result<(), dkregistry::errors::Error> {
-    let client = dkregistry::v2::Client::configure()
-        .registry(&dkr_ref.registry())
-        .insecure_registry(false)
-        .username(user)
-        .password(passwd)
-        .build()?;
-
-    let image = dkr_ref.repository();
-    let login_scope = format!("repository:{image}:pull");
-    let version = dkr_ref.version();
-
-    let dclient = client.authenticate(&[&login_scope]).await?;
-    let manifest = dclient.get_manifest(&image, &version).await?;
+    let dclient = Client::for_reference(dkr_ref, options).await?;
+    let manifest = dclient.get_manifest_for(dkr_ref).await?;
 
     if let Manifest::S1Signed(s1s) = manifest {
         let labels = s1s.get_labels(0);
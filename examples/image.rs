@@ -4,7 +4,6 @@ extern crate serde_json;
 extern crate tokio;
 
 use dkregistry::render;
-use futures::future::try_join_all;
 use std::path::Path;
 use std::result::Result;
 use std::{boxed, env, error, fs, io};
@@ -100,24 +99,15 @@ async fn run(
 
     let dclient = client.authenticate(&[&login_scope]).await?;
     let manifest = dclient.get_manifest(image, version).await?;
-    let layers_digests = manifest.layers_digests(None)?;
+    let layers = manifest.layer_descriptors()?;
 
-    println!("{} -> got {} layer(s)", &image, layers_digests.len(),);
+    println!("{} -> got {} layer(s)", &image, layers.len(),);
 
-    let blob_futures = layers_digests
-        .iter()
-        .map(|layer_digest| dclient.get_blob(image, layer_digest))
-        .collect::<Vec<_>>();
+    tokio::fs::create_dir(path).await?;
+    let can_path = path.canonicalize()?;
 
-    let blobs = try_join_all(blob_futures).await?;
-
-    println!("Downloaded {} layers", blobs.len());
-
-    // TODO: use async io
-    std::fs::create_dir(path).unwrap();
-    let can_path = path.canonicalize().unwrap();
-
-    println!("Unpacking layers to {:?}", &can_path);
-    render::unpack(&blobs, &can_path)?;
+    println!("Rendering layers to {:?}", &can_path);
+    let renderer = render::LayerRenderer::new(&can_path)?;
+    dclient.render_layers(image, &layers, &renderer).await?;
     Ok(())
 }
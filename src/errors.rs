@@ -27,6 +27,8 @@ pub enum Error {
     MimeParse(#[from] mime::FromStrError),
     #[error("missing authentication header {0}")]
     MissingAuthHeader(&'static str),
+    #[error("missing header {0}")]
+    MissingHeader(&'static str),
     #[error("unexpected HTTP status {0}")]
     UnexpectedHttpStatus(reqwest::StatusCode),
     #[error("invalid auth token '{0}'")]
@@ -37,20 +39,52 @@ pub enum Error {
     LoginReturnedBadToken,
     #[error("www-authenticate header parse error")]
     Www(#[from] crate::v2::WwwHeaderParseError),
+    #[error("{0}")]
+    Registry(Box<crate::v2::RegistryError>),
+    #[deprecated(note = "failed requests are reported as `Error::Registry`")]
     #[error("request failed with status {status}")]
     Client { status: reqwest::StatusCode },
+    #[deprecated(note = "failed requests are reported as `Error::Registry`")]
     #[error("request failed with status {status}")]
     Server { status: reqwest::StatusCode },
+    #[error("size mismatch for {digest}: expected {expected} bytes, got {got}")]
+    SizeMismatch {
+        digest: crate::v2::Digest,
+        expected: u64,
+        got: u64,
+    },
+    #[error("io error")]
+    Io(#[from] std::io::Error),
+    #[error("registry does not support range requests")]
+    RangeUnsupported,
+    #[error("too many redirects (more than {0})")]
+    TooManyRedirects(usize),
+    #[error("unexpected Content-Range {0:?}")]
+    ContentRange(String),
     #[error("content digest error")]
     ContentDigestParse(#[from] crate::v2::ContentDigestError),
     #[error("no header Content-Type given and no workaround to apply")]
     MediaTypeSniff,
     #[error("manifest error")]
     Manifest(#[from] crate::v2::manifest::ManifestError),
+    #[error("manifest signature error")]
+    Signature(#[from] crate::v2::manifest::SignatureError),
+    #[error("manifest conversion error")]
+    Conversion(#[from] crate::v2::manifest::ConversionError),
+    #[error("render error")]
+    Render(#[from] crate::render::RenderError),
     #[error("reference is invalid")]
     ReferenceParse(#[from] crate::reference::ReferenceParseError),
     #[error("requested operation requires that credentials are available")]
     NoCredentials,
+    #[error("short-name resolution failed")]
+    ShortName(#[from] crate::shortnames::ShortNameError),
+    #[error("image name is invalid")]
+    ImageNameParse(#[from] crate::reference::ImageNameParseError),
+    #[error("image error")]
+    Image(#[from] crate::image::ImageError),
+    #[error("reference {reference} is not on registry {registry}")]
+    RegistryMismatch { registry: String, reference: String },
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,112 @@
+//! Images stored as plain files in a directory.
+
+// The layout follows the `dir:` transport of containers/image, at
+// https://github.com/containers/image/blob/v5.16.0/directory/directory_transport.go
+
+use super::*;
+use crate::v2::DigestAlgorithm;
+use std::path::{Path, PathBuf};
+
+static VERSION_FILE: &str = "version";
+static VERSION: &str = "Directory Transport Version: 1.1\n";
+static MANIFEST_FILE: &str = "manifest.json";
+
+/// An image stored as plain files in a directory.
+///
+/// The manifest is stored as `manifest.json`, and each blob in a file
+/// named after its encoded digest. Manifests of other instances, such as
+/// the images of a manifest list, are stored as `<encoded>.manifest.json`.
+#[derive(Clone, Debug)]
+pub struct DirImage {
+    dir: PathBuf,
+}
+
+impl DirImage {
+    /// Refer to the image in `dir`, creating it when written to.
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Path at which the blob with the given digest is stored.
+    pub fn path_for(&self, digest: &Digest) -> PathBuf {
+        self.dir.join(digest.encoded())
+    }
+
+    fn instance_path_for(&self, digest: &Digest) -> PathBuf {
+        self.dir
+            .join(format!("{}.{}", digest.encoded(), MANIFEST_FILE))
+    }
+
+    async fn read_manifest(&self, instance: Option<&Digest>) -> Result<RawManifest> {
+        let not_found = || ImageError::ManifestNotFound(self.dir.display().to_string());
+        let bytes = match instance {
+            Some(digest) => match tokio::fs::read(self.instance_path_for(digest)).await {
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => None,
+                res => Some(res?),
+            },
+            None => None,
+        };
+        let bytes = match bytes {
+            Some(bytes) => bytes,
+            None => match tokio::fs::read(self.dir.join(MANIFEST_FILE)).await {
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Err(not_found().into()),
+                res => res?,
+            },
+        };
+
+        let media_type = sniff_media_type(&bytes)?;
+        let digest = match instance {
+            Some(digest) => {
+                let computed = manifest_digest(&media_type, &bytes, digest.algorithm())?;
+                if computed != *digest {
+                    return Err(not_found().into());
+                }
+                computed
+            }
+            None => manifest_digest(&media_type, &bytes, DigestAlgorithm::default())?,
+        };
+        Ok(RawManifest {
+            media_type,
+            bytes,
+            digest,
+        })
+    }
+
+    async fn write_manifest(&self, manifest: &RawManifest) -> Result<()> {
+        write_file(&self.dir.join(VERSION_FILE), VERSION.as_bytes()).await?;
+        write_file(&self.dir.join(MANIFEST_FILE), &manifest.bytes).await
+    }
+}
+
+impl ImageSource for DirImage {
+    fn get_manifest<'a>(
+        &'a self,
+        instance: Option<&'a Digest>,
+    ) -> BoxFuture<'a, Result<RawManifest>> {
+        Box::pin(self.read_manifest(instance))
+    }
+
+    fn get_blob<'a>(&'a self, digest: &'a Digest) -> BoxFuture<'a, Result<BlobStream<'a>>> {
+        Box::pin(async move { open_blob(&self.path_for(digest), digest).await })
+    }
+}
+
+impl ImageDestination for DirImage {
+    fn has_blob<'a>(&'a self, digest: &'a Digest) -> BoxFuture<'a, Result<bool>> {
+        Box::pin(async move { Ok(tokio::fs::try_exists(self.path_for(digest)).await?) })
+    }
+
+    fn put_blob<'a>(
+        &'a self,
+        digest: &'a Digest,
+        blob: BlobStream<'a>,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move { write_blob(&self.path_for(digest), blob).await })
+    }
+
+    fn put_manifest<'a>(&'a self, manifest: &'a RawManifest) -> BoxFuture<'a, Result<()>> {
+        Box::pin(self.write_manifest(manifest))
+    }
+}
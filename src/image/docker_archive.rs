@@ -0,0 +1,255 @@
+//! Archives as written by `docker save` and read by `docker load`.
+
+// The format is specified at
+// https://github.com/moby/moby/blob/v20.10.0/image/spec/v1.2.md#combined-image-json--filesystem-changeset-format
+
+use super::*;
+use crate::reference::Reference;
+use crate::v2::DigestAlgorithm;
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+use tempfile::TempDir;
+
+static MANIFEST_FILE: &str = "manifest.json";
+
+/// An image entry of the archive manifest.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct ArchiveEntry {
+    config: String,
+    #[serde(default)]
+    repo_tags: Vec<String>,
+    layers: Vec<String>,
+}
+
+/// Tag of an image in an archive, e.g. `busybox:latest`.
+fn repo_tag(reference: &Reference) -> Result<String> {
+    match (reference.tag(), reference.digest()) {
+        (_, Some(_)) => Err(ImageError::DigestReference(reference.to_string()).into()),
+        (Some(_), None) => Ok(reference.to_familiar()),
+        (None, None) => Ok(format!("{}:latest", reference.to_familiar())),
+    }
+}
+
+/// An image read from a `docker save` archive.
+///
+/// The archive is unpacked to a temporary directory, and a schema 2
+/// manifest is built for the image from its config and layer files.
+#[derive(Debug)]
+pub struct DockerArchiveSource {
+    manifest: RawManifest,
+    blobs: HashMap<Digest, PathBuf>,
+    _dir: TempDir,
+}
+
+impl DockerArchiveSource {
+    /// Open an archive, to read the image tagged `reference`.
+    ///
+    /// Without a reference, the archive must hold a single image.
+    pub async fn open<P: AsRef<Path>>(archive: P, reference: Option<Reference>) -> Result<Self> {
+        let archive = archive.as_ref().to_path_buf();
+        let dir = tempfile::tempdir()?;
+        let root = dir.path().to_path_buf();
+        let (manifest, blobs) = blocking(move || {
+            unpack(&archive, &root)?;
+            let entries: Vec<ArchiveEntry> =
+                serde_json::from_slice(&fs::read(root.join(MANIFEST_FILE))?)?;
+            let name = archive.display().to_string();
+            let tag = reference.as_ref().map(repo_tag).transpose()?;
+            let mut candidates = entries
+                .iter()
+                .filter(|e| tag.is_none() || e.repo_tags.iter().any(|t| Some(t) == tag.as_ref()));
+            match (candidates.next(), candidates.next()) {
+                (Some(entry), None) => image_manifest(&root, entry),
+                (None, _) => Err(ImageError::ManifestNotFound(name).into()),
+                (Some(_), Some(_)) => Err(ImageError::AmbiguousImage(name).into()),
+            }
+        })
+        .await?;
+
+        Ok(Self {
+            manifest,
+            blobs,
+            _dir: dir,
+        })
+    }
+}
+
+/// Build a schema 2 manifest for an image of an unpacked archive.
+fn image_manifest(
+    root: &Path,
+    entry: &ArchiveEntry,
+) -> Result<(RawManifest, HashMap<Digest, PathBuf>)> {
+    let mut blobs = HashMap::new();
+    let mut describe = |file: &str, media_type: Option<&str>| -> Result<serde_json::Value> {
+        let relative = Path::new(file);
+        if !relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+        {
+            return Err(ImageError::InvalidArchive(format!("invalid path {file}")).into());
+        }
+        let path = root.join(relative);
+        let mut hasher = DigestAlgorithm::default().hasher();
+        let mut magic = [0u8; 2];
+        let mut size = 0;
+        let mut f = fs::File::open(&path)?;
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            let n = f.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            if size == 0 && n >= 2 {
+                magic.copy_from_slice(&buf[..2]);
+            }
+            hasher.update(&buf[..n]);
+            size += n as u64;
+        }
+        let digest = hasher.finalize();
+        let media_type = match media_type {
+            Some(media_type) => media_type.to_string(),
+            // Layers may be gzip-compressed, or plain tar archives.
+            None if magic == [0x1f, 0x8b] => MediaTypes::ImageLayerTgz.to_string(),
+            None => MediaTypes::ImageLayerTar.to_string(),
+        };
+        let descriptor = serde_json::json!({
+            "mediaType": media_type,
+            "size": size,
+            "digest": digest,
+        });
+        blobs.insert(digest, path);
+        Ok(descriptor)
+    };
+
+    let config_type = MediaTypes::ContainerConfigV1.to_string();
+    let config = describe(&entry.config, Some(&config_type))?;
+    let layers = entry
+        .layers
+        .iter()
+        .map(|l| describe(l, None))
+        .collect::<Result<Vec<_>>>()?;
+    let manifest = serde_json::json!({
+        "schemaVersion": 2,
+        "mediaType": MediaTypes::ManifestV2S2.to_string(),
+        "config": config,
+        "layers": layers,
+    });
+
+    let bytes = serde_json::to_vec(&manifest)?;
+    let manifest = RawManifest {
+        digest: DigestAlgorithm::default().digest(&bytes),
+        media_type: MediaTypes::ManifestV2S2,
+        bytes,
+    };
+    Ok((manifest, blobs))
+}
+
+impl ImageSource for DockerArchiveSource {
+    fn get_manifest<'a>(
+        &'a self,
+        instance: Option<&'a Digest>,
+    ) -> BoxFuture<'a, Result<RawManifest>> {
+        Box::pin(async move {
+            match instance {
+                Some(digest) if *digest != self.manifest.digest => {
+                    Err(ImageError::ManifestNotFound(digest.to_string()).into())
+                }
+                _ => Ok(self.manifest.clone()),
+            }
+        })
+    }
+
+    fn get_blob<'a>(&'a self, digest: &'a Digest) -> BoxFuture<'a, Result<BlobStream<'a>>> {
+        Box::pin(async move {
+            let path = self
+                .blobs
+                .get(digest)
+                .ok_or_else(|| ImageError::BlobNotFound(digest.clone()))?;
+            open_blob(path, digest).await
+        })
+    }
+}
+
+/// An image written as a `docker save` archive.
+///
+/// Blobs are written to a temporary directory, which is packed into the
+/// archive on commit, replacing any existing one. Only schema 2 images can
+/// be written. Layers are stored as-is, which `docker load` supports even
+/// when compressed.
+#[derive(Debug)]
+pub struct DockerArchiveDestination {
+    archive: PathBuf,
+    repo_tag: Option<String>,
+    dir: TempDir,
+    entry: Mutex<Option<ArchiveEntry>>,
+}
+
+impl DockerArchiveDestination {
+    /// Prepare a new archive, to write the image tagged `reference`.
+    pub fn create<P: AsRef<Path>>(archive: P, reference: Option<Reference>) -> Result<Self> {
+        Ok(Self {
+            archive: archive.as_ref().to_path_buf(),
+            repo_tag: reference.as_ref().map(repo_tag).transpose()?,
+            dir: tempfile::tempdir()?,
+            entry: Mutex::new(None),
+        })
+    }
+
+    fn path_for(&self, digest: &Digest) -> PathBuf {
+        self.dir.path().join(digest.encoded())
+    }
+}
+
+impl ImageDestination for DockerArchiveDestination {
+    fn has_blob<'a>(&'a self, digest: &'a Digest) -> BoxFuture<'a, Result<bool>> {
+        Box::pin(async move { Ok(tokio::fs::try_exists(self.path_for(digest)).await?) })
+    }
+
+    fn put_blob<'a>(
+        &'a self,
+        digest: &'a Digest,
+        blob: BlobStream<'a>,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move { write_blob(&self.path_for(digest), blob).await })
+    }
+
+    fn put_manifest<'a>(&'a self, manifest: &'a RawManifest) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            if manifest.media_type != MediaTypes::ManifestV2S2 {
+                return Err(Error::UnsupportedMediaType(manifest.media_type.clone()));
+            }
+            let spec: ManifestSchema2Spec = serde_json::from_slice(&manifest.bytes)?;
+            let entry = ArchiveEntry {
+                config: spec.config().digest.encoded().to_string(),
+                repo_tags: self.repo_tag.iter().cloned().collect(),
+                layers: spec
+                    .layer_descriptors()
+                    .iter()
+                    .map(|l| l.digest.encoded().to_string())
+                    .collect(),
+            };
+            *self.entry.lock().expect("poisoned lock") = Some(entry);
+            Ok(())
+        })
+    }
+
+    fn commit(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            let entries = match self.entry.lock().expect("poisoned lock").take() {
+                Some(entry) => vec![entry],
+                None => {
+                    let name = self.archive.display().to_string();
+                    return Err(ImageError::ManifestNotFound(name).into());
+                }
+            };
+            let manifest = serde_json::to_vec(&entries)?;
+            write_file(&self.dir.path().join(MANIFEST_FILE), &manifest).await?;
+
+            let (from, to) = (self.dir.path().to_path_buf(), self.archive.clone());
+            blocking(move || pack(&from, &to)).await
+        })
+    }
+}
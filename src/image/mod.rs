@@ -0,0 +1,554 @@
+//! Image sources and destinations, and copies between them.
+//!
+//! Each transport of a `reference::ImageName` maps to a type of this module,
+//! which implements `ImageSource` and `ImageDestination`:
+//!
+//!  * `docker://` to `RegistryImage`,
+//!  * `oci:` to `OciLayout`, and `oci-archive:` to `OciArchive`,
+//!  * `docker-archive:` to `DockerArchiveSource` and `DockerArchiveDestination`,
+//!  * `dir:` to `DirImage`.
+//!
+//! Blobs are streamed from the source to the destination, and verified on
+//! the way. Manifests can be converted with `CopyOptions::format`, e.g. to
+//! republish schema 1 images.
+//!
+//! ## Example
+//!
+//! ```rust,no_run
+//! # extern crate dkregistry;
+//! # extern crate tokio;
+//! # #[tokio::main]
+//! # async fn main() {
+//! # async fn run() -> dkregistry::errors::Result<()> {
+//! #
+//! use dkregistry::image::{self, CopyOptions};
+//! use dkregistry::reference::ImageName;
+//! use dkregistry::v2::Client;
+//!
+//! // Copy an image from a registry into an OCI layout.
+//! let source: ImageName = "docker://quay.io/coreos/etcd:v3.1.0".parse()?;
+//! let destination: ImageName = "oci:etcd:v3.1.0".parse()?;
+//! let source = image::open_source(&source, Client::configure()).await?;
+//! let destination = image::open_destination(&destination, Client::configure()).await?;
+//! image::copy(source.as_ref(), destination.as_ref(), &CopyOptions::default()).await?;
+//! #
+//! # Ok(())
+//! # };
+//! # run().await.unwrap();
+//! # }
+//! ```
+
+use crate::errors::{Error, Result};
+use crate::mediatypes::MediaTypes;
+use crate::reference::ImageName;
+pub use crate::v2::manifest::ManifestFormat;
+use crate::v2::manifest::{
+    diff_id, manifest_digest, LayerDiff, ManifestError, ManifestList, ManifestSchema1Signed,
+    ManifestSchema2Spec, RawManifest,
+};
+use crate::v2::partial;
+use crate::v2::{Config, ContentDigest, Digest, DigestAlgorithm};
+use async_stream::try_stream;
+use bytes::{Bytes, BytesMut};
+use futures::future::BoxFuture;
+use futures::stream::{BoxStream, StreamExt, TryStreamExt};
+use std::collections::{HashMap, HashSet};
+use std::io::{Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::{fmt, fs, io};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+mod dir;
+pub use self::dir::DirImage;
+
+mod docker_archive;
+pub use self::docker_archive::{DockerArchiveDestination, DockerArchiveSource};
+
+mod oci;
+pub use self::oci::{OciArchive, OciLayout};
+
+mod registry;
+pub use self::registry::RegistryImage;
+
+/// Content of a blob, streamed in chunks.
+pub type BlobStream<'a> = BoxStream<'a, Result<Bytes>>;
+
+/// A location images can be copied from.
+pub trait ImageSource: fmt::Debug + Send + Sync {
+    /// Fetch the manifest of the image.
+    ///
+    /// If `instance` is given, the manifest with that digest is fetched
+    /// instead, e.g. one of the images of a manifest list.
+    fn get_manifest<'a>(
+        &'a self,
+        instance: Option<&'a Digest>,
+    ) -> BoxFuture<'a, Result<RawManifest>>;
+
+    /// Fetch a blob referenced by the manifest, as a stream of its content.
+    ///
+    /// The content need not be verified, `copy` verifies it while streaming.
+    fn get_blob<'a>(&'a self, digest: &'a Digest) -> BoxFuture<'a, Result<BlobStream<'a>>>;
+}
+
+/// A location images can be copied to.
+///
+/// Blobs are stored first, then the manifest referencing them, and
+/// `commit` is called once the whole image has been stored.
+pub trait ImageDestination: fmt::Debug + Send + Sync {
+    /// Check if a blob is already stored, in which case it is not copied again.
+    fn has_blob<'a>(&'a self, digest: &'a Digest) -> BoxFuture<'a, Result<bool>>;
+
+    /// Store a blob from a stream of its content.
+    ///
+    /// The stream fails at its end if the content does not match the digest,
+    /// in which case the blob must not be stored.
+    fn put_blob<'a>(
+        &'a self,
+        digest: &'a Digest,
+        blob: BlobStream<'a>,
+    ) -> BoxFuture<'a, Result<()>>;
+
+    /// Format to convert schema 1 manifests to when `CopyOptions` sets none,
+    /// for destinations that cannot store them as-is.
+    fn schema1_format(&self) -> Option<ManifestFormat> {
+        None
+    }
+
+    /// Store the manifest of the image.
+    fn put_manifest<'a>(&'a self, manifest: &'a RawManifest) -> BoxFuture<'a, Result<()>>;
+
+    /// Finish storing the image.
+    fn commit(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ImageError {
+    #[error("no manifest found for {0}")]
+    ManifestNotFound(String),
+    #[error("blob {0} not found")]
+    BlobNotFound(Digest),
+    #[error("{0} holds more than one image, a reference is required")]
+    AmbiguousImage(String),
+    #[error("reference {0} has a digest, a tag is required")]
+    DigestReference(String),
+    #[error("invalid archive: {0}")]
+    InvalidArchive(String),
+}
+
+/// Options for `copy`.
+#[derive(Clone, Debug)]
+pub struct CopyOptions {
+    architecture: String,
+    os: String,
+    variant: Option<String>,
+    format: Option<ManifestFormat>,
+}
+
+impl Default for CopyOptions {
+    fn default() -> Self {
+        Self {
+            architecture: crate::v2::default_architecture().to_string(),
+            os: "linux".to_string(),
+            variant: None,
+            format: None,
+        }
+    }
+}
+
+impl CopyOptions {
+    /// Set the architecture of the image copied out of a manifest list or
+    /// index, as `PullOptions::architecture` does for pulls.
+    pub fn architecture(mut self, architecture: &str) -> Self {
+        self.architecture = architecture.to_string();
+        self
+    }
+
+    /// Set the OS of the image copied out of a manifest list or index, as
+    /// `PullOptions::os` does for pulls.
+    pub fn os(mut self, os: &str) -> Self {
+        self.os = os.to_string();
+        self
+    }
+
+    /// Set the architecture variant to pick from manifest lists, e.g. `v8`.
+    ///
+    /// By default, any variant of the architecture is picked.
+    pub fn variant(mut self, variant: &str) -> Self {
+        self.variant = Some(variant.to_string());
+        self
+    }
+
+    /// Convert the manifest to the given format (defaults to keeping it as-is).
+    ///
+    /// Converting changes the manifest digest. Schema 1 images can be
+    /// converted, which requires fetching their layers to compute the
+    /// config blob.
+    pub fn format(mut self, format: ManifestFormat) -> Self {
+        self.format = Some(format);
+        self
+    }
+}
+
+/// Open an image to be copied from.
+///
+/// For `docker://` images, `config` is used to build the registry client,
+/// with the registry taken from the reference. It is ignored otherwise.
+pub async fn open_source(name: &ImageName, config: Config) -> Result<Box<dyn ImageSource>> {
+    let source: Box<dyn ImageSource> = match name {
+        ImageName::Docker(r) => Box::new(RegistryImage::connect(config, r, false).await?),
+        ImageName::Oci { path, reference } => Box::new(OciLayout::new(path, reference.clone())),
+        ImageName::OciArchive { path, reference } => {
+            Box::new(OciArchive::open(path, reference.clone()).await?)
+        }
+        ImageName::DockerArchive { path, reference } => {
+            Box::new(DockerArchiveSource::open(path, reference.clone()).await?)
+        }
+        ImageName::Dir(path) => Box::new(DirImage::new(path)),
+    };
+    Ok(source)
+}
+
+/// Open an image to be copied to.
+///
+/// The registry client of `docker://` images is built as in `open_source`,
+/// with push access to the repository.
+pub async fn open_destination(
+    name: &ImageName,
+    config: Config,
+) -> Result<Box<dyn ImageDestination>> {
+    let destination: Box<dyn ImageDestination> = match name {
+        ImageName::Docker(r) => Box::new(RegistryImage::connect(config, r, true).await?),
+        ImageName::Oci { path, reference } => Box::new(OciLayout::new(path, reference.clone())),
+        ImageName::OciArchive { path, reference } => {
+            Box::new(OciArchive::create(path, reference.clone())?)
+        }
+        ImageName::DockerArchive { path, reference } => {
+            Box::new(DockerArchiveDestination::create(path, reference.clone())?)
+        }
+        ImageName::Dir(path) => Box::new(DirImage::new(path)),
+    };
+    Ok(destination)
+}
+
+/// Copy an image from a source to a destination, returning the manifest digest.
+///
+/// Manifest lists and OCI indexes are resolved to the image matching the
+/// platform in `options`, which is then copied on its own. Blobs are verified
+/// against their digest and size before being stored, and blobs already
+/// present in the destination are skipped.
+///
+/// If a format is set in `options`, the manifest is converted to it and the
+/// digest of the converted manifest is returned. Schema 1 manifests are also
+/// converted to the `schema1_format` of destinations that have one.
+pub async fn copy(
+    source: &dyn ImageSource,
+    destination: &dyn ImageDestination,
+    options: &CopyOptions,
+) -> Result<Digest> {
+    let mut manifest = source.get_manifest(None).await?;
+    if let MediaTypes::ManifestList | MediaTypes::OciImageIndex = manifest.media_type {
+        let list: ManifestList = serde_json::from_slice(&manifest.bytes)?;
+        let digest = list
+            .find_platform(
+                &options.architecture,
+                &options.os,
+                options.variant.as_deref(),
+            )
+            .map(|m| m.digest())
+            .ok_or(ManifestError::ArchitectureMismatch)?;
+        trace!("Resolved manifest list to {digest}");
+        manifest = source.get_manifest(Some(&digest)).await?;
+    }
+
+    let format = options.format.or_else(|| match manifest.media_type {
+        MediaTypes::ManifestV2S1 | MediaTypes::ManifestV2S1Signed => destination.schema1_format(),
+        _ => None,
+    });
+    let mut fetched = HashMap::new();
+    if let Some(format) = format {
+        manifest = convert(source, manifest, format, &mut fetched).await?;
+    }
+
+    let mut seen = HashSet::new();
+    for (digest, size) in blob_descriptors(&manifest)? {
+        if !seen.insert(digest.clone()) {
+            continue;
+        }
+        if destination.has_blob(&digest).await? {
+            trace!("Skipping blob {digest}, already present");
+            continue;
+        }
+        let blob = match fetched.remove(&digest) {
+            Some(mut file) => {
+                file.seek(SeekFrom::Start(0))?;
+                file_stream(tokio::fs::File::from_std(file))
+            }
+            None => source.get_blob(&digest).await?,
+        };
+        trace!("Copying blob {digest}");
+        destination
+            .put_blob(&digest, verified(&digest, size, blob))
+            .await?;
+    }
+
+    destination.put_manifest(&manifest).await?;
+    destination.commit().await?;
+    Ok(manifest.digest)
+}
+
+/// Convert an image manifest to the given format.
+///
+/// Blobs fetched or created for the conversion are spooled to temporary
+/// files, which are added to `blobs`.
+async fn convert(
+    source: &dyn ImageSource,
+    manifest: RawManifest,
+    format: ManifestFormat,
+    blobs: &mut HashMap<Digest, fs::File>,
+) -> Result<RawManifest> {
+    let spec: ManifestSchema2Spec = match (&manifest.media_type, format) {
+        (MediaTypes::ManifestV2S2, ManifestFormat::Schema2)
+        | (MediaTypes::OciImageManifest, ManifestFormat::Oci) => return Ok(manifest),
+        (MediaTypes::ManifestV2S2, _) | (MediaTypes::OciImageManifest, _) => {
+            serde_json::from_slice(&manifest.bytes)?
+        }
+        (MediaTypes::ManifestV2S1Signed, _) => {
+            let m: ManifestSchema1Signed = serde_json::from_slice(&manifest.bytes)?;
+            let mut diffs = Vec::new();
+            for digest in m.non_empty_layers()? {
+                let blob = verified(&digest, None, source.get_blob(&digest).await?);
+                let (file, size) = spool(blob).await?;
+                let layer = file.try_clone()?;
+                let diff_id = blocking(move || diff_id(layer)).await?;
+                diffs.push(LayerDiff { size, diff_id });
+                blobs.insert(digest, file);
+            }
+            let (spec, config) = m.to_schema2(&diffs)?;
+            let (file, _) = spool(bytes_stream(config)).await?;
+            blobs.insert(spec.config().digest.clone(), file);
+            spec
+        }
+        (other, _) => return Err(Error::UnsupportedMediaType(other.clone())),
+    };
+
+    let spec = match format {
+        ManifestFormat::Schema2 => spec.to_schema2()?,
+        ManifestFormat::Oci => spec.to_oci()?,
+    };
+    let media_type = format.manifest_media_type();
+    let bytes = serde_json::to_vec(&spec)?;
+    trace!("Converted manifest {} to {media_type}", manifest.digest);
+    Ok(RawManifest {
+        digest: DigestAlgorithm::default().digest(&bytes),
+        media_type,
+        bytes,
+    })
+}
+
+/// List digests and sizes of the blobs referenced by an image manifest.
+fn blob_descriptors(manifest: &RawManifest) -> Result<Vec<(Digest, Option<u64>)>> {
+    match manifest.media_type {
+        MediaTypes::ManifestV2S2 | MediaTypes::OciImageManifest => {
+            let spec: ManifestSchema2Spec = serde_json::from_slice(&manifest.bytes)?;
+            let config = spec.config();
+            let layers = spec.layer_descriptors().into_iter();
+            Ok(std::iter::once((config.digest.clone(), Some(config.size)))
+                .chain(layers.map(|l| (l.digest, l.size)))
+                .collect())
+        }
+        MediaTypes::ManifestV2S1Signed => {
+            let m: ManifestSchema1Signed = serde_json::from_slice(&manifest.bytes)?;
+            Ok(m.get_layers().into_iter().map(|d| (d, None)).collect())
+        }
+        ref other => Err(Error::UnsupportedMediaType(other.clone())),
+    }
+}
+
+fn verify_blob(digest: &Digest, size: Option<u64>, data: &[u8]) -> Result<()> {
+    let got = data.len() as u64;
+    if let Some(expected) = size.filter(|&e| e != got) {
+        return Err(Error::SizeMismatch {
+            digest: digest.clone(),
+            expected,
+            got,
+        });
+    }
+    let mut content_digest = ContentDigest::new(digest.clone());
+    content_digest.update(data);
+    content_digest.verify()?;
+    Ok(())
+}
+
+/// Verify a blob against its digest and expected size while it is streamed.
+///
+/// The size is checked as chunks come, and the digest at the end of the
+/// stream, which fails on mismatch.
+fn verified<'a>(digest: &Digest, size: Option<u64>, mut blob: BlobStream<'a>) -> BlobStream<'a> {
+    let digest = digest.clone();
+    Box::pin(try_stream! {
+        let mut content_digest = ContentDigest::new(digest.clone());
+        let mut got: u64 = 0;
+        while let Some(chunk) = blob.try_next().await? {
+            got += chunk.len() as u64;
+            if let Some(expected) = size.filter(|&e| got > e) {
+                Err(Error::SizeMismatch { digest: digest.clone(), expected, got })?;
+            }
+            content_digest.update(&chunk);
+            yield chunk;
+        }
+        if let Some(expected) = size.filter(|&e| got != e) {
+            Err(Error::SizeMismatch { digest: digest.clone(), expected, got })?;
+        }
+        content_digest.verify()?;
+    })
+}
+
+/// Stream a blob held in memory.
+fn bytes_stream<'a>(data: Vec<u8>) -> BlobStream<'a> {
+    futures::stream::once(async { Ok(Bytes::from(data)) }).boxed()
+}
+
+/// Stream the content of a file.
+fn file_stream<'a>(mut file: tokio::fs::File) -> BlobStream<'a> {
+    Box::pin(try_stream! {
+        loop {
+            let mut chunk = BytesMut::with_capacity(64 * 1024);
+            if file.read_buf(&mut chunk).await? == 0 {
+                break;
+            }
+            yield chunk.freeze();
+        }
+    })
+}
+
+/// Write a blob to an anonymous temporary file, returning it rewound with its size.
+async fn spool(mut blob: BlobStream<'_>) -> Result<(fs::File, u64)> {
+    let mut file = tokio::fs::File::from_std(tempfile::tempfile()?);
+    let mut size = 0;
+    while let Some(chunk) = blob.try_next().await? {
+        file.write_all(&chunk).await?;
+        size += chunk.len() as u64;
+    }
+    file.flush().await?;
+    let mut file = file.into_std().await;
+    file.seek(SeekFrom::Start(0))?;
+    Ok((file, size))
+}
+
+/// Guess the media type of a manifest stored without it.
+pub(crate) fn sniff_media_type(bytes: &[u8]) -> Result<MediaTypes> {
+    #[derive(Deserialize)]
+    struct Probe {
+        #[serde(rename = "schemaVersion")]
+        schema_version: u16,
+        #[serde(rename = "mediaType")]
+        media_type: Option<String>,
+        signatures: Option<serde_json::Value>,
+        manifests: Option<serde_json::Value>,
+    }
+
+    let probe: Probe = serde_json::from_slice(bytes)?;
+    match (probe.media_type, probe.schema_version, probe.signatures) {
+        (Some(media_type), _, _) => Ok(MediaTypes::from_str(&media_type)?),
+        (None, 1, Some(_)) => Ok(MediaTypes::ManifestV2S1Signed),
+        (None, 1, None) => Ok(MediaTypes::ManifestV2S1),
+        // OCI manifests and indexes may omit their media type.
+        (None, 2, _) if probe.manifests.is_some() => Ok(MediaTypes::OciImageIndex),
+        (None, 2, _) => Ok(MediaTypes::OciImageManifest),
+        (None, _, _) => Err(Error::MediaTypeSniff),
+    }
+}
+
+/// Read a blob from a file, reporting a missing file as a missing blob.
+async fn read_blob(path: &Path, digest: &Digest) -> Result<Vec<u8>> {
+    match tokio::fs::read(path).await {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+            Err(ImageError::BlobNotFound(digest.clone()).into())
+        }
+        res => Ok(res?),
+    }
+}
+
+/// Open a blob stored in a file, reporting a missing file as a missing blob.
+async fn open_blob<'a>(path: &Path, digest: &Digest) -> Result<BlobStream<'a>> {
+    match tokio::fs::File::open(path).await {
+        Ok(file) => Ok(file_stream(file)),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+            Err(ImageError::BlobNotFound(digest.clone()).into())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Write a file through a temporary sibling file, creating its parent directory.
+async fn write_file(path: &Path, data: &[u8]) -> Result<()> {
+    write_blob(path, bytes_stream(data.to_vec())).await
+}
+
+/// Write a blob to a file through a temporary sibling file, creating its
+/// parent directory.
+///
+/// The file is only created once the whole stream has been written.
+async fn write_blob(path: &Path, mut blob: BlobStream<'_>) -> Result<()> {
+    let (mut file, temp) = partial::create(path).await?;
+    let written = async {
+        while let Some(chunk) = blob.try_next().await? {
+            file.write_all(&chunk).await?;
+        }
+        file.flush().await?;
+        partial::persist(file, &temp, path).await?;
+        Ok(())
+    }
+    .await;
+    if written.is_err() {
+        partial::discard(&temp).await;
+    }
+    written
+}
+
+/// Run blocking filesystem work, such as archive handling, off the runtime.
+async fn blocking<T, F>(f: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T> + Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(io::Error::other)?
+}
+
+/// Pack the content of a directory into a tar archive.
+fn pack(dir: &Path, archive: &Path) -> Result<()> {
+    let mut entries = fs::read_dir(dir)?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<io::Result<Vec<PathBuf>>>()?;
+    entries.sort();
+
+    let temp = partial::temp_path(archive);
+    let packed = (|| {
+        let mut builder = tar::Builder::new(fs::File::create(&temp)?);
+        for path in entries {
+            let name = path.strip_prefix(dir).expect("entry is in directory");
+            if path.is_dir() {
+                builder.append_dir_all(name, &path)?;
+            } else {
+                builder.append_path_with_name(&path, name)?;
+            }
+        }
+        builder.into_inner()?.sync_all()?;
+        fs::rename(&temp, archive)
+    })();
+    if packed.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    Ok(packed?)
+}
+
+/// Unpack a tar archive into a directory.
+fn unpack(archive: &Path, dir: &Path) -> Result<()> {
+    tar::Archive::new(fs::File::open(archive)?).unpack(dir)?;
+    Ok(())
+}
@@ -0,0 +1,276 @@
+//! OCI image layouts, as directories or tar archives.
+
+// The layout is specified at
+// https://github.com/opencontainers/image-spec/blob/v1.0.2/image-layout.md
+
+use super::*;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+/// Annotation naming the manifests of a layout.
+static REF_NAME_ANNOTATION: &str = "org.opencontainers.image.ref.name";
+static LAYOUT_FILE: &str = "oci-layout";
+static LAYOUT_VERSION: &str = "1.0.0";
+static INDEX_FILE: &str = "index.json";
+static INDEX_MEDIA_TYPE: &str = "application/vnd.oci.image.index.v1+json";
+
+/// Index of the manifests in a layout.
+///
+/// Unknown fields are kept, so that the index can be rewritten as-is.
+#[derive(Debug, Deserialize, Serialize)]
+struct Index {
+    #[serde(rename = "schemaVersion")]
+    schema_version: u16,
+    manifests: Vec<Descriptor>,
+    #[serde(flatten)]
+    other: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Default for Index {
+    fn default() -> Self {
+        let mut other = serde_json::Map::new();
+        other.insert("mediaType".into(), INDEX_MEDIA_TYPE.into());
+        Self {
+            schema_version: 2,
+            manifests: Vec::new(),
+            other,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct Descriptor {
+    #[serde(rename = "mediaType")]
+    media_type: String,
+    digest: Digest,
+    size: u64,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    annotations: BTreeMap<String, String>,
+    #[serde(flatten)]
+    other: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Descriptor {
+    fn ref_name(&self) -> Option<&str> {
+        self.annotations
+            .get(REF_NAME_ANNOTATION)
+            .map(String::as_str)
+    }
+}
+
+/// An image in an OCI layout directory.
+///
+/// The image is the manifest named `reference` in the layout index. Without
+/// a reference, the layout must hold a single manifest to read from, and
+/// manifests are written without a name.
+#[derive(Clone, Debug)]
+pub struct OciLayout {
+    dir: PathBuf,
+    reference: Option<String>,
+}
+
+impl OciLayout {
+    /// Refer to an image in the layout at `dir`, creating it when written to.
+    pub fn new<P: AsRef<Path>>(dir: P, reference: Option<String>) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+            reference,
+        }
+    }
+
+    /// Path at which the blob with the given digest is stored.
+    pub fn path_for(&self, digest: &Digest) -> PathBuf {
+        self.dir
+            .join("blobs")
+            .join(digest.algorithm().name())
+            .join(digest.encoded())
+    }
+
+    fn name(&self) -> String {
+        match &self.reference {
+            Some(r) => format!("{}:{}", self.dir.display(), r),
+            None => self.dir.display().to_string(),
+        }
+    }
+
+    async fn read_index(&self) -> Result<Option<Index>> {
+        match tokio::fs::read(self.dir.join(INDEX_FILE)).await {
+            Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn read_manifest(&self, instance: Option<&Digest>) -> Result<RawManifest> {
+        if let Some(digest) = instance {
+            let bytes = read_blob(&self.path_for(digest), digest).await?;
+            return Ok(RawManifest {
+                media_type: sniff_media_type(&bytes)?,
+                bytes,
+                digest: digest.clone(),
+            });
+        }
+
+        let index = self
+            .read_index()
+            .await?
+            .ok_or_else(|| ImageError::ManifestNotFound(self.name()))?;
+        let mut candidates = index
+            .manifests
+            .iter()
+            .filter(|d| self.reference.is_none() || d.ref_name() == self.reference.as_deref());
+        let descriptor = match (candidates.next(), candidates.next()) {
+            (Some(descriptor), None) => descriptor,
+            (None, _) => return Err(ImageError::ManifestNotFound(self.name()).into()),
+            (Some(_), Some(_)) => return Err(ImageError::AmbiguousImage(self.name()).into()),
+        };
+
+        let bytes = read_blob(&self.path_for(&descriptor.digest), &descriptor.digest).await?;
+        verify_blob(&descriptor.digest, Some(descriptor.size), &bytes)?;
+        Ok(RawManifest {
+            bytes,
+            media_type: MediaTypes::from_str(&descriptor.media_type)?,
+            digest: descriptor.digest.clone(),
+        })
+    }
+
+    async fn write_manifest(&self, manifest: &RawManifest) -> Result<()> {
+        write_file(&self.path_for(&manifest.digest), &manifest.bytes).await?;
+
+        // Replace the manifest with the same name, or the same unnamed manifest.
+        let mut index = self.read_index().await?.unwrap_or_default();
+        index.manifests.retain(|d| match &self.reference {
+            Some(r) => d.ref_name() != Some(r.as_str()),
+            None => d.ref_name().is_some() || d.digest != manifest.digest,
+        });
+        let mut annotations = BTreeMap::new();
+        if let Some(r) = &self.reference {
+            annotations.insert(REF_NAME_ANNOTATION.to_string(), r.clone());
+        }
+        index.manifests.push(Descriptor {
+            media_type: manifest.media_type.to_string(),
+            digest: manifest.digest.clone(),
+            size: manifest.bytes.len() as u64,
+            annotations,
+            other: Default::default(),
+        });
+
+        let layout = serde_json::json!({ "imageLayoutVersion": LAYOUT_VERSION });
+        write_file(&self.dir.join(LAYOUT_FILE), &serde_json::to_vec(&layout)?).await?;
+        write_file(&self.dir.join(INDEX_FILE), &serde_json::to_vec(&index)?).await
+    }
+}
+
+impl ImageSource for OciLayout {
+    fn get_manifest<'a>(
+        &'a self,
+        instance: Option<&'a Digest>,
+    ) -> BoxFuture<'a, Result<RawManifest>> {
+        Box::pin(self.read_manifest(instance))
+    }
+
+    fn get_blob<'a>(&'a self, digest: &'a Digest) -> BoxFuture<'a, Result<BlobStream<'a>>> {
+        Box::pin(async move { open_blob(&self.path_for(digest), digest).await })
+    }
+}
+
+impl ImageDestination for OciLayout {
+    fn has_blob<'a>(&'a self, digest: &'a Digest) -> BoxFuture<'a, Result<bool>> {
+        Box::pin(async move { Ok(tokio::fs::try_exists(self.path_for(digest)).await?) })
+    }
+
+    fn put_blob<'a>(
+        &'a self,
+        digest: &'a Digest,
+        blob: BlobStream<'a>,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move { write_blob(&self.path_for(digest), blob).await })
+    }
+
+    fn schema1_format(&self) -> Option<ManifestFormat> {
+        Some(ManifestFormat::Oci)
+    }
+
+    fn put_manifest<'a>(&'a self, manifest: &'a RawManifest) -> BoxFuture<'a, Result<()>> {
+        Box::pin(self.write_manifest(manifest))
+    }
+}
+
+/// An image in an OCI layout packed as a tar archive.
+///
+/// The archive is unpacked to a temporary directory when read from. When
+/// written to, the layout is built in a temporary directory and packed into
+/// the archive on commit, replacing any existing one.
+#[derive(Debug)]
+pub struct OciArchive {
+    archive: PathBuf,
+    layout: OciLayout,
+    // Removed on drop, so it must outlive the layout.
+    _dir: TempDir,
+}
+
+impl OciArchive {
+    /// Open an existing archive, to read the image named `reference`.
+    pub async fn open<P: AsRef<Path>>(archive: P, reference: Option<String>) -> Result<Self> {
+        let archive = archive.as_ref().to_path_buf();
+        let dir = tempfile::tempdir()?;
+        let (from, to) = (archive.clone(), dir.path().to_path_buf());
+        blocking(move || unpack(&from, &to)).await?;
+        Ok(Self {
+            layout: OciLayout::new(dir.path(), reference),
+            archive,
+            _dir: dir,
+        })
+    }
+
+    /// Prepare a new archive, to write the image named `reference`.
+    pub fn create<P: AsRef<Path>>(archive: P, reference: Option<String>) -> Result<Self> {
+        let dir = tempfile::tempdir()?;
+        Ok(Self {
+            layout: OciLayout::new(dir.path(), reference),
+            archive: archive.as_ref().to_path_buf(),
+            _dir: dir,
+        })
+    }
+}
+
+impl ImageSource for OciArchive {
+    fn get_manifest<'a>(
+        &'a self,
+        instance: Option<&'a Digest>,
+    ) -> BoxFuture<'a, Result<RawManifest>> {
+        self.layout.get_manifest(instance)
+    }
+
+    fn get_blob<'a>(&'a self, digest: &'a Digest) -> BoxFuture<'a, Result<BlobStream<'a>>> {
+        self.layout.get_blob(digest)
+    }
+}
+
+impl ImageDestination for OciArchive {
+    fn has_blob<'a>(&'a self, digest: &'a Digest) -> BoxFuture<'a, Result<bool>> {
+        self.layout.has_blob(digest)
+    }
+
+    fn put_blob<'a>(
+        &'a self,
+        digest: &'a Digest,
+        blob: BlobStream<'a>,
+    ) -> BoxFuture<'a, Result<()>> {
+        self.layout.put_blob(digest, blob)
+    }
+
+    fn schema1_format(&self) -> Option<ManifestFormat> {
+        Some(ManifestFormat::Oci)
+    }
+
+    fn put_manifest<'a>(&'a self, manifest: &'a RawManifest) -> BoxFuture<'a, Result<()>> {
+        self.layout.put_manifest(manifest)
+    }
+
+    fn commit(&self) -> BoxFuture<'_, Result<()>> {
+        let (from, to) = (self.layout.dir.clone(), self.archive.clone());
+        Box::pin(blocking(move || pack(&from, &to)))
+    }
+}
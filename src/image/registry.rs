@@ -0,0 +1,87 @@
+//! Images in a registry.
+
+use super::*;
+use crate::reference::Reference;
+use crate::v2::{Client, Config, ReferenceOptions};
+
+/// An image in a registry repository, named by a tag or digest.
+#[derive(Clone, Debug)]
+pub struct RegistryImage {
+    client: Client,
+    repository: String,
+    reference: String,
+}
+
+impl RegistryImage {
+    /// Refer to an image through a client already set up for its registry.
+    pub fn new(client: Client, repository: &str, reference: &str) -> Self {
+        Self {
+            client,
+            repository: repository.to_string(),
+            reference: reference.to_string(),
+        }
+    }
+
+    /// Build a client for the registry of `reference` from `config`.
+    ///
+    /// The client is authenticated as by `Client::for_reference`, with push
+    /// access if `push` is set. Images are pushed by tag if the reference
+    /// has one, and pulled by digest if it has one.
+    pub async fn connect(config: Config, reference: &Reference, push: bool) -> Result<Self> {
+        let options = ReferenceOptions::default().config(config).push(push);
+        let client = Client::for_reference(reference, options).await?;
+        let version = match (push, reference.tag()) {
+            (true, Some(tag)) => tag.to_string(),
+            _ => reference.version(),
+        };
+        Ok(Self::new(client, &reference.repository(), &version))
+    }
+}
+
+impl ImageSource for RegistryImage {
+    fn get_manifest<'a>(
+        &'a self,
+        instance: Option<&'a Digest>,
+    ) -> BoxFuture<'a, Result<RawManifest>> {
+        let reference = instance.map_or(self.reference.as_str(), Digest::as_str);
+        Box::pin(self.client.get_manifest_raw(&self.repository, reference))
+    }
+
+    fn get_blob<'a>(&'a self, digest: &'a Digest) -> BoxFuture<'a, Result<BlobStream<'a>>> {
+        Box::pin(async move {
+            let blob = self
+                .client
+                .get_blob_stream(&self.repository, digest)
+                .await?;
+            Ok(blob.boxed())
+        })
+    }
+}
+
+impl ImageDestination for RegistryImage {
+    fn has_blob<'a>(&'a self, digest: &'a Digest) -> BoxFuture<'a, Result<bool>> {
+        Box::pin(self.client.has_blob(&self.repository, digest))
+    }
+
+    fn put_blob<'a>(
+        &'a self,
+        digest: &'a Digest,
+        blob: BlobStream<'a>,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(self.client.push_blob_stream(&self.repository, digest, blob))
+    }
+
+    fn put_manifest<'a>(&'a self, manifest: &'a RawManifest) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            self.client
+                .put_manifest(
+                    &self.repository,
+                    &self.reference,
+                    &manifest.media_type,
+                    manifest.bytes.clone(),
+                )
+                .await
+                .map(|_| ())
+        })
+    }
+}
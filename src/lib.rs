@@ -41,9 +41,11 @@ extern crate log;
 extern crate strum_macros;
 
 pub mod errors;
+pub mod image;
 pub mod mediatypes;
 pub mod reference;
 pub mod render;
+pub mod shortnames;
 pub mod v2;
 
 use errors::{Error, Result};
@@ -62,13 +64,29 @@ pub fn get_credentials<T: Read>(
     index: &str,
 ) -> Result<(Option<String>, Option<String>)> {
     let map: Auths = serde_json::from_reader(reader)?;
-    let real_index = match index {
+    let is_docker_hub = reference::DOCKER_HUB_DOMAINS.contains(&index);
+    let real_index = match is_docker_hub {
         // docker.io has some special casing in config.json
-        "docker.io" | "registry-1.docker.io" => "https://index.docker.io/v1/",
-        other => other,
+        true => "https://index.docker.io/v1/",
+        false => index,
     };
-    let auth = match map.auths.get(real_index) {
-        Some(x) => base64::decode(x.auth.as_str())?,
+    // Entries may be keyed by a bare host or by a URL, which for Docker Hub
+    // can point to the index or to the registry itself.
+    let auth = map
+        .auths
+        .iter()
+        .filter(|(key, _)| {
+            let host = key.split_once("://").map_or(key.as_str(), |(_, h)| h);
+            let host = host.split('/').next().unwrap_or_default();
+            match is_docker_hub {
+                true => reference::DOCKER_HUB_DOMAINS.contains(&host),
+                false => host == index,
+            }
+        })
+        // Prefer an exact match, e.g. `https://index.docker.io/v1/`.
+        .min_by_key(|(key, _)| (key.as_str() != real_index, *key));
+    let auth = match auth {
+        Some((_, x)) => base64::decode(x.auth.as_str())?,
         None => return Err(Error::AuthInfoMissing(real_index.to_string())),
     };
     let s = String::from_utf8(auth)?;
@@ -92,3 +110,39 @@ struct Auths {
 struct AuthObj {
     auth: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    // "user:secret"
+    const AUTH: &str = "dXNlcjpzZWNyZXQ=";
+
+    #[test_case("https://index.docker.io/v1/", "registry-1.docker.io"; "hub index url")]
+    #[test_case("docker.io", "registry-1.docker.io"; "hub bare domain")]
+    #[test_case("https://registry-1.docker.io", "docker.io"; "hub registry url")]
+    #[test_case("quay.io", "quay.io"; "bare host")]
+    #[test_case("https://localhost:5000/v2/", "localhost:5000"; "url with port")]
+    fn credentials_found(key: &str, index: &str) {
+        let config = format!(r#"{{"auths":{{"{key}":{{"auth":"{AUTH}"}}}}}}"#);
+        let creds = get_credentials(config.as_bytes(), index).unwrap();
+        assert_eq!(creds, (Some("user".into()), Some("secret".into())));
+    }
+
+    #[test_case("quay.io", "registry-1.docker.io"; "other registry")]
+    #[test_case("https://quay.io.example.com", "quay.io"; "host prefix")]
+    fn credentials_missing(key: &str, index: &str) {
+        let config = format!(r#"{{"auths":{{"{key}":{{"auth":"{AUTH}"}}}}}}"#);
+        assert!(get_credentials(config.as_bytes(), index).is_err());
+    }
+
+    #[test]
+    fn credentials_prefer_exact_key() {
+        let config = format!(
+            r#"{{"auths":{{"docker.io":{{"auth":"b3RoZXI6b3RoZXI="}},"https://index.docker.io/v1/":{{"auth":"{AUTH}"}}}}}}"#
+        );
+        let creds = get_credentials(config.as_bytes(), "docker.io").unwrap();
+        assert_eq!(creds.0.as_deref(), Some("user"));
+    }
+}
@@ -5,6 +5,7 @@ use strum::EnumProperty;
 
 // For schema1 types, see https://docs.docker.com/registry/spec/manifest-v2-1/
 // For schema2 types, see https://docs.docker.com/registry/spec/manifest-v2-2/
+// For OCI types, see https://github.com/opencontainers/image-spec/blob/v1.0.2/media-types.md
 
 #[derive(EnumProperty, EnumString, Display, Debug, Hash, PartialEq, Eq, Clone)]
 pub enum MediaTypes {
@@ -28,10 +29,34 @@ pub enum MediaTypes {
     #[strum(serialize = "application/vnd.docker.image.rootfs.diff.tar.gzip")]
     #[strum(props(Sub = "vnd.docker.image.rootfs.diff.tar.gzip"))]
     ImageLayerTgz,
+    /// Image layer, as an uncompressed tar.
+    #[strum(serialize = "application/vnd.docker.image.rootfs.diff.tar")]
+    #[strum(props(Sub = "vnd.docker.image.rootfs.diff.tar"))]
+    ImageLayerTar,
     /// Configuration object for a container.
     #[strum(serialize = "application/vnd.docker.container.image.v1+json")]
     #[strum(props(Sub = "vnd.docker.container.image.v1+json"))]
     ContainerConfigV1,
+    /// OCI image manifest.
+    #[strum(serialize = "application/vnd.oci.image.manifest.v1+json")]
+    #[strum(props(Sub = "vnd.oci.image.manifest.v1+json"))]
+    OciImageManifest,
+    /// OCI image index.
+    #[strum(serialize = "application/vnd.oci.image.index.v1+json")]
+    #[strum(props(Sub = "vnd.oci.image.index.v1+json"))]
+    OciImageIndex,
+    /// OCI image configuration.
+    #[strum(serialize = "application/vnd.oci.image.config.v1+json")]
+    #[strum(props(Sub = "vnd.oci.image.config.v1+json"))]
+    OciImageConfig,
+    /// OCI image layer, as a gzip-compressed tar.
+    #[strum(serialize = "application/vnd.oci.image.layer.v1.tar+gzip")]
+    #[strum(props(Sub = "vnd.oci.image.layer.v1.tar+gzip"))]
+    OciImageLayerTgz,
+    /// OCI image layer, as an uncompressed tar.
+    #[strum(serialize = "application/vnd.oci.image.layer.v1.tar")]
+    #[strum(props(Sub = "vnd.oci.image.layer.v1.tar"))]
+    OciImageLayerTar,
     /// Generic JSON
     #[strum(serialize = "application/json")]
     #[strum(props(Sub = "json"))]
@@ -43,6 +68,12 @@ impl MediaTypes {
     pub fn from_mime(mtype: &mime::Mime) -> Result<Self> {
         match (mtype.type_(), mtype.subtype(), mtype.suffix()) {
             (mime::APPLICATION, mime::JSON, _) => Ok(MediaTypes::ApplicationJson),
+            (mime::APPLICATION, subt, None) if subt == "vnd.docker.image.rootfs.diff.tar" => {
+                Ok(MediaTypes::ImageLayerTar)
+            }
+            (mime::APPLICATION, subt, None) if subt == "vnd.oci.image.layer.v1.tar" => {
+                Ok(MediaTypes::OciImageLayerTar)
+            }
             (mime::APPLICATION, subt, Some(suff)) => {
                 match (subt.to_string().as_str(), suff.to_string().as_str()) {
                     ("vnd.docker.distribution.manifest.v1", "json") => Ok(MediaTypes::ManifestV2S1),
@@ -55,6 +86,10 @@ impl MediaTypes {
                     }
                     ("vnd.docker.image.rootfs.diff.tar.gzip", _) => Ok(MediaTypes::ImageLayerTgz),
                     ("vnd.docker.container.image.v1", "json") => Ok(MediaTypes::ContainerConfigV1),
+                    ("vnd.oci.image.manifest.v1", "json") => Ok(MediaTypes::OciImageManifest),
+                    ("vnd.oci.image.index.v1", "json") => Ok(MediaTypes::OciImageIndex),
+                    ("vnd.oci.image.config.v1", "json") => Ok(MediaTypes::OciImageConfig),
+                    ("vnd.oci.image.layer.v1.tar", "gzip") => Ok(MediaTypes::OciImageLayerTgz),
                     _ => Err(crate::Error::UnknownMimeType(mtype.clone())),
                 }
             }
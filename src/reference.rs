@@ -17,6 +17,10 @@
 //! assert_eq!(dkref.registry(), "registry-1.docker.io");
 //! assert_eq!(dkref.repository(), "library/busybox");
 //! assert_eq!(dkref.version(), "latest");
+//!
+//! // Print it back, in full or in the short form
+//! assert_eq!(dkref.to_string(), "registry-1.docker.io/library/busybox:latest");
+//! assert_eq!(dkref.to_familiar(), "busybox");
 //! #
 //! # Ok(())
 //! # };
@@ -24,30 +28,80 @@
 //! # }
 //! ```
 //!
+//! Images outside of a registry are named by a transport prefix, as with
+//! skopeo, e.g. `oci:/srv/layout:v1` or `docker-archive:busybox.tar`; see
+//! `ImageName`.
 //!
 
 // The `docker://` schema is not officially documented, but has a reference implementation:
-// https://github.com/docker/distribution/blob/v2.6.1/reference/reference.go
+// https://github.com/distribution/reference/blob/v0.6.0/reference.go
+//
+// The grammar is:
+//
+//  reference        := name [ ":" tag ] [ "@" digest ]
+//  name             := [domain '/'] remote-name
+//  domain           := host [':' port-number]
+//  host             := domain-name | IPv4address | \[ IPv6address \]
+//  domain-name      := domain-component ['.' domain-component]*
+//  domain-component := /([a-zA-Z0-9]|[a-zA-Z0-9][a-zA-Z0-9-]*[a-zA-Z0-9])/
+//  port-number      := /[0-9]+/
+//  remote-name      := path-component ['/' path-component]*
+//  path-component   := alpha-numeric [separator alpha-numeric]*
+//  alpha-numeric    := /[a-z0-9]+/
+//  separator        := /[_.]|__|[-]+/
+//  tag              := /[\w][\w.-]{0,127}/
+//  digest           := digest-algorithm ":" digest-hex
 
+use crate::v2::{ContentDigestError, Digest};
+use regex::Regex;
+use std::cmp::Ordering;
 use std::collections::VecDeque;
-use std::str::FromStr;
+use std::convert::TryFrom;
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+use std::sync::OnceLock;
 use std::{fmt, str};
 
 pub static DEFAULT_REGISTRY: &str = "registry-1.docker.io";
+/// Domains referring to Docker Hub, normalized to `DEFAULT_REGISTRY`.
+pub static DOCKER_HUB_DOMAINS: &[&str] = &["docker.io", "index.docker.io", DEFAULT_REGISTRY];
+static DEFAULT_NAMESPACE: &str = "library";
 static DEFAULT_TAG: &str = "latest";
 static DEFAULT_SCHEME: &str = "docker";
 
+/// Maximum length of a name, including its domain.
+const NAME_TOTAL_LENGTH_MAX: usize = 255;
+
+const DOMAIN_REGEX: &str = r"^(?:(?:[a-zA-Z0-9]|[a-zA-Z0-9][a-zA-Z0-9-]*[a-zA-Z0-9])(?:\.(?:[a-zA-Z0-9]|[a-zA-Z0-9][a-zA-Z0-9-]*[a-zA-Z0-9]))*|\[[a-fA-F0-9:]+\])(?::[0-9]+)?$";
+const PATH_COMPONENT_REGEX: &str = r"^[a-z0-9]+(?:(?:[._]|__|[-]+)[a-z0-9]+)*$";
+const TAG_REGEX: &str = r"^[a-zA-Z0-9_][a-zA-Z0-9_.-]{0,127}$";
+// Grammar of `org.opencontainers.image.ref.name`, from
+// https://github.com/opencontainers/image-spec/blob/v1.0.2/annotations.md
+const OCI_REF_REGEX: &str = r"^[A-Za-z0-9]+(?:(?:[-._:@+]|--)[A-Za-z0-9]+)*(?:/[A-Za-z0-9]+(?:(?:[-._:@+]|--)[A-Za-z0-9]+)*)*$";
+
+static DOMAIN_RE: OnceLock<Regex> = OnceLock::new();
+static PATH_COMPONENT_RE: OnceLock<Regex> = OnceLock::new();
+static TAG_RE: OnceLock<Regex> = OnceLock::new();
+static OCI_REF_RE: OnceLock<Regex> = OnceLock::new();
+
+fn is_match(re: &'static OnceLock<Regex>, regex: &'static str, s: &str) -> bool {
+    re.get_or_init(|| Regex::new(regex).expect("hardcoded regex is invalid"))
+        .is_match(s)
+}
+
 /// Image version, either a tag or a digest.
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub enum Version {
     Tag(String),
-    Digest(String, String),
+    Digest(Digest),
 }
 
 #[derive(thiserror::Error, Debug)]
 pub enum VersionParseError {
-    #[error("wrong digest format: checksum missing")]
-    WrongDigestFormat,
+    #[error("invalid digest")]
+    InvalidDigest(#[from] ContentDigestError),
+    #[error("tag '{0}' does not conform to regex '{TAG_REGEX}'")]
+    InvalidTag(String),
     #[error("unknown prefix: digest must start from : or @")]
     UnknownPrefix,
     #[error("empty string is invalid digest")]
@@ -58,14 +112,14 @@ impl str::FromStr for Version {
     type Err = VersionParseError;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let v = match s.chars().next() {
-            Some(':') => Version::Tag(s.trim_start_matches(':').to_string()),
-            Some('@') => {
-                let r: Vec<&str> = s.trim_start_matches('@').splitn(2, ':').collect();
-                if r.len() != 2 {
-                    return Err(VersionParseError::WrongDigestFormat);
-                };
-                Version::Digest(r[0].to_string(), r[1].to_string())
+            Some(':') => {
+                let tag = &s[1..];
+                if !is_match(&TAG_RE, TAG_REGEX, tag) {
+                    return Err(VersionParseError::InvalidTag(tag.to_string()));
+                }
+                Version::Tag(tag.to_string())
             }
+            Some('@') => Version::Digest(s[1..].parse()?),
             Some(_) => return Err(VersionParseError::UnknownPrefix),
             None => return Err(VersionParseError::Empty),
         };
@@ -81,42 +135,57 @@ impl Default for Version {
 
 impl fmt::Debug for Version {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        let v = match *self {
-            Version::Tag(ref s) => ":".to_string() + s,
-            Version::Digest(ref t, ref d) => "@".to_string() + t + ":" + d,
-        };
-        write!(f, "{v}")
+        match self {
+            Version::Tag(s) => write!(f, ":{s}"),
+            Version::Digest(d) => write!(f, "@{d}"),
+        }
     }
 }
 
 impl fmt::Display for Version {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        let v = match *self {
-            Version::Tag(ref s) => s.to_string(),
-            Version::Digest(ref t, ref d) => t.to_string() + ":" + d,
-        };
-        write!(f, "{v}")
+        match self {
+            Version::Tag(s) => write!(f, "{s}"),
+            Version::Digest(d) => write!(f, "{d}"),
+        }
     }
 }
 
 /// A registry image reference.
-#[derive(Clone, Debug, Default)]
+///
+/// A reference may have both a tag and a digest, e.g. `busybox:1.36@sha256:...`,
+/// in which case the digest identifies the image and the tag is informative.
+///
+/// Docker Hub domains are normalized to `DEFAULT_REGISTRY`. References compare,
+/// hash and serialize as their canonical string form (see `Display`), where a
+/// reference without tag nor digest is the same as one tagged `latest`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
 pub struct Reference {
     raw_input: String,
     registry: String,
     repository: String,
-    version: Version,
+    tag: Option<String>,
+    digest: Option<Digest>,
 }
 
 impl Reference {
     pub fn new(registry: Option<String>, repository: String, version: Option<Version>) -> Self {
-        let reg = registry.unwrap_or_else(|| DEFAULT_REGISTRY.to_string());
-        let ver = version.unwrap_or_else(|| Version::Tag(DEFAULT_TAG.to_string()));
+        let reg = match registry {
+            Some(r) if !DOCKER_HUB_DOMAINS.contains(&r.as_str()) => r,
+            _ => DEFAULT_REGISTRY.to_string(),
+        };
+        let (tag, digest) = match version {
+            Some(Version::Digest(d)) => (None, Some(d)),
+            Some(Version::Tag(t)) => (Some(t), None),
+            None => (Some(DEFAULT_TAG.to_string()), None),
+        };
         Self {
             raw_input: "".into(),
             registry: reg,
             repository,
-            version: ver,
+            tag,
+            digest,
         }
     }
 
@@ -128,8 +197,25 @@ impl Reference {
         self.repository.clone()
     }
 
+    /// Return the tag, if any.
+    pub fn tag(&self) -> Option<&str> {
+        self.tag.as_deref()
+    }
+
+    /// Return the digest, if any.
+    pub fn digest(&self) -> Option<&Digest> {
+        self.digest.as_ref()
+    }
+
+    /// Return the version to fetch the image by.
+    ///
+    /// This is the digest if there is one, otherwise the tag, defaulting to `latest`.
     pub fn version(&self) -> String {
-        self.version.to_string()
+        match (&self.digest, &self.tag) {
+            (Some(d), _) => d.to_string(),
+            (None, Some(t)) => t.clone(),
+            (None, None) => DEFAULT_TAG.to_string(),
+        }
     }
 
     pub fn to_raw_string(&self) -> String {
@@ -138,16 +224,88 @@ impl Reference {
 
     //TODO(lucab): move this to a real URL type
     pub fn to_url(&self) -> String {
-        format!(
-            "{}://{}/{}{:?}",
-            DEFAULT_SCHEME, self.registry, self.repository, self.version
-        )
+        format!("{}://{}", DEFAULT_SCHEME, self)
+    }
+
+    /// Return the short form of this reference, as shown by the Docker CLI.
+    ///
+    /// The default registry and the `library/` namespace are omitted, and
+    /// the tag is only included if present, e.g. `busybox` or `quay.io/foo/bar:1.0`.
+    pub fn to_familiar(&self) -> String {
+        let mut familiar = if self.registry == DEFAULT_REGISTRY {
+            match self.repository.split_once('/') {
+                Some((namespace, rest))
+                    if namespace == DEFAULT_NAMESPACE && !rest.contains('/') =>
+                {
+                    rest.to_string()
+                }
+                _ => self.repository.clone(),
+            }
+        } else {
+            format!("{}/{}", self.registry, self.repository)
+        };
+        if let Some(tag) = &self.tag {
+            familiar.push(':');
+            familiar.push_str(tag);
+        }
+        if let Some(digest) = &self.digest {
+            familiar.push('@');
+            familiar.push_str(digest.as_str());
+        }
+        familiar
+    }
+
+    /// Fields identifying this reference, with the tag defaulting to `latest`.
+    fn key(&self) -> (&str, &str, Option<&str>, Option<&Digest>) {
+        let tag = match (&self.tag, &self.digest) {
+            (None, None) => Some(DEFAULT_TAG),
+            (tag, _) => tag.as_deref(),
+        };
+        (&self.registry, &self.repository, tag, self.digest.as_ref())
+    }
+}
+
+impl PartialEq for Reference {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Reference {}
+
+impl Hash for Reference {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state)
+    }
+}
+
+impl PartialOrd for Reference {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Reference {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
     }
 }
 
 impl fmt::Display for Reference {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        write!(f, "{}/{}{:?}", self.registry, self.repository, self.version)
+        write!(f, "{}/{}", self.registry, self.repository)?;
+        match (&self.tag, &self.digest) {
+            (None, None) => write!(f, ":{DEFAULT_TAG}"),
+            (tag, digest) => {
+                if let Some(tag) = tag {
+                    write!(f, ":{tag}")?;
+                }
+                if let Some(digest) = digest {
+                    write!(f, "@{digest}")?;
+                }
+                Ok(())
+            }
+        }
     }
 }
 
@@ -158,6 +316,19 @@ impl str::FromStr for Reference {
     }
 }
 
+impl TryFrom<String> for Reference {
+    type Error = ReferenceParseError;
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        parse_url(&s)
+    }
+}
+
+impl From<Reference> for String {
+    fn from(r: Reference) -> Self {
+        r.to_string()
+    }
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum ReferenceParseError {
     #[error("missing image name")]
@@ -173,79 +344,87 @@ pub enum ReferenceParseError {
     },
     #[error("empty repository name")]
     EmptyRepositoryName,
-    #[error("repository name too long")]
+    #[error("repository name longer than {NAME_TOTAL_LENGTH_MAX} characters")]
     RepositoryNameTooLong,
 }
 
-fn parse_url(input: &str) -> Result<Reference, ReferenceParseError> {
-    // TODO(lucab): investigate using a grammar-based parser.
-    let mut rest = input;
+/// Whether the first component of a name is a registry domain.
+///
+/// As with the Docker CLI, it is a domain if it contains a `.` or a `:`,
+/// is `localhost`, or contains uppercase letters (which names cannot).
+pub(crate) fn is_domain(component: &str) -> bool {
+    component.contains(['.', ':'])
+        || component == "localhost"
+        || component.chars().any(|c| c.is_ascii_uppercase())
+}
 
+fn parse_url(input: &str) -> Result<Reference, ReferenceParseError> {
     // Detect and remove schema.
-    let has_schema = rest.starts_with("docker://");
-    if has_schema {
-        rest = input.trim_start_matches("docker://");
-    };
-
-    // Split path components apart and retain non-empty ones.
-    let mut components: VecDeque<String> = rest
-        .split('/')
-        .filter(|s| !s.is_empty())
-        .map(String::from)
-        .collect();
+    let rest = input.strip_prefix("docker://").unwrap_or(input);
+    if rest.is_empty() {
+        return Err(ReferenceParseError::MissingImageName);
+    }
 
-    // Figure out if the first component is a registry String, and assume the
-    // default registry if it's not.
-    let first = components
-        .pop_front()
-        .ok_or(ReferenceParseError::MissingImageName)?;
-
-    let registry = if regex::Regex::new(r"(?x)
-        ^
-        # hostname
-        (([a-zA-Z0-9]|[a-zA-Z0-9][a-zA-Z0-9\-]*[a-zA-Z0-9])\.)+([A-Za-z0-9]|[A-Za-z0-9][A-Za-z0-9\-]*[A-Za-z0-9])
-
-        # optional port
-        ([:][0-9]{1,6})?
-        $
-    ").expect("hardcoded regex is invalid").is_match(&first) {
-        first
-    } else {
-        components.push_front(first);
-        DEFAULT_REGISTRY.to_string()
+    // Split off the digest, then the tag, if any. Neither can contain a `/`.
+    let (name, digest) = match rest.split_once('@') {
+        Some((name, digest)) => {
+            let digest = digest.parse().map_err(VersionParseError::from)?;
+            (name, Some(digest))
+        }
+        None => (rest, None),
     };
-
-    // Take image name and extract tag or digest-ref, if any.
-    let last = components
-        .pop_back()
-        .ok_or(ReferenceParseError::MissingImageName)?;
-    let (image_name, version) = match (last.rfind('@'), last.rfind(':')) {
-        (Some(i), _) | (None, Some(i)) => {
-            let s = last.split_at(i);
-            (String::from(s.0), Version::from_str(s.1)?)
+    let last_slash = name.rfind('/').map(|i| i + 1).unwrap_or(0);
+    let (name, tag) = match name[last_slash..].rfind(':') {
+        Some(i) => {
+            let (name, tag) = (&name[..last_slash + i], &name[last_slash + i + 1..]);
+            if !is_match(&TAG_RE, TAG_REGEX, tag) {
+                return Err(VersionParseError::InvalidTag(tag.to_string()).into());
+            }
+            (name, Some(tag.to_string()))
         }
-        (None, None) => (last, Version::default()),
+        None => (name, None),
     };
-    if image_name.is_empty() {
+    if name.is_empty() {
         return Err(ReferenceParseError::EmptyImageName);
     }
+    if name.len() > NAME_TOTAL_LENGTH_MAX {
+        return Err(ReferenceParseError::RepositoryNameTooLong);
+    }
+
+    // Figure out if the first component is a registry, and assume the
+    // default registry if it's not.
+    let mut components: VecDeque<&str> = name.split('/').collect();
+    let registry = match components.front() {
+        Some(first) if components.len() > 1 && is_domain(first) => {
+            if !is_match(&DOMAIN_RE, DOMAIN_REGEX, first) {
+                return Err(ReferenceParseError::RegexViolation {
+                    component: first.to_string(),
+                    regex: DOMAIN_REGEX,
+                });
+            }
+            let registry = if DOCKER_HUB_DOMAINS.contains(first) {
+                DEFAULT_REGISTRY.to_string()
+            } else {
+                first.to_string()
+            };
+            components.pop_front();
+            registry
+        }
+        _ => DEFAULT_REGISTRY.to_string(),
+    };
 
     // Handle images in default library namespace, that is:
     // `ubuntu` -> `library/ubuntu`
-    if components.is_empty() && registry == DEFAULT_REGISTRY {
-        components.push_back("library".to_string());
+    if components.len() == 1 && registry == DEFAULT_REGISTRY {
+        components.push_front(DEFAULT_NAMESPACE);
     }
-    components.push_back(image_name);
 
-    // Check if all path components conform to the regex at
-    // https://docs.docker.com/registry/spec/api/#overview.
-    const REGEX: &str = "^[a-z0-9]+(?:[._-][a-z0-9]+)*$";
-    let path_re = regex::Regex::new(REGEX).expect("hardcoded regex is invalid");
+    // Check if all path components conform to the grammar.
     components.iter().try_for_each(|component| {
-        if !path_re.is_match(component) {
+        if !is_match(&PATH_COMPONENT_RE, PATH_COMPONENT_REGEX, component) {
             return Err(ReferenceParseError::RegexViolation {
-                component: component.clone(),
-                regex: REGEX,
+                component: component.to_string(),
+                regex: PATH_COMPONENT_REGEX,
             });
         };
 
@@ -257,14 +436,131 @@ fn parse_url(input: &str) -> Result<Reference, ReferenceParseError> {
     if repository.is_empty() {
         return Err(ReferenceParseError::EmptyRepositoryName);
     }
-    if repository.len() > 127 {
-        return Err(ReferenceParseError::RepositoryNameTooLong);
-    }
 
     Ok(Reference {
         raw_input: input.to_string(),
         registry,
         repository,
-        version,
+        tag,
+        digest,
     })
 }
+
+/// An image name qualified by its transport, as used by skopeo.
+///
+/// Each transport is opened as an `ImageSource` or an `ImageDestination`
+/// by `image::open_source` and `image::open_destination`, so that
+/// `image::copy` can move images between any two of them.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ImageName {
+    /// An image in a registry, `docker://<reference>`.
+    Docker(Reference),
+    /// An image in an OCI layout directory, `oci:<path>[:<reference>]`.
+    Oci {
+        path: PathBuf,
+        reference: Option<String>,
+    },
+    /// An OCI layout packed as a tar archive, `oci-archive:<path>[:<reference>]`.
+    OciArchive {
+        path: PathBuf,
+        reference: Option<String>,
+    },
+    /// An archive as written by `docker save`, `docker-archive:<path>[:<reference>]`.
+    DockerArchive {
+        path: PathBuf,
+        reference: Option<Reference>,
+    },
+    /// A directory holding the manifest and blobs as plain files, `dir:<path>`.
+    Dir(PathBuf),
+}
+
+impl fmt::Display for ImageName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let (transport, path, reference) = match self {
+            ImageName::Docker(r) => return write!(f, "{}", r.to_url()),
+            ImageName::Oci { path, reference } => ("oci", path, reference.clone()),
+            ImageName::OciArchive { path, reference } => ("oci-archive", path, reference.clone()),
+            ImageName::DockerArchive { path, reference } => (
+                "docker-archive",
+                path,
+                reference.as_ref().map(Reference::to_familiar),
+            ),
+            ImageName::Dir(path) => ("dir", path, None),
+        };
+        write!(f, "{}:{}", transport, path.display())?;
+        if let Some(reference) = reference {
+            write!(f, ":{reference}")?;
+        }
+        Ok(())
+    }
+}
+
+impl str::FromStr for ImageName {
+    type Err = ImageNameParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (transport, rest) = s
+            .split_once(':')
+            .ok_or(ImageNameParseError::MissingTransport)?;
+        if transport == "docker" {
+            let rest = rest
+                .strip_prefix("//")
+                .ok_or(ImageNameParseError::MissingTransport)?;
+            return Ok(ImageName::Docker(parse_url(rest)?));
+        }
+        if transport == "dir" {
+            if rest.is_empty() {
+                return Err(ImageNameParseError::EmptyPath);
+            }
+            return Ok(ImageName::Dir(PathBuf::from(rest)));
+        }
+
+        // Paths cannot contain a `:`, everything after it is the reference.
+        let (path, reference) = match rest.split_once(':') {
+            Some((path, reference)) => (path, Some(reference)),
+            None => (rest, None),
+        };
+        if path.is_empty() {
+            return Err(ImageNameParseError::EmptyPath);
+        }
+        let path = PathBuf::from(path);
+        let oci_reference = || -> Result<Option<String>, ImageNameParseError> {
+            match reference {
+                Some(r) if !is_match(&OCI_REF_RE, OCI_REF_REGEX, r) => {
+                    Err(ImageNameParseError::InvalidOciReference(r.to_string()))
+                }
+                r => Ok(r.map(String::from)),
+            }
+        };
+
+        let name = match transport {
+            "oci" => ImageName::Oci {
+                path,
+                reference: oci_reference()?,
+            },
+            "oci-archive" => ImageName::OciArchive {
+                path,
+                reference: oci_reference()?,
+            },
+            "docker-archive" => ImageName::DockerArchive {
+                path,
+                reference: reference.map(parse_url).transpose()?,
+            },
+            other => return Err(ImageNameParseError::UnknownTransport(other.to_string())),
+        };
+        Ok(name)
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ImageNameParseError {
+    #[error("missing transport, e.g. 'docker://'")]
+    MissingTransport,
+    #[error("unknown transport '{0}'")]
+    UnknownTransport(String),
+    #[error("empty path")]
+    EmptyPath,
+    #[error("reference '{0}' does not conform to regex '{OCI_REF_REGEX}'")]
+    InvalidOciReference(String),
+    #[error("reference is invalid")]
+    Reference(#[from] ReferenceParseError),
+}
@@ -2,9 +2,16 @@
 
 // Docker image format is specified at
 // https://github.com/moby/moby/blob/v17.05.0-ce/image/spec/v1.md
+// Whiteouts are specified at
+// https://github.com/opencontainers/image-spec/blob/v1.0.2/layer.md#whiteouts
 
 use libflate::gzip;
-use std::{fs, io, path};
+use std::collections::HashSet;
+use std::future::Future;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::sync::Arc;
+use std::{fmt, fs, io, path};
+use tokio::io::AsyncRead;
 
 #[derive(Debug, thiserror::Error)]
 pub enum RenderError {
@@ -16,19 +23,20 @@ pub enum RenderError {
 
 /// Unpack an ordered list of layers to a target directory.
 ///
-/// Layers must be provided as gzip-compressed tar archives, with lower layers
-/// coming first. Target directory must be an existing absolute path.
+/// Layers must be provided as tar archives, gzip-compressed or not, with
+/// lower layers coming first. Target directory must be an existing absolute path.
 pub fn unpack(layers: &[Vec<u8>], target_dir: &path::Path) -> Result<(), RenderError> {
-    _unpack(layers, target_dir, |mut archive, target_dir| {
-        Ok(archive.unpack(target_dir)?)
-    })
+    let renderer = LayerRenderer::new(target_dir)?;
+    layers
+        .iter()
+        .try_for_each(|l| renderer.apply_with(l.as_slice(), &|_| true))
 }
 
 /// Unpack an ordered list of layers to a target directory, filtering
 /// file entries by path.
 ///
-/// Layers must be provided as gzip-compressed tar archives, with lower layers
-/// coming first. Target directory must be an existing absolute path.
+/// Layers must be provided as tar archives, gzip-compressed or not, with
+/// lower layers coming first. Target directory must be an existing absolute path.
 pub fn filter_unpack<P>(
     layers: &[Vec<u8>],
     target_dir: &path::Path,
@@ -37,62 +45,239 @@ pub fn filter_unpack<P>(
 where
     P: Fn(&path::Path) -> bool,
 {
-    _unpack(layers, target_dir, |mut archive, target_dir| {
+    let renderer = LayerRenderer::new(target_dir)?;
+    layers
+        .iter()
+        .try_for_each(|l| renderer.apply_with(l.as_slice(), &predicate))
+}
+
+/// Renderer unpacking layers one at a time to a target directory.
+///
+/// Each layer is read once, as a tar archive either gzip-compressed or not,
+/// and its whiteouts are applied to the lower layers while it is unpacked.
+#[derive(Clone)]
+pub struct LayerRenderer {
+    target_dir: path::PathBuf,
+    filter: Option<Filter>,
+}
+
+type Filter = Arc<dyn Fn(&path::Path) -> bool + Send + Sync>;
+
+impl fmt::Debug for LayerRenderer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LayerRenderer")
+            .field("target_dir", &self.target_dir)
+            .field("filter", &self.filter.is_some())
+            .finish()
+    }
+}
+
+impl LayerRenderer {
+    /// Create a renderer for a target directory, which must be an existing absolute path.
+    pub fn new(target_dir: &path::Path) -> Result<Self, RenderError> {
+        if !target_dir.is_absolute() || !target_dir.exists() || !target_dir.is_dir() {
+            return Err(RenderError::WrongTargetPath(target_dir.to_path_buf()));
+        }
+        Ok(Self {
+            target_dir: target_dir.to_path_buf(),
+            filter: None,
+        })
+    }
+
+    /// Only unpack file entries whose path matches the predicate.
+    pub fn filter<P>(mut self, predicate: P) -> Self
+    where
+        P: Fn(&path::Path) -> bool + Send + Sync + 'static,
+    {
+        self.filter = Some(Arc::new(predicate));
+        self
+    }
+
+    /// Unpack a layer on top of the previous ones.
+    pub fn apply<R: Read>(&self, layer: R) -> Result<(), RenderError> {
+        match &self.filter {
+            Some(predicate) => self.apply_with(layer, predicate.as_ref()),
+            None => self.apply_with(layer, &|_| true),
+        }
+    }
+
+    fn apply_with<R: Read>(
+        &self,
+        layer: R,
+        predicate: &dyn Fn(&path::Path) -> bool,
+    ) -> Result<(), RenderError> {
+        let mut reader = BufReader::new(layer);
+        if reader.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+            self.unpack_archive(gzip::MultiDecoder::new(reader)?, predicate)
+        } else {
+            self.unpack_archive(reader, predicate)
+        }
+    }
+
+    fn unpack_archive<R: Read>(
+        &self,
+        layer: R,
+        predicate: &dyn Fn(&path::Path) -> bool,
+    ) -> Result<(), RenderError> {
+        let mut archive = tar::Archive::new(layer);
+        archive.set_preserve_permissions(true);
+        archive.set_unpack_xattrs(true);
+
+        // Whiteouts only hide lower layers, never entries of their own layer.
+        let mut unpacked = HashSet::new();
         for entry in archive.entries()? {
             let mut entry = entry?;
-            let path = entry.path()?;
+            let path = entry.path()?.into_owned();
+            let relative = match relative_path(&path) {
+                Some(relative) => relative,
+                None => continue,
+            };
+            let parent = relative.parent().unwrap_or_else(|| path::Path::new(""));
+            let name = relative
+                .file_name()
+                .map(|f| f.to_string_lossy().into_owned())
+                .unwrap_or_default();
 
-            if predicate(&path) {
-                entry.unpack_in(target_dir)?;
+            if name == ".wh..wh..opq" {
+                self.remove_opaque(parent, &unpacked)?;
+            } else if let Some(real_name) = name.strip_prefix(".wh.") {
+                let real_path = parent.join(real_name);
+                if unpacked.contains(&real_path) {
+                    continue;
+                }
+                if let Some(dir) = self.resolve_dir(parent)? {
+                    remove_whiteout(dir.join(real_name))?;
+                }
+            } else if predicate(&path) {
+                entry.unpack_in(&self.target_dir)?;
+                unpacked.insert(relative);
             }
         }
+        Ok(())
+    }
 
+    /// Remove the content of a directory coming from lower layers.
+    fn remove_opaque(
+        &self,
+        dir: &path::Path,
+        unpacked: &HashSet<path::PathBuf>,
+    ) -> Result<(), RenderError> {
+        let target = match self.resolve_dir(dir)? {
+            Some(target) => target,
+            None => return Ok(()),
+        };
+        let children = match fs::read_dir(target) {
+            Ok(children) => children,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        for child in children {
+            let child = dir.join(child?.file_name());
+            if !unpacked.iter().any(|p| p.starts_with(&child)) {
+                remove_whiteout(self.target_dir.join(child))?;
+            }
+        }
         Ok(())
-    })
+    }
 }
 
-fn _unpack<U>(layers: &[Vec<u8>], target_dir: &path::Path, unpacker: U) -> Result<(), RenderError>
-where
-    U: Fn(tar::Archive<gzip::Decoder<&[u8]>>, &path::Path) -> Result<(), RenderError>,
-{
-    if !target_dir.is_absolute() || !target_dir.exists() || !target_dir.is_dir() {
-        return Err(RenderError::WrongTargetPath(target_dir.to_path_buf()));
+impl LayerRenderer {
+    /// Resolve a directory relative to the target directory, without
+    /// following symlinks.
+    ///
+    /// Lower layers may have replaced any component with a symlink pointing
+    /// outside of the target directory, through which whiteouts must not
+    /// remove anything. Returns `None` if a component is missing, is a
+    /// symlink or is not a directory.
+    fn resolve_dir(&self, dir: &path::Path) -> io::Result<Option<path::PathBuf>> {
+        let mut resolved = self.target_dir.clone();
+        for component in dir.components() {
+            resolved.push(component);
+            match fs::symlink_metadata(&resolved) {
+                Ok(metadata) if metadata.file_type().is_symlink() => {
+                    warn!(
+                        "not applying whiteout through symlink {}",
+                        resolved.display()
+                    );
+                    return Ok(None);
+                }
+                Ok(metadata) if metadata.is_dir() => {}
+                Ok(_) => return Ok(None),
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(Some(resolved))
     }
-    for l in layers {
-        // Unpack layers
-        let gz_dec = gzip::Decoder::new(l.as_slice())?;
-        let mut archive = tar::Archive::new(gz_dec);
-        archive.set_preserve_permissions(true);
-        archive.set_unpack_xattrs(true);
-        unpacker(archive, target_dir)?;
+}
 
-        // Clean whiteouts
-        let gz_dec = gzip::Decoder::new(l.as_slice())?;
-        let mut archive = tar::Archive::new(gz_dec);
-        for entry in archive.entries()? {
-            let file = entry?;
-            let path = file.path()?;
-            let parent = path.parent().unwrap_or_else(|| path::Path::new("/"));
-            if let Some(fname) = path.file_name() {
-                let wh_name = fname.to_string_lossy();
-                if wh_name == ".wh..wh..opq" {
-                    //TODO(lucab): opaque whiteout, dir removal
-                } else if wh_name.starts_with(".wh.") {
-                    let rel_parent =
-                        path::PathBuf::from("./".to_string() + &parent.to_string_lossy());
-
-                    // Remove real file behind whiteout
-                    let real_name = wh_name.trim_start_matches(".wh.");
-                    let abs_real_path = target_dir.join(&rel_parent).join(real_name);
-                    remove_whiteout(abs_real_path)?;
-
-                    // Remove whiteout place-holder
-                    let abs_wh_path = target_dir.join(&rel_parent).join(fname);
-                    remove_whiteout(abs_wh_path)?;
-                };
-            }
+/// Path of an archive entry relative to the target directory, unless it
+/// escapes it.
+fn relative_path(path: &path::Path) -> Option<path::PathBuf> {
+    let mut relative = path::PathBuf::new();
+    for component in path.components() {
+        match component {
+            path::Component::Normal(c) => relative.push(c),
+            path::Component::RootDir | path::Component::CurDir => {}
+            path::Component::ParentDir | path::Component::Prefix(_) => return None,
         }
     }
+    Some(relative)
+}
+
+/// Render layers fetched asynchronously to the target directory of `renderer`.
+///
+/// Each item of `layers` resolves to the content of a layer, with lower
+/// layers coming first. Layers are spooled to temporary files, the next one
+/// being downloaded while the previous one is unpacked.
+pub async fn render_async<I, F, R>(renderer: &LayerRenderer, layers: I) -> crate::errors::Result<()>
+where
+    I: IntoIterator<Item = F>,
+    F: Future<Output = crate::errors::Result<R>>,
+    R: AsyncRead + Unpin,
+{
+    let spools = layers.into_iter().map(|layer| async {
+        let mut reader = layer.await?;
+        let mut file = tokio::fs::File::from_std(tempfile::tempfile()?);
+        tokio::io::copy(&mut reader, &mut file).await?;
+        Ok(file)
+    });
+    render_spooled(renderer, spools).await
+}
+
+/// Render layers as they are spooled to files, each one while the next one
+/// is being spooled.
+pub(crate) async fn render_spooled<I, F>(
+    renderer: &LayerRenderer,
+    spools: I,
+) -> crate::errors::Result<()>
+where
+    I: IntoIterator<Item = F>,
+    F: Future<Output = crate::errors::Result<tokio::fs::File>>,
+{
+    let mut pending = None;
+    for spool in spools {
+        let (file, ()) = futures::try_join!(spool, unpack_spooled(renderer, pending.take()))?;
+        pending = Some(file);
+    }
+    unpack_spooled(renderer, pending).await
+}
+
+async fn unpack_spooled(
+    renderer: &LayerRenderer,
+    file: Option<tokio::fs::File>,
+) -> crate::errors::Result<()> {
+    let mut file = match file {
+        Some(file) => file.into_std().await,
+        None => return Ok(()),
+    };
+    let renderer = renderer.clone();
+    tokio::task::spawn_blocking(move || {
+        file.seek(SeekFrom::Start(0))?;
+        renderer.apply(file)
+    })
+    .await
+    .map_err(io::Error::other)??;
     Ok(())
 }
 
@@ -101,7 +286,11 @@ where
 // error is non-fatal.  Otherwise still return error for other
 // failures.
 fn remove_whiteout(path: path::PathBuf) -> io::Result<()> {
-    let res = fs::remove_dir_all(path);
+    let res = match fs::symlink_metadata(&path) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(e) => Err(e),
+    };
 
     match res {
         Ok(_) => res,
@@ -111,3 +300,144 @@ fn remove_whiteout(path: path::PathBuf) -> io::Result<()> {
         },
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    /// Build a layer from (path, content) entries, directories ending with `/`.
+    fn layer(entries: &[(&str, &str)], compress: bool) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, content) in entries {
+            let mut header = tar::Header::new_gnu();
+            match path.ends_with('/') {
+                true => {
+                    header.set_entry_type(tar::EntryType::Directory);
+                    header.set_mode(0o755);
+                    header.set_size(0);
+                }
+                false => {
+                    header.set_mode(0o644);
+                    header.set_size(content.len() as u64);
+                }
+            }
+            builder
+                .append_data(&mut header, path, content.as_bytes())
+                .unwrap();
+        }
+        let tar = builder.into_inner().unwrap();
+        if !compress {
+            return tar;
+        }
+        let mut encoder = gzip::Encoder::new(Vec::new()).unwrap();
+        encoder.write_all(&tar).unwrap();
+        encoder.finish().into_result().unwrap()
+    }
+
+    fn layers() -> Vec<Vec<u8>> {
+        vec![
+            layer(
+                &[
+                    ("etc/", ""),
+                    ("etc/hosts", "base"),
+                    ("etc/passwd", "base"),
+                    ("var/", ""),
+                    ("var/cache/", ""),
+                    ("var/cache/a", "base"),
+                    ("var/cache/b", "base"),
+                ],
+                true,
+            ),
+            layer(
+                &[
+                    ("etc/.wh.passwd", ""),
+                    ("var/cache/", ""),
+                    ("var/cache/.wh..wh..opq", ""),
+                    ("var/cache/c", "top"),
+                    // Whiteouts never hide entries of their own layer.
+                    ("etc/hosts", "top"),
+                    ("etc/.wh.hosts", ""),
+                ],
+                false,
+            ),
+        ]
+    }
+
+    fn read(dir: &path::Path, path: &str) -> Option<String> {
+        fs::read_to_string(dir.join(path)).ok()
+    }
+
+    #[test]
+    fn test_unpack_whiteouts() {
+        let dir = tempfile::tempdir().unwrap();
+        unpack(&layers(), dir.path()).unwrap();
+
+        assert_eq!(read(dir.path(), "etc/hosts").as_deref(), Some("top"));
+        assert_eq!(read(dir.path(), "etc/passwd"), None);
+        assert_eq!(read(dir.path(), "etc/.wh.passwd"), None);
+        let mut cache = fs::read_dir(dir.path().join("var/cache"))
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        cache.sort();
+        assert_eq!(cache, vec!["c"]);
+    }
+
+    #[test]
+    fn test_filter_unpack() {
+        let dir = tempfile::tempdir().unwrap();
+        filter_unpack(&layers(), dir.path(), |p| p.starts_with("etc")).unwrap();
+
+        assert_eq!(read(dir.path(), "etc/hosts").as_deref(), Some("top"));
+        assert!(!dir.path().join("var").exists());
+    }
+
+    #[test]
+    fn test_whiteouts_through_symlink() {
+        let outside = tempfile::tempdir().unwrap();
+        fs::write(outside.path().join("victim"), "host").unwrap();
+        fs::write(outside.path().join("other"), "host").unwrap();
+
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        builder
+            .append_link(&mut header, "etc", outside.path())
+            .unwrap();
+        let base = builder.into_inner().unwrap();
+        let top = layer(&[("etc/.wh.victim", ""), ("etc/.wh..wh..opq", "")], false);
+
+        let dir = tempfile::tempdir().unwrap();
+        unpack(&[base, top], dir.path()).unwrap();
+
+        assert_eq!(read(outside.path(), "victim").as_deref(), Some("host"));
+        assert_eq!(read(outside.path(), "other").as_deref(), Some("host"));
+        assert!(fs::symlink_metadata(dir.path().join("etc")).is_ok());
+    }
+
+    #[test]
+    fn test_wrong_target_path() {
+        match LayerRenderer::new(path::Path::new("relative")) {
+            Err(RenderError::WrongTargetPath(_)) => {}
+            r => panic!("expected a wrong target path, got {:?}", r),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_render_async() {
+        let dir = tempfile::tempdir().unwrap();
+        let renderer = LayerRenderer::new(dir.path())
+            .unwrap()
+            .filter(|p| !p.starts_with("var"));
+        let layers = layers()
+            .into_iter()
+            .map(|l| async move { Ok(io::Cursor::new(l)) });
+        render_async(&renderer, layers).await.unwrap();
+
+        assert_eq!(read(dir.path(), "etc/hosts").as_deref(), Some("top"));
+        assert_eq!(read(dir.path(), "etc/passwd"), None);
+        assert!(!dir.path().join("var").exists());
+    }
+}
@@ -0,0 +1,442 @@
+//! Short-name resolution for image references.
+//!
+//! This module implements the short-name semantics used by the `containers`
+//! tooling (podman, skopeo, buildah), as described in
+//! `containers-registries.conf(5)`. Instead of silently expanding an
+//! unqualified name such as `busybox` to Docker Hub, a resolver returns
+//! the list of candidate references to try, in order.
+//!
+//! ## Example
+//!
+//! ```rust
+//! # extern crate dkregistry;
+//! # fn main() {
+//! # fn run() -> dkregistry::errors::Result<()> {
+//! #
+//! use dkregistry::shortnames::{ResolutionOrigin, ShortNameConfig};
+//!
+//! let config = ShortNameConfig::from_toml_str(
+//!     r#"
+//!     unqualified-search-registries = ["registry.fedoraproject.org", "docker.io"]
+//!     short-name-mode = "permissive"
+//!
+//!     [aliases]
+//!     "ubi8" = "registry.access.redhat.com/ubi8"
+//!     "#,
+//! )?;
+//!
+//! let resolved = config.resolve("ubi8:8.4")?;
+//! assert_eq!(resolved.origin(), &ResolutionOrigin::Alias("ubi8".to_string()));
+//! assert_eq!(resolved.candidates()[0].registry(), "registry.access.redhat.com");
+//! assert_eq!(resolved.candidates()[0].version(), "8.4");
+//!
+//! let resolved = config.resolve("busybox")?;
+//! assert_eq!(resolved.candidates().len(), 2);
+//! #
+//! # Ok(())
+//! # };
+//! # run().unwrap();
+//! # }
+//! ```
+
+// Semantics follow the reference implementation at
+// https://github.com/containers/image/blob/v5.16.0/pkg/shortnames/shortnames.go
+
+use crate::reference::{self, Reference, ReferenceParseError};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::{fs, io, path};
+
+/// System-wide registries configuration file.
+pub static SYSTEM_REGISTRIES_CONF: &str = "/etc/containers/registries.conf";
+/// Directory holding system-wide drop-in registries configuration files.
+pub static SYSTEM_REGISTRIES_CONF_DIR: &str = "/etc/containers/registries.conf.d";
+
+/// How to treat short names which resolve to more than one candidate.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ShortNameMode {
+    /// Ambiguous short names are an error, the caller must pick a candidate.
+    Enforcing,
+    /// Ambiguous short names resolve to all search registries, in order.
+    #[default]
+    Permissive,
+    /// Short-name checks are disabled, all search registries are used.
+    Disabled,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ShortNameError {
+    #[error("short name {0:?} cannot be resolved: no unqualified-search registries configured")]
+    NoSearchRegistries(String),
+    #[error("short name {name:?} is ambiguous, candidates are {candidates:?}")]
+    Ambiguous {
+        name: String,
+        candidates: Vec<String>,
+    },
+    #[error(
+        "alias {alias:?} must be a fully-qualified repository without tag or digest, got {value:?}"
+    )]
+    InvalidAlias { alias: String, value: String },
+    #[error("reference is invalid")]
+    ReferenceParse(#[from] ReferenceParseError),
+    #[error("registries configuration parse error")]
+    Toml(#[from] toml::de::Error),
+    #[error("io error")]
+    Io(#[from] io::Error),
+}
+
+/// Where the candidates of a `Resolution` come from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ResolutionOrigin {
+    /// The input was already fully qualified.
+    FullyQualified,
+    /// The input matched the named entry of an `[aliases]` table.
+    Alias(String),
+    /// The input was combined with the unqualified-search registries.
+    SearchRegistries,
+}
+
+/// Result of resolving a (possibly short) image name.
+#[derive(Clone, Debug)]
+pub struct Resolution {
+    candidates: Vec<Reference>,
+    origin: ResolutionOrigin,
+}
+
+impl Resolution {
+    /// Candidate references, to be tried in order.
+    pub fn candidates(&self) -> &[Reference] {
+        &self.candidates
+    }
+
+    /// How the candidates were determined.
+    pub fn origin(&self) -> &ResolutionOrigin {
+        &self.origin
+    }
+
+    /// Consume the resolution, returning its candidate references.
+    pub fn into_candidates(self) -> Vec<Reference> {
+        self.candidates
+    }
+}
+
+/// Short-name configuration, as found in `registries.conf`.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ShortNameConfig {
+    #[serde(default)]
+    unqualified_search_registries: Option<Vec<String>>,
+    #[serde(default)]
+    short_name_mode: Option<ShortNameMode>,
+    #[serde(default)]
+    aliases: HashMap<String, String>,
+}
+
+impl ShortNameConfig {
+    /// Parse a configuration in `registries.conf` (version 2) format.
+    ///
+    /// Unrelated settings (e.g. `[[registry]]` tables) are ignored.
+    pub fn from_toml_str(input: &str) -> Result<Self, ShortNameError> {
+        let config: ShortNameConfig = toml::from_str(input)?;
+        for (alias, value) in &config.aliases {
+            validate_alias(alias, value)?;
+        }
+        Ok(config)
+    }
+
+    /// Load and merge configuration files, in order.
+    ///
+    /// Later files take precedence: search registries and mode are replaced
+    /// when set, aliases are merged entry by entry. Missing files are skipped.
+    pub fn load_files<I, P>(paths: I) -> Result<Self, ShortNameError>
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<path::Path>,
+    {
+        let mut config = Self::default();
+        for p in paths {
+            let p = p.as_ref();
+            let content = match fs::read_to_string(p) {
+                Ok(c) => c,
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            trace!("Loading short-name configuration from {}", p.display());
+            config.merge(Self::from_toml_str(&content)?);
+        }
+        Ok(config)
+    }
+
+    /// Load the system-wide configuration.
+    ///
+    /// This reads `/etc/containers/registries.conf`, followed by the
+    /// lexically sorted `*.conf` drop-ins in `/etc/containers/registries.conf.d`.
+    pub fn load_system() -> Result<Self, ShortNameError> {
+        let mut paths = vec![path::PathBuf::from(SYSTEM_REGISTRIES_CONF)];
+        match fs::read_dir(SYSTEM_REGISTRIES_CONF_DIR) {
+            Ok(entries) => {
+                let mut dropins = entries
+                    .filter_map(|e| e.ok().map(|e| e.path()))
+                    .filter(|p| p.extension().map(|e| e == "conf").unwrap_or(false))
+                    .collect::<Vec<_>>();
+                dropins.sort();
+                paths.extend(dropins);
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        };
+        Self::load_files(paths)
+    }
+
+    /// Merge `other` on top of this configuration.
+    pub fn merge(&mut self, other: ShortNameConfig) {
+        if other.unqualified_search_registries.is_some() {
+            self.unqualified_search_registries = other.unqualified_search_registries;
+        }
+        if other.short_name_mode.is_some() {
+            self.short_name_mode = other.short_name_mode;
+        }
+        self.aliases.extend(other.aliases);
+    }
+
+    /// Set the registries to search for short names, in order.
+    pub fn unqualified_search_registries(mut self, registries: Vec<String>) -> Self {
+        self.unqualified_search_registries = Some(registries);
+        self
+    }
+
+    /// Set the short-name mode.
+    pub fn mode(mut self, mode: ShortNameMode) -> Self {
+        self.short_name_mode = Some(mode);
+        self
+    }
+
+    /// Add an alias from a short name to a fully-qualified repository.
+    pub fn alias(mut self, name: &str, value: &str) -> Result<Self, ShortNameError> {
+        validate_alias(name, value)?;
+        self.aliases.insert(name.to_string(), value.to_string());
+        Ok(self)
+    }
+
+    /// Return the effective short-name mode.
+    pub fn short_name_mode(&self) -> ShortNameMode {
+        self.short_name_mode.unwrap_or_default()
+    }
+
+    /// Return the effective unqualified-search registries.
+    pub fn search_registries(&self) -> &[String] {
+        self.unqualified_search_registries
+            .as_deref()
+            .unwrap_or_default()
+    }
+
+    /// Resolve an image name to a list of candidate references.
+    ///
+    /// Fully-qualified names are returned as-is. Short names are first looked up
+    /// in the aliases, then combined with each unqualified-search registry.
+    /// In enforcing mode, a short name matching more than one search registry
+    /// is rejected with `ShortNameError::Ambiguous`, which lists the candidates.
+    pub fn resolve(&self, input: &str) -> Result<Resolution, ShortNameError> {
+        let name = input.trim_start_matches("docker://");
+        let (repo, suffix) = split_version(name);
+
+        if is_qualified(repo) {
+            return Ok(Resolution {
+                candidates: vec![Reference::from_str(input)?],
+                origin: ResolutionOrigin::FullyQualified,
+            });
+        }
+
+        if let Some(value) = self.aliases.get(repo) {
+            trace!("Short name {repo:?} is an alias for {value:?}");
+            return Ok(Resolution {
+                candidates: vec![qualify_alias(value, suffix)?],
+                origin: ResolutionOrigin::Alias(repo.to_string()),
+            });
+        }
+
+        let registries = self.search_registries();
+        if registries.is_empty() {
+            return Err(ShortNameError::NoSearchRegistries(name.to_string()));
+        }
+        let candidates = registries
+            .iter()
+            .map(|registry| qualify(registry, repo, suffix))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if candidates.len() > 1 && self.short_name_mode() == ShortNameMode::Enforcing {
+            return Err(ShortNameError::Ambiguous {
+                name: name.to_string(),
+                candidates: candidates.iter().map(|c| c.to_string()).collect(),
+            });
+        }
+
+        Ok(Resolution {
+            candidates,
+            origin: ResolutionOrigin::SearchRegistries,
+        })
+    }
+}
+
+/// Split an image name into repository and tag/digest suffix (including separator).
+fn split_version(name: &str) -> (&str, &str) {
+    let end = name.find('@').unwrap_or(name.len());
+    let last_slash = name[..end].rfind('/').map(|i| i + 1).unwrap_or(0);
+    match name[last_slash..end].rfind(':') {
+        Some(i) => name.split_at(last_slash + i),
+        None => name.split_at(end),
+    }
+}
+
+/// Whether the first path component of `repo` is a registry domain.
+fn is_qualified(repo: &str) -> bool {
+    match repo.split_once('/') {
+        Some((first, _)) => reference::is_domain(first),
+        None => false,
+    }
+}
+
+fn qualify(registry: &str, repo: &str, suffix: &str) -> Result<Reference, ReferenceParseError> {
+    // Docker Hub domains and the `library/` namespace are normalized by parsing.
+    Reference::from_str(&format!("{registry}/{repo}{suffix}"))
+}
+
+fn qualify_alias(value: &str, suffix: &str) -> Result<Reference, ReferenceParseError> {
+    match value.split_once('/') {
+        Some((registry, repo)) => qualify(registry, repo, suffix),
+        None => Reference::from_str(&format!("{value}{suffix}")),
+    }
+}
+
+fn validate_alias(alias: &str, value: &str) -> Result<(), ShortNameError> {
+    let (repo, suffix) = split_version(value);
+    if !suffix.is_empty() || !is_qualified(repo) || qualify_alias(value, "").is_err() {
+        return Err(ShortNameError::InvalidAlias {
+            alias: alias.to_string(),
+            value: value.to_string(),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    static RHEL_CONF: &str = r#"
+unqualified-search-registries = ["registry.fedoraproject.org", "registry.access.redhat.com", "docker.io"]
+short-name-mode = "enforcing"
+
+[[registry]]
+location = "registry.access.redhat.com"
+
+[aliases]
+"ubi8" = "registry.access.redhat.com/ubi8"
+"fedora" = "registry.fedoraproject.org/fedora"
+"busybox" = "docker.io/library/busybox"
+"#;
+
+    #[test_case("ubi8", "registry.access.redhat.com", "ubi8", "latest"; "alias")]
+    #[test_case("ubi8:8.4", "registry.access.redhat.com", "ubi8", "8.4"; "alias with tag")]
+    #[test_case("busybox", "registry-1.docker.io", "library/busybox", "latest"; "docker hub alias")]
+    #[test_case("quay.io/coreos/etcd:v3", "quay.io", "coreos/etcd", "v3"; "fully qualified")]
+    #[test_case("localhost:5000/etcd", "localhost:5000", "etcd", "latest"; "localhost")]
+    #[test_case("ubi8:8.4@sha256:d5a3477d91583e65a7aba6f6db7a53e2de739bc7bf8f4a08f0df0457b637f1fb", "registry.access.redhat.com", "ubi8", "sha256:d5a3477d91583e65a7aba6f6db7a53e2de739bc7bf8f4a08f0df0457b637f1fb"; "alias with tag and digest")]
+    fn resolve_single(input: &str, registry: &str, repository: &str, version: &str) {
+        let config = ShortNameConfig::from_toml_str(RHEL_CONF).unwrap();
+        let resolved = config.resolve(input).unwrap();
+
+        assert_eq!(resolved.candidates().len(), 1);
+        let r = &resolved.candidates()[0];
+        assert_eq!(r.registry(), registry);
+        assert_eq!(r.repository(), repository);
+        assert_eq!(r.version(), version);
+    }
+
+    #[test]
+    fn resolve_enforcing_ambiguous() {
+        let config = ShortNameConfig::from_toml_str(RHEL_CONF).unwrap();
+
+        match config.resolve("httpd:2.4") {
+            Err(ShortNameError::Ambiguous { candidates, .. }) => assert_eq!(candidates.len(), 3),
+            r => panic!("expected ambiguous short name, got {:?}", r),
+        }
+    }
+
+    #[test_case(ShortNameMode::Permissive; "permissive")]
+    #[test_case(ShortNameMode::Disabled; "disabled")]
+    fn resolve_search_registries(mode: ShortNameMode) {
+        let config = ShortNameConfig::from_toml_str(RHEL_CONF)
+            .unwrap()
+            .mode(mode);
+        let resolved = config.resolve("httpd:2.4").unwrap();
+
+        assert_eq!(resolved.origin(), &ResolutionOrigin::SearchRegistries);
+        let candidates = resolved
+            .candidates()
+            .iter()
+            .map(|r| (r.registry(), r.repository()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            candidates,
+            vec![
+                ("registry.fedoraproject.org".into(), "httpd".into()),
+                ("registry.access.redhat.com".into(), "httpd".into()),
+                ("registry-1.docker.io".into(), "library/httpd".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn resolve_single_search_registry_enforcing() {
+        let config = ShortNameConfig::default()
+            .mode(ShortNameMode::Enforcing)
+            .unqualified_search_registries(vec!["quay.io".into()]);
+        let resolved = config.resolve("coreos/etcd").unwrap();
+
+        assert_eq!(resolved.candidates()[0].registry(), "quay.io");
+        assert_eq!(resolved.candidates()[0].repository(), "coreos/etcd");
+    }
+
+    #[test]
+    fn resolve_without_search_registries() {
+        match ShortNameConfig::default().resolve("busybox") {
+            Err(ShortNameError::NoSearchRegistries(_)) => {}
+            r => panic!("expected missing search registries, got {:?}", r),
+        }
+    }
+
+    #[test_case("busybox"; "short value")]
+    #[test_case("quay.io/coreos/etcd:latest"; "value with tag")]
+    fn invalid_alias(value: &str) {
+        assert!(ShortNameConfig::default().alias("foo", value).is_err());
+    }
+
+    #[test]
+    fn merge_overrides() {
+        let mut config = ShortNameConfig::from_toml_str(RHEL_CONF).unwrap();
+        config.merge(
+            ShortNameConfig::from_toml_str(
+                r#"
+unqualified-search-registries = ["quay.io"]
+
+[aliases]
+"ubi8" = "quay.io/mirror/ubi8"
+"#,
+            )
+            .unwrap(),
+        );
+
+        assert_eq!(config.search_registries(), &["quay.io".to_string()]);
+        assert_eq!(config.short_name_mode(), ShortNameMode::Enforcing);
+        let resolved = config.resolve("ubi8").unwrap();
+        assert_eq!(resolved.candidates()[0].registry(), "quay.io");
+        let resolved = config.resolve("fedora").unwrap();
+        assert_eq!(
+            resolved.candidates()[0].registry(),
+            "registry.fedoraproject.org"
+        );
+    }
+}
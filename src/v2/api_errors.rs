@@ -0,0 +1,249 @@
+//! Error responses returned by registries.
+//!
+//! Registries describe failures with a JSON body in the format defined by
+//! the distribution spec: `{"errors": [{"code": ..., "message": ..., "detail": ...}]}`.
+//! See <https://github.com/opencontainers/distribution-spec/blob/main/spec.md#error-codes>.
+
+use crate::errors::Error;
+use reqwest::{Method, StatusCode, Url};
+use std::fmt;
+
+/// Error code of a registry error, as defined by the distribution spec.
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(from = "String", into = "String")]
+pub enum ErrorCode {
+    /// Blob unknown to registry.
+    BlobUnknown,
+    /// Blob upload invalid.
+    BlobUploadInvalid,
+    /// Blob upload unknown to registry.
+    BlobUploadUnknown,
+    /// Provided digest did not match uploaded content.
+    DigestInvalid,
+    /// Manifest references a blob unknown to registry.
+    ManifestBlobUnknown,
+    /// Manifest invalid.
+    ManifestInvalid,
+    /// Manifest unknown to registry.
+    ManifestUnknown,
+    /// Manifest failed signature verification.
+    ManifestUnverified,
+    /// Invalid repository name.
+    NameInvalid,
+    /// Repository name not known to registry.
+    NameUnknown,
+    /// Provided length did not match content length.
+    SizeInvalid,
+    /// Manifest tag did not match URI.
+    TagInvalid,
+    /// Authentication required.
+    Unauthorized,
+    /// Requested access to the resource is denied.
+    Denied,
+    /// The operation is unsupported.
+    Unsupported,
+    /// Too many requests.
+    TooManyRequests,
+    /// A code not defined by the spec.
+    Other(String),
+}
+
+impl ErrorCode {
+    /// Return the code as it appears in error responses.
+    pub fn as_str(&self) -> &str {
+        match self {
+            ErrorCode::BlobUnknown => "BLOB_UNKNOWN",
+            ErrorCode::BlobUploadInvalid => "BLOB_UPLOAD_INVALID",
+            ErrorCode::BlobUploadUnknown => "BLOB_UPLOAD_UNKNOWN",
+            ErrorCode::DigestInvalid => "DIGEST_INVALID",
+            ErrorCode::ManifestBlobUnknown => "MANIFEST_BLOB_UNKNOWN",
+            ErrorCode::ManifestInvalid => "MANIFEST_INVALID",
+            ErrorCode::ManifestUnknown => "MANIFEST_UNKNOWN",
+            ErrorCode::ManifestUnverified => "MANIFEST_UNVERIFIED",
+            ErrorCode::NameInvalid => "NAME_INVALID",
+            ErrorCode::NameUnknown => "NAME_UNKNOWN",
+            ErrorCode::SizeInvalid => "SIZE_INVALID",
+            ErrorCode::TagInvalid => "TAG_INVALID",
+            ErrorCode::Unauthorized => "UNAUTHORIZED",
+            ErrorCode::Denied => "DENIED",
+            ErrorCode::Unsupported => "UNSUPPORTED",
+            ErrorCode::TooManyRequests => "TOOMANYREQUESTS",
+            ErrorCode::Other(code) => code,
+        }
+    }
+}
+
+impl From<String> for ErrorCode {
+    fn from(code: String) -> Self {
+        match code.as_str() {
+            "BLOB_UNKNOWN" => ErrorCode::BlobUnknown,
+            "BLOB_UPLOAD_INVALID" => ErrorCode::BlobUploadInvalid,
+            "BLOB_UPLOAD_UNKNOWN" => ErrorCode::BlobUploadUnknown,
+            "DIGEST_INVALID" => ErrorCode::DigestInvalid,
+            "MANIFEST_BLOB_UNKNOWN" => ErrorCode::ManifestBlobUnknown,
+            "MANIFEST_INVALID" => ErrorCode::ManifestInvalid,
+            "MANIFEST_UNKNOWN" => ErrorCode::ManifestUnknown,
+            "MANIFEST_UNVERIFIED" => ErrorCode::ManifestUnverified,
+            "NAME_INVALID" => ErrorCode::NameInvalid,
+            "NAME_UNKNOWN" => ErrorCode::NameUnknown,
+            "SIZE_INVALID" => ErrorCode::SizeInvalid,
+            "TAG_INVALID" => ErrorCode::TagInvalid,
+            "UNAUTHORIZED" => ErrorCode::Unauthorized,
+            "DENIED" => ErrorCode::Denied,
+            "UNSUPPORTED" => ErrorCode::Unsupported,
+            "TOOMANYREQUESTS" => ErrorCode::TooManyRequests,
+            _ => ErrorCode::Other(code),
+        }
+    }
+}
+
+impl From<ErrorCode> for String {
+    fn from(code: ErrorCode) -> Self {
+        code.as_str().to_string()
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A single error reported by a registry.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct ApiError {
+    /// Error code.
+    pub code: ErrorCode,
+    /// Human-readable description of the error.
+    #[serde(default)]
+    pub message: String,
+    /// Unstructured, code-specific details.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<serde_json::Value>,
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.message.is_empty() {
+            true => write!(f, "{}", self.code),
+            false => write!(f, "{}: {}", self.code, self.message),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct Errors {
+    errors: Vec<ApiError>,
+}
+
+/// A request which was rejected by a registry.
+#[derive(Clone, Debug)]
+pub struct RegistryError {
+    /// Method of the failed request.
+    pub method: Method,
+    /// URL of the failed request.
+    pub url: Url,
+    /// Repository the request was about, if any.
+    pub repository: Option<String>,
+    /// HTTP status of the response.
+    pub status: StatusCode,
+    /// Errors reported in the response body, if it could be parsed.
+    pub errors: Vec<ApiError>,
+}
+
+impl RegistryError {
+    /// Whether the registry reported an error with the given code.
+    pub fn has_code(&self, code: &ErrorCode) -> bool {
+        self.errors.iter().any(|e| &e.code == code)
+    }
+}
+
+impl fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} failed with status {}",
+            self.method, self.url, self.status
+        )?;
+        if let Some(repository) = &self.repository {
+            write!(f, " for repository {repository}")?;
+        }
+        for (i, error) in self.errors.iter().enumerate() {
+            let sep = if i == 0 { ": " } else { "; " };
+            write!(f, "{sep}{error}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for RegistryError {}
+
+/// Build an error for a non-successful response, parsing its error body.
+pub(crate) async fn error_from_response(method: Method, resp: reqwest::Response) -> Error {
+    let status = resp.status();
+    let url = resp.url().clone();
+    let errors = match resp.bytes().await {
+        Ok(body) => parse_errors(&body),
+        Err(e) => {
+            debug!("failed to read error body of {url}: {e}");
+            Vec::new()
+        }
+    };
+
+    Error::Registry(Box::new(RegistryError {
+        repository: repository(&url),
+        method,
+        url,
+        status,
+        errors,
+    }))
+}
+
+fn parse_errors(body: &[u8]) -> Vec<ApiError> {
+    match serde_json::from_slice::<Errors>(body) {
+        Ok(errors) => errors.errors,
+        Err(_) if body.is_empty() => Vec::new(),
+        Err(e) => {
+            trace!("error body is not a registry error: {e}");
+            Vec::new()
+        }
+    }
+}
+
+/// Extract the repository name from a `/v2/<name>/...` API URL.
+fn repository(url: &Url) -> Option<String> {
+    let path = url.path().strip_prefix("/v2/")?;
+    ["/manifests/", "/blobs/", "/tags/list"]
+        .iter()
+        .filter_map(|endpoint| path.rfind(endpoint))
+        .max()
+        .map(|end| path[..end].to_string())
+        .filter(|name| !name.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("https://quay.io/v2/coreos/etcd/manifests/latest" => Some("coreos/etcd".to_string()); "manifest")]
+    #[test_case("https://quay.io/v2/a/blobs/b/blobs/uploads/" => Some("a/blobs/b".to_string()); "blob upload")]
+    #[test_case("https://quay.io/v2/library/busybox/tags/list?n=1" => Some("library/busybox".to_string()); "tags")]
+    #[test_case("https://quay.io/v2/_catalog" => None; "catalog")]
+    #[test_case("https://auth.docker.io/token?service=registry" => None; "token")]
+    fn repository_from_url(url: &str) -> Option<String> {
+        repository(&Url::parse(url).unwrap())
+    }
+
+    #[test]
+    fn parse_error_body() {
+        let body = br#"{"errors":[{"code":"MANIFEST_UNKNOWN","message":"manifest unknown","detail":{"Tag":"nope"}},{"code":"X_CUSTOM"}]}"#;
+        let errors = parse_errors(body);
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].code, ErrorCode::ManifestUnknown);
+        assert_eq!(errors[0].to_string(), "MANIFEST_UNKNOWN: manifest unknown");
+        assert_eq!(errors[1].code, ErrorCode::Other("X_CUSTOM".to_string()));
+        assert!(parse_errors(b"<html>Bad Gateway</html>").is_empty());
+    }
+}
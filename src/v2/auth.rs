@@ -42,24 +42,21 @@ impl BearerAuth {
 
         let url = reqwest::Url::parse(&auth_ep)?;
 
-        let auth_req = {
-            Client {
-                auth: credentials.map(|(user, password)| {
-                    Auth::Basic(BasicAuth {
-                        user,
-                        password: Some(password),
-                    })
-                }),
-                ..client
-            }
-        }
-        .build_reqwest(Method::GET, url);
+        let auth_client = Client {
+            auth: credentials.map(|(user, password)| {
+                Auth::Basic(BasicAuth {
+                    user,
+                    password: Some(password),
+                })
+            }),
+            ..client
+        };
 
-        let r = auth_req.send().await?;
+        let r = auth_client.build_reqwest(Method::GET, url).send().await?;
         let status = r.status();
         trace!("authenticate: got status {status}");
         if status != StatusCode::OK {
-            return Err(Error::UnexpectedHttpStatus(status));
+            return Err(error_from_response(Method::GET, r).await);
         }
 
         let bearer_auth = r.json::<BearerAuth>().await?;
@@ -297,11 +294,10 @@ impl Client {
         let resp = req.send().await?;
         trace!("GET '{resp:?}'");
 
-        let status = resp.status();
-        match status {
+        match resp.status() {
             reqwest::StatusCode::OK => Ok(true),
             reqwest::StatusCode::UNAUTHORIZED => Ok(false),
-            _ => Err(Error::UnexpectedHttpStatus(status)),
+            _ => Err(error_from_response(Method::GET, resp).await),
         }
     }
 }
@@ -1,17 +1,31 @@
 use crate::errors::{Error, Result};
+use crate::v2::progress::BlobProgress;
 use crate::v2::*;
 
+use std::io::{self, SeekFrom};
+use std::ops::{Bound, RangeBounds};
+use std::path::Path;
 use std::pin::Pin;
+use std::sync::Arc;
 
-use bytes::Bytes;
-use futures::stream::Stream;
+use async_stream::try_stream;
+use bytes::{Bytes, BytesMut};
+use futures::sink::SinkExt;
+use futures::stream::{Stream, StreamExt, TryStreamExt};
 use futures::task::{Context, Poll};
 use pin_project::pin_project;
-use reqwest::{self, Method, StatusCode};
+use reqwest::{self, header, Method, StatusCode};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+
+/// Size of the chunks read from cached blobs.
+const CACHE_CHUNK_SIZE: usize = 64 * 1024;
 
 impl Client {
     /// Check if a blob exists.
-    pub async fn has_blob(&self, name: &str, digest: &str) -> Result<bool> {
+    ///
+    /// Only a `404 Not Found` response means the blob is absent, other
+    /// failures are reported as `Error::Registry`.
+    pub async fn has_blob(&self, name: &str, digest: &Digest) -> Result<bool> {
         let url = {
             let ep = format!("{}/v2/{}/blobs/{}", self.base_url, name, digest);
             reqwest::Url::parse(&ep)?
@@ -22,84 +36,666 @@ impl Client {
         trace!("Blob HEAD status: {:?}", res.status());
 
         match res.status() {
-            StatusCode::OK => Ok(true),
-            _ => Ok(false),
+            s if s.is_success() => Ok(true),
+            StatusCode::NOT_FOUND => Ok(false),
+            _ => Err(error_from_response(Method::HEAD, res).await),
         }
     }
 
-    pub async fn get_blob_response(&self, name: &str, digest: &str) -> Result<BlobResponse> {
+    /// Retrieve a blob response, whose content can then be read or streamed.
+    ///
+    /// If the client has a `BlobCache` holding the blob, the response is
+    /// served from the cache. Otherwise the blob content is added to the
+    /// cache once it has been fully read and verified.
+    pub async fn get_blob_response(&self, name: &str, digest: &Digest) -> Result<BlobResponse> {
+        let content_digest = ContentDigest::new(digest.clone());
+        if let Some(body) = self.cached_blob(name, digest).await? {
+            return Ok(BlobResponse::new(body, content_digest, None));
+        }
+        let resp = self.fetch_blob(name, digest, None).await?;
+
+        if let Some(len) = resp.content_length() {
+            trace!("Receiving a blob with {len} bytes");
+        } else {
+            trace!("Receiving a blob");
+        }
+        Ok(BlobResponse::new(
+            BlobBody::Remote(resp),
+            content_digest,
+            self.cache.clone(),
+        ))
+    }
+
+    /// Open a blob from the client cache, if it holds it.
+    async fn cached_blob(&self, name: &str, digest: &Digest) -> Result<Option<BlobBody>> {
+        let cache = match &self.cache {
+            Some(cache) => cache,
+            None => return Ok(None),
+        };
+        let (file, size) = match cache.open(digest).await? {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+
         let ep = format!("{}/v2/{}/blobs/{}", self.base_url, name, digest);
-        let url = reqwest::Url::parse(&ep)?;
+        Ok(Some(BlobBody::Cached {
+            url: reqwest::Url::parse(&ep)?,
+            file,
+            size,
+            cache: cache.clone(),
+        }))
+    }
 
-        let resp = self.build_reqwest(Method::GET, url.clone()).send().await?;
+    /// Resolve the URL a blob can be downloaded from, without downloading it.
+    ///
+    /// Registries commonly redirect blob downloads to external storage, e.g.
+    /// pre-signed S3 or GCS URLs. Redirects within the registry origin are
+    /// followed, and the first URL on a different origin is returned; such a
+    /// URL can be handed off to another downloader and does not require
+    /// registry credentials. If the registry serves the blob itself, the
+    /// registry URL is returned.
+    pub async fn get_blob_url(&self, name: &str, digest: &Digest) -> Result<Url> {
+        let ep = format!("{}/v2/{}/blobs/{}", self.base_url, name, digest);
+        let mut url = reqwest::Url::parse(&ep)?;
 
-        let status = resp.status();
-        trace!("GET {} status: {}", resp.url(), status);
+        for _ in 0..=request::MAX_REDIRECTS {
+            let resp = self
+                .build_reqwest(Method::GET, url.clone())
+                .follow_redirects(false)
+                .send()
+                .await?;
+            let status = resp.status();
+            trace!("GET {} status: {}", resp.url(), status);
 
-        match resp.error_for_status_ref() {
-            Ok(_) => {
-                if let Some(len) = resp.content_length() {
-                    trace!("Receiving a blob with {len} bytes");
-                } else {
-                    trace!("Receiving a blob");
-                }
-                Ok(BlobResponse::new(resp, ContentDigest::try_new(digest)?))
+            let location = match request::redirect_location(&resp) {
+                Some(location) => location,
+                None if status.is_success() => return Ok(url),
+                None => return Err(error_from_response(Method::GET, resp).await),
+            };
+            if location.origin() != url.origin() {
+                trace!("Blob {digest} is served from {location}");
+                return Ok(location);
             }
-            Err(_) if status.is_client_error() => Err(Error::Client { status }),
-            Err(_) if status.is_server_error() => Err(Error::Server { status }),
-            Err(_) => {
-                error!("Received unexpected HTTP status '{status}'");
-                Err(Error::UnexpectedHttpStatus(status))
+            url = location;
+        }
+        Err(Error::TooManyRedirects(request::MAX_REDIRECTS))
+    }
+
+    /// Retrieve a byte range of a blob.
+    ///
+    /// The registry must support range requests, otherwise this fails
+    /// with `Error::RangeUnsupported`. As only part of the blob is
+    /// transferred, its content is not verified against the digest.
+    pub async fn get_blob_range<R>(&self, name: &str, digest: &Digest, range: R) -> Result<Vec<u8>>
+    where
+        R: RangeBounds<u64>,
+    {
+        let (start, end) = match resolve_range(&range) {
+            Some(range) => range,
+            None => return Ok(Vec::new()),
+        };
+        let cached = match &self.cache {
+            Some(cache) => cache.open(digest).await?,
+            None => None,
+        };
+        if let Some((mut file, size)) = cached {
+            if start >= size {
+                return Ok(Vec::new());
             }
+            let len = end.map_or(size - 1, |e| e.min(size - 1)) - start + 1;
+            file.seek(SeekFrom::Start(start)).await?;
+            let mut data = Vec::with_capacity(len as usize);
+            file.take(len).read_to_end(&mut data).await?;
+            return Ok(data);
         }
+
+        let resp = self.fetch_blob(name, digest, Some((start, end))).await?;
+        Ok(resp.bytes().await?.to_vec())
     }
 
     /// Retrieve blob.
-    pub async fn get_blob(&self, name: &str, digest: &str) -> Result<Vec<u8>> {
+    pub async fn get_blob(&self, name: &str, digest: &Digest) -> Result<Vec<u8>> {
         self.get_blob_response(name, digest).await?.bytes().await
     }
 
     /// Retrieve blob stream.
+    ///
+    /// Transient failures are retried as per the client `RetryPolicy`.
+    /// If the connection drops after part of the blob was received, the
+    /// download is resumed with a range request, provided the registry
+    /// supports it. The content is verified against the digest at the end.
     pub async fn get_blob_stream(
         &self,
         name: &str,
-        digest: &str,
-    ) -> Result<impl Stream<Item = Result<Vec<u8>>>> {
-        Ok(self.get_blob_response(name, digest).await?.stream())
+        digest: &Digest,
+    ) -> Result<impl Stream<Item = Result<Bytes>>> {
+        self.blob_stream(name, digest, None, BlobProgress::new(None, digest))
+            .await
+    }
+
+    /// Retrieve blob stream, reporting the transfer progress to `observer`.
+    ///
+    /// This behaves like `get_blob_stream`.
+    pub async fn get_blob_stream_with_progress(
+        &self,
+        name: &str,
+        digest: &Digest,
+        observer: Arc<dyn ProgressObserver>,
+    ) -> Result<impl Stream<Item = Result<Bytes>>> {
+        let progress = BlobProgress::new(Some(observer), digest);
+        let failed = progress.clone();
+        let stream = self.blob_stream(name, digest, None, progress).await?;
+        Ok(stream.inspect_err(move |_| failed.failed()))
+    }
+
+    /// Download a blob into a writer, returning its size.
+    ///
+    /// The blob is streamed as with `get_blob_stream`, and the writer is
+    /// flushed once the content has been verified against the digest. On
+    /// failure, the writer may hold partial content.
+    pub async fn download_blob_to<W>(&self, name: &str, digest: &Digest, writer: W) -> Result<u64>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let mut writer = writer;
+        let progress = BlobProgress::new(None, digest);
+        self.copy_blob(name, digest, None, &mut writer, progress)
+            .await
+    }
+
+    /// Download a blob into a file, returning its size.
+    ///
+    /// The content is written to a uniquely named temporary file next to
+    /// `path`, verified against the digest and the expected `size` if known,
+    /// synced to disk, and then renamed into place. On failure, `path` is
+    /// left untouched.
+    pub async fn download_blob_to_path<P: AsRef<Path>>(
+        &self,
+        name: &str,
+        digest: &Digest,
+        size: Option<u64>,
+        path: P,
+    ) -> Result<u64> {
+        let path = path.as_ref();
+        let (mut file, temp) = partial::create(path).await?;
+        let downloaded = async {
+            let progress = BlobProgress::new(None, digest);
+            let size = self
+                .copy_blob(name, digest, size, &mut file, progress)
+                .await?;
+            partial::persist(file, &temp, path).await?;
+            Ok(size)
+        }
+        .await;
+
+        if downloaded.is_err() {
+            partial::discard(&temp).await;
+        }
+        downloaded
+    }
+
+    /// Upload a blob, in a single request.
+    ///
+    /// The upload session is opened with a `POST`, and the whole content is
+    /// then sent with a `PUT` to the location returned by the registry.
+    /// The registry verifies the content against the digest.
+    pub async fn push_blob(&self, name: &str, digest: &Digest, data: Vec<u8>) -> Result<()> {
+        let url = self.start_upload(name, digest).await?;
+        trace!("Uploading blob {} ({} bytes)", digest, data.len());
+        self.finish_upload(url, data.into()).await
+    }
+
+    /// Upload a blob from a stream of its content, in a single request.
+    ///
+    /// This behaves like `push_blob`, without holding the content in memory.
+    /// If the stream fails, the upload is aborted and the stream error is
+    /// returned. As the content cannot be replayed, the upload is not retried.
+    pub async fn push_blob_stream<S>(&self, name: &str, digest: &Digest, blob: S) -> Result<()>
+    where
+        S: Stream<Item = Result<Bytes>> + Send,
+    {
+        let url = self.start_upload(name, digest).await?;
+        trace!("Uploading blob {digest} from a stream");
+
+        // The request body must be 'static, so chunks are fed through a channel.
+        let (mut sender, receiver) = futures::channel::mpsc::channel::<io::Result<Bytes>>(1);
+        let upload = self.finish_upload(url, reqwest::Body::wrap_stream(receiver));
+        let feed = async move {
+            let mut blob = Box::pin(blob);
+            while let Some(chunk) = blob.next().await {
+                let chunk = match chunk {
+                    Ok(chunk) => chunk,
+                    Err(e) => {
+                        let aborted = io::Error::other("blob stream failed");
+                        let _ = sender.send(Err(aborted)).await;
+                        return Err(e);
+                    }
+                };
+                if sender.send(Ok(chunk)).await.is_err() {
+                    // The request is over, and reports its own outcome.
+                    break;
+                }
+            }
+            Ok(())
+        };
+
+        let (uploaded, fed) = futures::join!(upload, feed);
+        fed?;
+        uploaded
+    }
+
+    /// Open an upload session, returning the URL to send the content for `digest` to.
+    async fn start_upload(&self, name: &str, digest: &Digest) -> Result<Url> {
+        let url = {
+            let ep = format!("{}/v2/{}/blobs/uploads/", self.base_url, name);
+            reqwest::Url::parse(&ep)?
+        };
+
+        let res = self
+            .build_reqwest(Method::POST, url)
+            .body(Vec::new())
+            .send()
+            .await?;
+        trace!("POST '{}' status: {:?}", res.url(), res.status());
+        match res.status() {
+            StatusCode::ACCEPTED => {}
+            _ => return Err(error_from_response(Method::POST, res).await),
+        }
+
+        let location = res
+            .headers()
+            .get(header::LOCATION)
+            .ok_or(Error::MissingHeader("Location"))?
+            .to_str()?;
+        let mut url = res.url().join(location)?;
+        url.query_pairs_mut().append_pair("digest", digest.as_str());
+        Ok(url)
+    }
+
+    /// Send the whole content of a blob to an upload session, closing it.
+    async fn finish_upload(&self, url: Url, body: reqwest::Body) -> Result<()> {
+        let res = self
+            .build_reqwest(Method::PUT, url)
+            .header(
+                header::CONTENT_TYPE,
+                header::HeaderValue::from_static("application/octet-stream"),
+            )
+            .body(body)
+            .send()
+            .await?;
+        trace!("PUT '{}' status: {:?}", res.url(), res.status());
+        match res.status() {
+            StatusCode::CREATED => Ok(()),
+            _ => Err(error_from_response(Method::PUT, res).await),
+        }
+    }
+
+    /// Stream a blob into a writer, verifying its digest and expected size.
+    pub(crate) async fn copy_blob<W: AsyncWrite + Unpin>(
+        &self,
+        name: &str,
+        digest: &Digest,
+        size: Option<u64>,
+        writer: &mut W,
+        progress: BlobProgress,
+    ) -> Result<u64> {
+        let failed = progress.clone();
+        let mut stream = Box::pin(self.blob_stream(name, digest, size, progress).await?);
+        let copied = async {
+            let mut written: u64 = 0;
+            while let Some(chunk) = stream.try_next().await? {
+                written += chunk.len() as u64;
+                if let Some(expected) = size.filter(|&e| written > e) {
+                    return Err(Error::SizeMismatch {
+                        digest: digest.clone(),
+                        expected,
+                        got: written,
+                    });
+                }
+                writer.write_all(&chunk).await?;
+            }
+            writer.flush().await?;
+
+            match size {
+                Some(expected) if expected != written => Err(Error::SizeMismatch {
+                    digest: digest.clone(),
+                    expected,
+                    got: written,
+                }),
+                _ => Ok(written),
+            }
+        }
+        .await;
+        if copied.is_err() {
+            failed.failed();
+        }
+        copied
+    }
+
+    /// Start a resumable blob stream.
+    ///
+    /// The expected `size` is reported as total, falling back to the
+    /// `Content-Length` of the response. Errors of the returned stream are
+    /// not reported to `progress`; callers are expected to do so.
+    pub(crate) async fn blob_stream(
+        &self,
+        name: &str,
+        digest: &Digest,
+        size: Option<u64>,
+        mut progress: BlobProgress,
+    ) -> Result<impl Stream<Item = Result<Bytes>>> {
+        let blob_response = match self.get_blob_response(name, digest).await {
+            Ok(r) => r,
+            Err(e) => {
+                progress.failed();
+                return Err(e);
+            }
+        };
+        progress.started(TransferDirection::Download, size.or(blob_response.size()));
+
+        let BlobResponse {
+            body,
+            digest: content_digest,
+            cache,
+        } = blob_response;
+        Ok(match body {
+            BlobBody::Cached { file, cache, .. } => {
+                cached_stream(file, content_digest, cache, progress).left_stream()
+            }
+            BlobBody::Remote(resp) => resumable_stream(
+                self.clone(),
+                name.to_string(),
+                digest.clone(),
+                resp,
+                content_digest,
+                cache,
+                progress,
+            )
+            .right_stream(),
+        })
+    }
+
+    /// Send a GET request for a blob, optionally limited to a byte range.
+    async fn fetch_blob(
+        &self,
+        name: &str,
+        digest: &Digest,
+        range: Option<(u64, Option<u64>)>,
+    ) -> Result<reqwest::Response> {
+        let ep = format!("{}/v2/{}/blobs/{}", self.base_url, name, digest);
+        let url = reqwest::Url::parse(&ep)?;
+
+        let mut req = self.build_reqwest(Method::GET, url);
+        if let Some((start, end)) = range {
+            let value = match end {
+                Some(end) => format!("bytes={start}-{end}"),
+                None => format!("bytes={start}-"),
+            };
+            req = req.header(
+                header::RANGE,
+                header::HeaderValue::from_str(&value).expect("range is a valid header value"),
+            );
+        }
+        let resp = req.send().await?;
+
+        let status = resp.status();
+        trace!("GET {} status: {}", resp.url(), status);
+
+        if !status.is_success() {
+            return Err(error_from_response(Method::GET, resp).await);
+        }
+
+        if let Some((start, _)) = range {
+            if status != StatusCode::PARTIAL_CONTENT {
+                return Err(Error::RangeUnsupported);
+            }
+            let content_range = resp
+                .headers()
+                .get(header::CONTENT_RANGE)
+                .map(|v| v.to_str())
+                .transpose()?
+                .unwrap_or_default();
+            if content_range_start(content_range) != Some(start) {
+                return Err(Error::ContentRange(content_range.to_string()));
+            }
+        }
+
+        Ok(resp)
+    }
+}
+
+/// Parse the first byte position of a `Content-Range: bytes <start>-<end>/<size>` header.
+fn content_range_start(value: &str) -> Option<u64> {
+    value
+        .strip_prefix("bytes ")?
+        .split('-')
+        .next()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Stream a blob, resuming the download from the current offset on transient failures.
+fn resumable_stream(
+    client: Client,
+    name: String,
+    digest: Digest,
+    resp: reqwest::Response,
+    mut content_digest: ContentDigest,
+    cache: Option<BlobCache>,
+    progress: BlobProgress,
+) -> impl Stream<Item = Result<Bytes>> {
+    try_stream! {
+        let mut cache_writer = match &cache {
+            Some(cache) => cache
+                .writer(&digest)
+                .await
+                .map_err(|e| warn!("not caching blob {digest}: {e}"))
+                .ok(),
+            None => None,
+        };
+        let ranges_supported = resp
+            .headers()
+            .get(header::ACCEPT_RANGES)
+            .map(|v| v.as_bytes() != b"none")
+            .unwrap_or(true);
+        let mut chunks = Box::pin(resp.bytes_stream());
+        let mut offset: u64 = 0;
+        let mut attempt = 1;
+
+        loop {
+            match chunks.next().await {
+                Some(Ok(chunk)) => {
+                    content_digest.update(&chunk);
+                    offset += chunk.len() as u64;
+                    progress.progress(offset);
+                    if let Some(writer) = &mut cache_writer {
+                        if let Err(e) = writer.write(&chunk).await {
+                            warn!("not caching blob {digest}: {e}");
+                            cache_writer = None;
+                        }
+                    }
+                    yield chunk;
+                }
+                Some(Err(e)) => {
+                    let delay = client
+                        .retry_policy
+                        .delay_for_error(&e, attempt)
+                        .filter(|_| offset == 0 || ranges_supported);
+                    let delay = match delay {
+                        Some(delay) => {
+                            debug!("blob {digest} failed after {offset} bytes: {e}, resuming in {delay:?}");
+                            delay
+                        }
+                        None => Err(Error::from(e))?,
+                    };
+                    progress.retrying(offset, attempt, delay);
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+
+                    let range = if offset == 0 { None } else { Some((offset, None)) };
+                    let resp = client.fetch_blob(&name, &digest, range).await?;
+                    progress.resumed(offset);
+                    chunks = Box::pin(resp.bytes_stream());
+                }
+                None => break,
+            }
+        }
+
+        content_digest.verify()?;
+        if let Some(writer) = cache_writer {
+            if let Err(e) = writer.commit().await {
+                warn!("failed to cache blob {digest}: {e}");
+            }
+        }
+        progress.completed(offset);
+    }
+}
+
+/// Stream a blob from the client cache, verifying it against its digest.
+///
+/// A corrupted entry is removed from the cache once the mismatch is detected.
+fn cached_stream(
+    mut file: tokio::fs::File,
+    mut content_digest: ContentDigest,
+    cache: BlobCache,
+    progress: BlobProgress,
+) -> impl Stream<Item = Result<Bytes>> {
+    try_stream! {
+        let mut offset: u64 = 0;
+        loop {
+            let mut chunk = BytesMut::with_capacity(CACHE_CHUNK_SIZE);
+            if file.read_buf(&mut chunk).await? == 0 {
+                break;
+            }
+            let chunk = chunk.freeze();
+            content_digest.update(&chunk);
+            offset += chunk.len() as u64;
+            progress.progress(offset);
+            yield chunk;
+        }
+
+        let digest = content_digest.digest().clone();
+        if let Err(e) = content_digest.verify() {
+            warn!("removing corrupted cache entry for {digest}: {e}");
+            cache.remove(&digest).await?;
+            Err(Error::from(e))?;
+        }
+        progress.completed(offset);
+    }
+}
+
+/// Resolve range bounds to a start offset and an optional inclusive end
+/// offset, as sent in a `Range` header, or `None` if the range is empty.
+fn resolve_range<R: RangeBounds<u64>>(range: &R) -> Option<(u64, Option<u64>)> {
+    let start = match range.start_bound() {
+        Bound::Included(&s) => s,
+        Bound::Excluded(&s) => s.checked_add(1)?,
+        Bound::Unbounded => 0,
+    };
+    let end = match range.end_bound() {
+        Bound::Included(&e) => Some(e),
+        Bound::Excluded(&e) => Some(e.checked_sub(1)?),
+        Bound::Unbounded => None,
+    };
+    match end {
+        Some(end) if end < start => None,
+        _ => Some((start, end)),
     }
 }
 
 #[derive(Debug)]
 pub struct BlobResponse {
-    resp: reqwest::Response,
+    body: BlobBody,
     digest: ContentDigest,
+    cache: Option<BlobCache>,
+}
+
+/// Source of the content of a `BlobResponse`.
+#[derive(Debug)]
+enum BlobBody {
+    /// Response of the registry.
+    Remote(reqwest::Response),
+    /// Entry of the client cache.
+    Cached {
+        url: Url,
+        file: tokio::fs::File,
+        size: u64,
+        cache: BlobCache,
+    },
 }
 
 impl BlobResponse {
-    fn new(resp: reqwest::Response, digest: ContentDigest) -> Self {
-        Self { resp, digest }
+    fn new(body: BlobBody, digest: ContentDigest, cache: Option<BlobCache>) -> Self {
+        Self {
+            body,
+            digest,
+            cache,
+        }
+    }
+
+    /// Get the URL the blob is served from, after following redirects.
+    pub fn url(&self) -> &Url {
+        match &self.body {
+            BlobBody::Remote(resp) => resp.url(),
+            BlobBody::Cached { url, .. } => url,
+        }
     }
 
     /// Get size of the blob.
     /// This method can be useful to render progress bar when downloading a blob.
     pub fn size(&self) -> Option<u64> {
-        self.resp.content_length()
+        match &self.body {
+            BlobBody::Remote(resp) => resp.content_length(),
+            BlobBody::Cached { size, .. } => Some(*size),
+        }
     }
 
     /// Retrieve content of the blob.
     pub async fn bytes(self) -> Result<Vec<u8>> {
-        let blob = self.resp.bytes().await?.to_vec();
+        let resp = match self.body {
+            BlobBody::Remote(resp) => resp,
+            BlobBody::Cached {
+                file, size, cache, ..
+            } => {
+                let progress = BlobProgress::new(None, self.digest.digest());
+                let mut stream = Box::pin(cached_stream(file, self.digest, cache, progress));
+                let mut blob = Vec::with_capacity(size as usize);
+                while let Some(chunk) = stream.try_next().await? {
+                    blob.extend_from_slice(&chunk);
+                }
+                return Ok(blob);
+            }
+        };
+        let blob = resp.bytes().await?.to_vec();
 
         let mut digest = self.digest;
+        let expected = digest.digest().clone();
         digest.update(&blob);
         digest.verify()?;
 
+        if let Some(cache) = self.cache {
+            if let Err(e) = cache.insert(&expected, &blob).await {
+                warn!("failed to cache blob {expected}: {e}");
+            }
+        }
         Ok(blob)
     }
 
     /// Get bytes stream of the blob.
-    pub fn stream(self) -> impl Stream<Item = Result<Vec<u8>>> {
-        BlobStream::new(self.resp.bytes_stream(), self.digest)
+    ///
+    /// Unlike `Client::get_blob_stream`, this does not add the blob to the
+    /// client cache.
+    pub fn stream(self) -> impl Stream<Item = Result<Bytes>> {
+        match self.body {
+            BlobBody::Remote(resp) => {
+                BlobStream::new(resp.bytes_stream(), self.digest).left_stream()
+            }
+            BlobBody::Cached { file, cache, .. } => {
+                let progress = BlobProgress::new(None, self.digest.digest());
+                Box::pin(cached_stream(file, self.digest, cache, progress)).right_stream()
+            }
+        }
     }
 }
 
@@ -130,7 +726,7 @@ impl<S> Stream for BlobStream<S>
 where
     S: Stream<Item = reqwest::Result<Bytes>> + Unpin,
 {
-    type Item = Result<Vec<u8>>;
+    type Item = Result<Bytes>;
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         let mut this = self.project();
@@ -142,7 +738,7 @@ where
                 };
                 let chunk = chunk_res?;
                 digest.update(&chunk);
-                Poll::Ready(Some(Ok(chunk.to_vec())))
+                Poll::Ready(Some(Ok(chunk)))
             }
             Poll::Ready(None) => match this.digest.take() {
                 Some(digest) => match digest.verify() {
@@ -155,3 +751,20 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case((Bound::Included(2), Bound::Included(4)), Some((2, Some(4))) ; "inclusive")]
+    #[test_case((Bound::Included(2), Bound::Excluded(5)), Some((2, Some(4))) ; "exclusive end")]
+    #[test_case((Bound::Excluded(1), Bound::Unbounded), Some((2, None)) ; "exclusive start")]
+    #[test_case((Bound::Excluded(u64::MAX), Bound::Unbounded), None ; "exclusive start overflow")]
+    #[test_case((Bound::Unbounded, Bound::Excluded(0)), None ; "empty prefix")]
+    #[test_case((Bound::Included(5), Bound::Excluded(5)), None ; "empty")]
+    #[test_case((Bound::Unbounded, Bound::Unbounded), Some((0, None)) ; "full")]
+    fn test_resolve_range(range: (Bound<u64>, Bound<u64>), expected: Option<(u64, Option<u64>)>) {
+        assert_eq!(resolve_range(&range), expected);
+    }
+}
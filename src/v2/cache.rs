@@ -0,0 +1,400 @@
+//! On-disk content-addressable cache for blobs and manifests.
+//!
+//! Entries are stored as `<dir>/blobs/<algorithm>/<hex>` and are only ever
+//! written by renaming a fully written and verified temporary file into
+//! place, so readers never observe partial content. The cache can be shared
+//! by several clients and processes; garbage collection is serialized with
+//! an advisory lock on `<dir>/gc.lock`.
+
+use crate::errors::Result;
+use crate::v2::partial::{self, TEMP_MARKER};
+use crate::v2::{ContentDigest, Digest};
+use fs2::FileExt;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{fs, io};
+use tokio::io::AsyncWriteExt;
+
+/// Suffix of the sidecar file holding the media type of a cached manifest.
+const MEDIA_TYPE_SUFFIX: &str = ".mediatype";
+
+/// A content-addressable cache of blobs and manifests, keyed by digest.
+///
+/// When configured with `Config::blob_cache`, the client looks blobs and
+/// manifests fetched by digest up in the cache before contacting the
+/// registry, and stores verified content it downloads.
+///
+/// If a size limit is set, the least recently used entries are removed
+/// whenever an insertion makes the cache exceed it.
+#[derive(Clone, Debug)]
+pub struct BlobCache {
+    dir: PathBuf,
+    max_size: Option<u64>,
+}
+
+impl BlobCache {
+    /// Create a cache stored under the given directory.
+    ///
+    /// The directory is created on first insertion.
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+            max_size: None,
+        }
+    }
+
+    /// Limit the total size of cached content, in bytes.
+    pub fn max_size(mut self, max_size: u64) -> Self {
+        self.max_size = Some(max_size);
+        self
+    }
+
+    /// Return the directory the cache is stored in.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Path of the cache entry for a digest.
+    ///
+    /// Digests are validated on parsing, so they cannot escape the cache directory.
+    fn entry_path(&self, digest: &Digest) -> PathBuf {
+        self.dir
+            .join("blobs")
+            .join(digest.algorithm().name())
+            .join(digest.encoded())
+    }
+
+    /// Whether content for the digest is cached.
+    pub fn contains(&self, digest: &Digest) -> bool {
+        self.entry_path(digest).is_file()
+    }
+
+    /// Return the cached content for a digest, if any.
+    ///
+    /// Content is verified against the digest; corrupted entries are
+    /// removed and reported as missing.
+    pub async fn get(&self, digest: &Digest) -> Result<Option<Vec<u8>>> {
+        let path = self.entry_path(digest);
+        let data = match tokio::fs::read(&path).await {
+            Ok(data) => data,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut content_digest = ContentDigest::new(digest.clone());
+        content_digest.update(&data);
+        if let Err(e) = content_digest.verify() {
+            warn!("removing corrupted cache entry {}: {}", path.display(), e);
+            self.remove(digest).await?;
+            return Ok(None);
+        }
+
+        touch(&path);
+        trace!("Cache hit for {digest}");
+        Ok(Some(data))
+    }
+
+    /// Open the cached content for a digest, if any, returning it with its size.
+    ///
+    /// Unlike `get`, the content is not read nor verified; readers are
+    /// expected to verify it while streaming it, and to `remove` the entry
+    /// if it turns out to be corrupted.
+    pub(crate) async fn open(&self, digest: &Digest) -> Result<Option<(tokio::fs::File, u64)>> {
+        let path = self.entry_path(digest);
+        let file = match tokio::fs::File::open(&path).await {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let size = file.metadata().await?.len();
+
+        touch(&path);
+        trace!("Cache hit for {digest}");
+        Ok(Some((file, size)))
+    }
+
+    /// Return a cached manifest along with its media type, if any.
+    ///
+    /// The content is not verified, as schema 1 manifests are identified by
+    /// the digest of their payload only; callers are expected to verify it,
+    /// and to `remove` the entry if it turns out to be corrupted.
+    pub(crate) async fn get_manifest(&self, digest: &Digest) -> Result<Option<(String, Vec<u8>)>> {
+        let path = self.entry_path(digest);
+        let media_type = match tokio::fs::read_to_string(media_type_path(&path)).await {
+            Ok(media_type) => media_type,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let data = match tokio::fs::read(&path).await {
+            Ok(data) => data,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        touch(&path);
+        trace!("Cache hit for manifest {digest}");
+        Ok(Some((media_type, data)))
+    }
+
+    /// Store content, after verifying it against its digest.
+    pub async fn insert(&self, digest: &Digest, data: &[u8]) -> Result<()> {
+        let mut writer = self.writer(digest).await?;
+        writer.write(data).await?;
+        writer.commit().await
+    }
+
+    /// Store a manifest along with its media type.
+    ///
+    /// The manifest must already have been verified against its digest,
+    /// which for schema 1 manifests covers the payload without signatures.
+    pub(crate) async fn insert_manifest(
+        &self,
+        digest: &Digest,
+        media_type: &str,
+        data: &[u8],
+    ) -> Result<()> {
+        // Manifests are only looked up through their media type, which is
+        // written last so that the manifest is complete once it is visible.
+        let path = self.entry_path(digest);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        write_entry(&path, data).await?;
+        write_entry(&media_type_path(&path), media_type.as_bytes()).await?;
+        trace!("Cached manifest {}", path.display());
+
+        if self.max_size.is_some() {
+            self.gc().await?;
+        }
+        Ok(())
+    }
+
+    /// Remove the cached content for a digest, if any.
+    pub async fn remove(&self, digest: &Digest) -> Result<()> {
+        let path = self.entry_path(digest);
+        remove_entry(&path)?;
+        Ok(())
+    }
+
+    /// Start writing content for a digest.
+    pub(crate) async fn writer(&self, digest: &Digest) -> Result<CacheWriter> {
+        let path = self.entry_path(digest);
+        let (file, temp) = partial::create(&path).await?;
+        Ok(CacheWriter {
+            cache: self.clone(),
+            content_digest: ContentDigest::new(digest.clone()),
+            file: Some(file),
+            temp,
+            path,
+        })
+    }
+
+    /// Remove least recently used entries until the cache fits its size limit.
+    ///
+    /// Returns the number of bytes freed. If another client or process is
+    /// already collecting garbage, this returns immediately.
+    pub async fn gc(&self) -> Result<u64> {
+        let max_size = match self.max_size {
+            Some(max_size) => max_size,
+            None => return Ok(0),
+        };
+        let dir = self.dir.clone();
+        let freed = tokio::task::spawn_blocking(move || gc(&dir, max_size))
+            .await
+            .map_err(io::Error::other)??;
+        Ok(freed)
+    }
+}
+
+/// Content being written to the cache.
+///
+/// The content is only visible in the cache once committed; dropping the
+/// writer discards it.
+#[derive(Debug)]
+pub(crate) struct CacheWriter {
+    cache: BlobCache,
+    content_digest: ContentDigest,
+    file: Option<tokio::fs::File>,
+    temp: PathBuf,
+    path: PathBuf,
+}
+
+impl CacheWriter {
+    /// Append a chunk of content.
+    pub(crate) async fn write(&mut self, chunk: &[u8]) -> io::Result<()> {
+        self.content_digest.update(chunk);
+        match &mut self.file {
+            Some(file) => file.write_all(chunk).await,
+            None => Err(io::Error::other("cache writer already committed")),
+        }
+    }
+
+    /// Verify the content and move it into place.
+    pub(crate) async fn commit(mut self) -> Result<()> {
+        let mut file = self
+            .file
+            .take()
+            .ok_or_else(|| io::Error::other("cache writer already committed"))?;
+        file.flush().await?;
+        self.content_digest.clone().verify()?;
+        partial::persist(file, &self.temp, &self.path).await?;
+        trace!("Cached {}", self.path.display());
+
+        if self.cache.max_size.is_some() {
+            self.cache.gc().await?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for CacheWriter {
+    fn drop(&mut self) {
+        // After a successful commit, the temporary file no longer exists.
+        match fs::remove_file(&self.temp) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => debug!("failed to remove {}: {}", self.temp.display(), e),
+            Ok(()) => {}
+        }
+    }
+}
+
+/// Record an access to an entry for LRU garbage collection.
+fn touch(path: &Path) {
+    let touched = fs::File::open(path).and_then(|f| f.set_modified(SystemTime::now()));
+    if let Err(e) = touched {
+        debug!("failed to update access time of {}: {}", path.display(), e);
+    }
+}
+
+/// Path of the media type sidecar of a cache entry.
+fn media_type_path(path: &Path) -> PathBuf {
+    let mut sidecar = path.as_os_str().to_owned();
+    sidecar.push(MEDIA_TYPE_SUFFIX);
+    sidecar.into()
+}
+
+/// Write a cache entry through a synced temporary file.
+async fn write_entry(path: &Path, data: &[u8]) -> io::Result<()> {
+    let (mut file, temp) = partial::create(path).await?;
+    let written = async {
+        file.write_all(data).await?;
+        partial::persist(file, &temp, path).await
+    }
+    .await;
+    if written.is_err() {
+        partial::discard(&temp).await;
+    }
+    written
+}
+
+/// Remove a cache entry and its media type sidecar.
+fn remove_entry(path: &Path) -> io::Result<()> {
+    // The sidecar is removed first, so that the entry is no longer looked up.
+    for p in [media_type_path(path), path.to_path_buf()].iter() {
+        match fs::remove_file(p) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+            res => res?,
+        }
+    }
+    Ok(())
+}
+
+fn gc(dir: &Path, max_size: u64) -> io::Result<u64> {
+    let lock = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(dir.join("gc.lock"))?;
+    if lock.try_lock_exclusive().is_err() {
+        trace!("Cache garbage collection already in progress");
+        return Ok(0);
+    }
+
+    let mut entries = Vec::new();
+    let mut total = 0;
+    for algorithm in fs::read_dir(dir.join("blobs"))? {
+        for entry in fs::read_dir(algorithm?.path())? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if name.contains(TEMP_MARKER) || name.ends_with(MEDIA_TYPE_SUFFIX) {
+                continue;
+            }
+            let metadata = entry.metadata()?;
+            total += metadata.len();
+            let used = metadata.modified().unwrap_or(UNIX_EPOCH);
+            entries.push((used, metadata.len(), entry.path()));
+        }
+    }
+
+    entries.sort();
+    let mut freed = 0;
+    for (_, size, path) in entries {
+        if total - freed <= max_size {
+            break;
+        }
+        remove_entry(&path)?;
+        debug!("Evicted {} from cache", path.display());
+        freed += size;
+    }
+
+    // The lock is released when the file is closed.
+    drop(lock);
+    Ok(freed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v2::DigestAlgorithm;
+
+    fn digest(data: &[u8]) -> Digest {
+        DigestAlgorithm::Sha256.digest(data)
+    }
+
+    #[test]
+    fn entry_path_layout() {
+        assert_eq!(
+            BlobCache::new("/cache").entry_path(&digest(b"")),
+            Path::new("/cache/blobs/sha256")
+                .join("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855")
+        );
+    }
+
+    #[tokio::test]
+    async fn insert_and_get() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = BlobCache::new(dir.path());
+        let data = b"cached content";
+
+        assert_eq!(cache.get(&digest(data)).await.unwrap(), None);
+        cache.insert(&digest(data), data).await.unwrap();
+        assert!(cache.contains(&digest(data)));
+        assert_eq!(cache.get(&digest(data)).await.unwrap(), Some(data.to_vec()));
+
+        // Content not matching its digest is rejected.
+        assert!(cache.insert(&digest(b"other"), data).await.is_err());
+        assert!(!cache.contains(&digest(b"other")));
+    }
+
+    #[tokio::test]
+    async fn gc_evicts_least_recently_used() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = BlobCache::new(dir.path()).max_size(25);
+        let blobs: [&[u8]; 3] = [b"first blob", b"second blob", b"third blob"];
+
+        cache.insert(&digest(blobs[0]), blobs[0]).await.unwrap();
+        cache.insert(&digest(blobs[1]), blobs[1]).await.unwrap();
+        // Make the first blob the most recently used one.
+        let past = SystemTime::now() - std::time::Duration::from_secs(60);
+        fs::File::open(cache.entry_path(&digest(blobs[1])))
+            .unwrap()
+            .set_modified(past)
+            .unwrap();
+        cache.insert(&digest(blobs[2]), blobs[2]).await.unwrap();
+
+        assert!(cache.contains(&digest(blobs[0])));
+        assert!(!cache.contains(&digest(blobs[1])));
+        assert!(cache.contains(&digest(blobs[2])));
+    }
+}
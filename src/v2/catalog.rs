@@ -3,7 +3,7 @@ use crate::v2;
 use async_stream::try_stream;
 use futures::stream::Stream;
 use futures::{self};
-use reqwest::{Method, RequestBuilder, StatusCode};
+use reqwest::{Method, StatusCode};
 
 #[derive(Debug, Default, Deserialize, Serialize)]
 struct Catalog {
@@ -38,12 +38,12 @@ impl v2::Client {
     }
 }
 
-async fn fetch_catalog(req: RequestBuilder) -> Result<Catalog> {
+async fn fetch_catalog(req: v2::RequestBuilder<'_>) -> Result<Catalog> {
     let r = req.send().await?;
     let status = r.status();
     trace!("Got status: {status:?}");
     match status {
         StatusCode::OK => r.json::<Catalog>().await.map_err(Into::into),
-        _ => Err(crate::Error::UnexpectedHttpStatus(status)),
+        _ => Err(v2::error_from_response(Method::GET, r).await),
     }
 }
@@ -1,5 +1,6 @@
 use crate::{mediatypes::MediaTypes, v2::*};
 use reqwest::Certificate;
+use std::sync::Arc;
 
 /// Configuration for a `Client`.
 #[derive(Debug)]
@@ -12,6 +13,10 @@ pub struct Config {
     accept_invalid_certs: bool,
     root_certificates: Vec<Certificate>,
     accepted_types: Option<Vec<(MediaTypes, Option<f64>)>>,
+    retry_policy: RetryPolicy,
+    middlewares: Vec<Arc<dyn Middleware>>,
+    transport: Option<Arc<dyn Transport>>,
+    cache: Option<BlobCache>,
 }
 
 impl Config {
@@ -48,6 +53,36 @@ impl Config {
         self
     }
 
+    /// Set the policy for retrying transient failures.
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Add a middleware, applied to every request sent by the client.
+    ///
+    /// Middlewares are applied in the order they are added.
+    pub fn middleware(mut self, middleware: Arc<dyn Middleware>) -> Self {
+        self.middlewares.push(middleware);
+        self
+    }
+
+    /// Set the transport used to send requests to the registry.
+    ///
+    /// By default requests are sent with `reqwest`, as configured by the
+    /// TLS settings of this `Config`; these settings are ignored by custom
+    /// transports.
+    pub fn transport(mut self, transport: Arc<dyn Transport>) -> Self {
+        self.transport = Some(transport);
+        self
+    }
+
+    /// Set a local cache for blobs and manifests fetched by digest.
+    pub fn blob_cache(mut self, cache: BlobCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
     /// Set the user-agent to be used for registry authentication.
     pub fn user_agent(mut self, user_agent: Option<String>) -> Self {
         self.user_agent = user_agent;
@@ -75,6 +110,11 @@ impl Config {
         self
     }
 
+    /// Whether a username or password has been set.
+    pub(crate) fn has_credentials(&self) -> bool {
+        self.username.is_some() || self.password.is_some()
+    }
+
     /// Return a `Client` to interact with a v2 registry.
     pub fn build(self) -> Result<Client> {
         let base = if self.insecure_registry {
@@ -96,14 +136,21 @@ impl Config {
             )),
         };
 
-        let mut builder =
-            reqwest::ClientBuilder::new().danger_accept_invalid_certs(self.accept_invalid_certs);
+        // Redirects are followed by `Client` itself, so that credentials
+        // are not forwarded to other origins (e.g. blob storage).
+        let mut builder = reqwest::ClientBuilder::new()
+            .danger_accept_invalid_certs(self.accept_invalid_certs)
+            .redirect(reqwest::redirect::Policy::none());
 
         for ca in self.root_certificates {
             builder = builder.add_root_certificate(ca)
         }
 
         let client = builder.build()?;
+        let transport = match self.transport {
+            Some(t) => t,
+            None => Arc::new(ReqwestTransport::new(client.clone())),
+        };
 
         let accepted_types = match self.accepted_types {
             Some(a) => a,
@@ -132,6 +179,11 @@ impl Config {
             auth: None,
             client,
             accepted_types,
+            retry_policy: self.retry_policy,
+            middlewares: self.middlewares,
+            cache: self.cache,
+            transport,
+            rate_limits: Default::default(),
         };
         Ok(c)
     }
@@ -146,6 +198,10 @@ impl Default for Config {
             accept_invalid_certs: false,
             root_certificates: Default::default(),
             accepted_types: None,
+            retry_policy: RetryPolicy::default(),
+            middlewares: Vec::new(),
+            transport: None,
+            cache: None,
             user_agent: Some(crate::USER_AGENT.to_owned()),
             username: None,
             password: None,
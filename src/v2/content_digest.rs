@@ -1,71 +1,328 @@
 /// Implements types and methods for content verification
-use sha2::{self, Digest};
-use std::str;
+use sha2::{self, Digest as _};
+use std::convert::TryFrom;
+use std::{fmt, str};
 
-/// DigestAlgorithm declares the supported algorithms
-#[derive(Display, Clone, Debug)]
+/// Hash algorithms supported for content digests.
+///
+/// Further algorithms can be enabled with cargo features (e.g. `blake3`).
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum DigestAlgorithm {
-    Sha256(sha2::Sha256),
+    /// SHA-256, the canonical algorithm.
+    #[default]
+    Sha256,
+    /// SHA-512.
+    Sha512,
+    /// BLAKE3, with 256 bits of output.
+    #[cfg(feature = "blake3")]
+    Blake3,
+}
+
+impl DigestAlgorithm {
+    /// Return all supported algorithms.
+    pub fn all() -> &'static [DigestAlgorithm] {
+        &[
+            DigestAlgorithm::Sha256,
+            DigestAlgorithm::Sha512,
+            #[cfg(feature = "blake3")]
+            DigestAlgorithm::Blake3,
+        ]
+    }
+
+    /// Return the algorithm identifier, as used in digests.
+    pub fn name(&self) -> &'static str {
+        match self {
+            DigestAlgorithm::Sha256 => "sha256",
+            DigestAlgorithm::Sha512 => "sha512",
+            #[cfg(feature = "blake3")]
+            DigestAlgorithm::Blake3 => "blake3",
+        }
+    }
+
+    /// Return the length of the hex-encoded hash.
+    pub fn encoded_len(&self) -> usize {
+        match self {
+            DigestAlgorithm::Sha256 => 64,
+            DigestAlgorithm::Sha512 => 128,
+            #[cfg(feature = "blake3")]
+            DigestAlgorithm::Blake3 => 64,
+        }
+    }
+
+    /// Start computing a digest incrementally.
+    pub fn hasher(&self) -> DigestHasher {
+        let state = match self {
+            DigestAlgorithm::Sha256 => HasherState::Sha256(sha2::Sha256::new()),
+            DigestAlgorithm::Sha512 => HasherState::Sha512(sha2::Sha512::new()),
+            #[cfg(feature = "blake3")]
+            DigestAlgorithm::Blake3 => HasherState::Blake3(Box::new(blake3::Hasher::new())),
+        };
+        DigestHasher { state }
+    }
+
+    /// Compute the digest of some content.
+    pub fn digest(&self, content: &[u8]) -> Digest {
+        let mut hasher = self.hasher();
+        hasher.update(content);
+        hasher.finalize()
+    }
+}
+
+impl fmt::Display for DigestAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
 }
 
 impl std::str::FromStr for DigestAlgorithm {
     type Err = ContentDigestError;
 
     fn from_str(name: &str) -> Result<Self, Self::Err> {
-        match name {
-            "sha256" => Ok(DigestAlgorithm::Sha256(sha2::Sha256::new())),
-            _ => Err(ContentDigestError::AlgorithmUnknown(name.to_string())),
+        DigestAlgorithm::all()
+            .iter()
+            .copied()
+            .find(|a| a.name() == name)
+            .ok_or_else(|| ContentDigestError::AlgorithmUnknown(name.to_string()))
+    }
+}
+
+/// Incremental computation of a content digest.
+#[derive(Clone, Debug)]
+pub struct DigestHasher {
+    state: HasherState,
+}
+
+#[derive(Clone, Debug)]
+enum HasherState {
+    Sha256(sha2::Sha256),
+    Sha512(sha2::Sha512),
+    #[cfg(feature = "blake3")]
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl DigestHasher {
+    /// Return the algorithm of this hasher.
+    pub fn algorithm(&self) -> DigestAlgorithm {
+        match self.state {
+            HasherState::Sha256(_) => DigestAlgorithm::Sha256,
+            HasherState::Sha512(_) => DigestAlgorithm::Sha512,
+            #[cfg(feature = "blake3")]
+            HasherState::Blake3(_) => DigestAlgorithm::Blake3,
+        }
+    }
+
+    /// Feed content into the hasher.
+    pub fn update(&mut self, input: &[u8]) {
+        match &mut self.state {
+            HasherState::Sha256(hash) => hash.update(input),
+            HasherState::Sha512(hash) => hash.update(input),
+            #[cfg(feature = "blake3")]
+            HasherState::Blake3(hash) => {
+                hash.update(input);
+            }
+        }
+    }
+
+    /// Return the digest of all content fed so far.
+    pub fn finalize(self) -> Digest {
+        let algorithm = self.algorithm();
+        let hex = match self.state {
+            HasherState::Sha256(hash) => format!("{:x}", hash.finalize()),
+            HasherState::Sha512(hash) => format!("{:x}", hash.finalize()),
+            #[cfg(feature = "blake3")]
+            HasherState::Blake3(hash) => hash.finalize().to_hex().to_string(),
+        };
+        Digest {
+            value: format!("{algorithm}:{hex}"),
+            algorithm,
         }
     }
 }
 
+/// A validated content digest, as `<algorithm>:<encoded>`.
+///
+/// Parsing follows the grammar of the OCI image spec and additionally
+/// requires the algorithm to be supported and the encoded part to be
+/// the lowercase hex encoding of a hash of the right length.
+/// See <https://github.com/opencontainers/image-spec/blob/main/descriptor.md#digests>.
+///
+/// ```rust
+/// # extern crate dkregistry;
+/// use dkregistry::v2::{Digest, DigestAlgorithm};
+///
+/// let digest: Digest = "sha256:d5a3477d91583e65a7aba6f6db7a53e2de739bc7bf8f4a08f0df0457b637f1fb"
+///     .parse()
+///     .unwrap();
+/// assert_eq!(digest.algorithm(), DigestAlgorithm::Sha256);
+/// assert_eq!(digest, DigestAlgorithm::Sha256.digest(b"somecontent"));
+/// assert!("sha256:abc".parse::<Digest>().is_err());
+/// ```
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Digest {
+    value: String,
+    algorithm: DigestAlgorithm,
+}
+
+impl Digest {
+    /// Return the algorithm of this digest.
+    pub fn algorithm(&self) -> DigestAlgorithm {
+        self.algorithm
+    }
+
+    /// Return the encoded hash, without the algorithm prefix.
+    pub fn encoded(&self) -> &str {
+        &self.value[self.algorithm.name().len() + 1..]
+    }
+
+    /// Return the digest as `<algorithm>:<encoded>`.
+    pub fn as_str(&self) -> &str {
+        &self.value
+    }
+}
+
+impl str::FromStr for Digest {
+    type Err = ContentDigestError;
+
+    fn from_str(digest: &str) -> Result<Self, Self::Err> {
+        let bad_digest = || ContentDigestError::BadDigest(digest.to_string());
+        let (algorithm, encoded) = digest.split_once(':').ok_or_else(bad_digest)?;
+
+        // algorithm-component ::= [a-z0-9]+, joined by one of [+._-]
+        let well_formed = algorithm
+            .split(['+', '.', '_', '-'])
+            .all(|c| !c.is_empty() && c.bytes().all(|b| matches!(b, b'a'..=b'z' | b'0'..=b'9')));
+        if !well_formed {
+            return Err(bad_digest());
+        }
+        let algorithm: DigestAlgorithm = algorithm.parse()?;
+
+        let hex = encoded
+            .bytes()
+            .all(|b| matches!(b, b'a'..=b'f' | b'0'..=b'9'));
+        if encoded.len() != algorithm.encoded_len() || !hex {
+            return Err(ContentDigestError::BadEncoding(digest.to_string()));
+        }
+
+        Ok(Digest {
+            value: digest.to_string(),
+            algorithm,
+        })
+    }
+}
+
+impl TryFrom<String> for Digest {
+    type Error = ContentDigestError;
+
+    fn try_from(digest: String) -> Result<Self, Self::Error> {
+        digest.parse()
+    }
+}
+
+impl From<Digest> for String {
+    fn from(digest: Digest) -> Self {
+        digest.value
+    }
+}
+
+impl AsRef<str> for Digest {
+    fn as_ref(&self) -> &str {
+        &self.value
+    }
+}
+
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.value)
+    }
+}
+
+impl fmt::Debug for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Digest({:?})", self.value)
+    }
+}
+
+impl PartialEq<str> for Digest {
+    fn eq(&self, other: &str) -> bool {
+        self.value == other
+    }
+}
+
+impl PartialEq<&str> for Digest {
+    fn eq(&self, other: &&str) -> bool {
+        self.value == *other
+    }
+}
+
+impl PartialEq<String> for Digest {
+    fn eq(&self, other: &String) -> bool {
+        &self.value == other
+    }
+}
+
+impl PartialEq<Digest> for String {
+    fn eq(&self, other: &Digest) -> bool {
+        self == &other.value
+    }
+}
+
+impl PartialEq<Digest> for str {
+    fn eq(&self, other: &Digest) -> bool {
+        self == other.value
+    }
+}
+
+impl PartialEq<Digest> for &str {
+    fn eq(&self, other: &Digest) -> bool {
+        *self == other.value
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum ContentDigestError {
     #[error("digest {0} does not have algorithm prefix")]
     BadDigest(String),
+    #[error("digest {0} has an invalid encoded hash")]
+    BadEncoding(String),
     #[error("unknown algorithm: {0}")]
     AlgorithmUnknown(String),
     #[error("verification failed: expected '{expected}', got '{got}'")]
     Verify { expected: String, got: String },
 }
 
-/// ContentDigest stores a digest and its DigestAlgorithm
+/// ContentDigest stores a digest and the hasher to verify content against it
 #[derive(Clone, Debug)]
 pub struct ContentDigest {
-    digest: String,
-    algorithm: DigestAlgorithm,
+    digest: Digest,
+    hasher: DigestHasher,
 }
 
 impl ContentDigest {
-    /// try_new attempts to parse the digest string and create a ContentDigest instance from it
-    ///
-    /// Success depends on
-    /// - the string having an "algorithm:" prefix
-    /// - the algorithm being supported by DigestAlgorithm
-    pub fn try_new(digest: &str) -> std::result::Result<Self, ContentDigestError> {
-        let digest_split = digest.split(':').collect::<Vec<&str>>();
-
-        if digest_split.len() != 2 {
-            return Err(ContentDigestError::BadDigest(digest.to_string()));
+    /// Create a ContentDigest to verify content against `digest`.
+    pub fn new(digest: Digest) -> Self {
+        ContentDigest {
+            hasher: digest.algorithm().hasher(),
+            digest,
         }
+    }
 
-        let algorithm = std::str::FromStr::from_str(digest_split[0])?;
-        Ok(ContentDigest {
-            digest: digest.to_string(),
-            algorithm,
-        })
+    /// Return the expected digest.
+    pub(crate) fn digest(&self) -> &Digest {
+        &self.digest
     }
 
     pub fn update(&mut self, input: &[u8]) {
-        self.algorithm.update(input)
+        self.hasher.update(input)
     }
 
     pub fn verify(self) -> std::result::Result<(), ContentDigestError> {
-        let digest = self.algorithm.digest();
+        let digest = self.hasher.finalize();
         if digest != self.digest {
             return Err(ContentDigestError::Verify {
-                expected: self.digest,
-                got: digest,
+                expected: self.digest.into(),
+                got: digest.into(),
             });
         }
         Ok(())
@@ -74,31 +331,14 @@ impl ContentDigest {
 
 impl std::fmt::Display for ContentDigest {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{}:{}", self.algorithm, self.digest)
-    }
-}
-
-impl DigestAlgorithm {
-    fn update(&mut self, input: &[u8]) {
-        match self {
-            DigestAlgorithm::Sha256(hash) => {
-                hash.update(input);
-            }
-        }
-    }
-
-    fn digest(self) -> String {
-        let (algo, digest) = match self {
-            DigestAlgorithm::Sha256(hash) => ("sha256", hash.finalize()),
-        };
-        format!("{}:{:x}", algo, &digest)
+        write!(f, "{}", self.digest)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use sha2;
+    use test_case::test_case;
 
     type Fallible<T> = Result<T, crate::Error>;
 
@@ -107,7 +347,7 @@ mod tests {
         {
             let correct_digest =
                 &"sha256:0000000000000000000000000000000000000000000000000000000000000000";
-            ContentDigest::try_new(correct_digest)?;
+            correct_digest.parse::<Digest>()?;
         }
 
         Ok(())
@@ -120,7 +360,7 @@ mod tests {
             "invalid:",
             "invalid:0000000000000000000000000000000000000000000000000000000000000000",
         ] {
-            if ContentDigest::try_new(incorrect_digest).is_ok() {
+            if incorrect_digest.parse::<Digest>().is_ok() {
                 panic!(
                     "expected try_new to fail for incorrect digest {}",
                     incorrect_digest
@@ -132,18 +372,18 @@ mod tests {
     #[test]
     fn verify_succeeds_with_same_content() -> Fallible<()> {
         let blob: &[u8] = b"somecontent";
-        let mut content_digest = ContentDigest::try_new(
-            "sha256:d5a3477d91583e65a7aba6f6db7a53e2de739bc7bf8f4a08f0df0457b637f1fb",
-        )?;
+        let mut content_digest = ContentDigest::new(
+            "sha256:d5a3477d91583e65a7aba6f6db7a53e2de739bc7bf8f4a08f0df0457b637f1fb".parse()?,
+        );
         content_digest.update(blob);
         content_digest.verify().map_err(Into::into)
     }
 
     #[test]
     fn verify_chunked_succeeds_with_same_content() -> Fallible<()> {
-        let mut content_digest = ContentDigest::try_new(
-            "sha256:d5a3477d91583e65a7aba6f6db7a53e2de739bc7bf8f4a08f0df0457b637f1fb",
-        )?;
+        let mut content_digest = ContentDigest::new(
+            "sha256:d5a3477d91583e65a7aba6f6db7a53e2de739bc7bf8f4a08f0df0457b637f1fb".parse()?,
+        );
         content_digest.update(b"some");
         content_digest.update(b"content");
         content_digest.verify().map_err(Into::into)
@@ -154,15 +394,71 @@ mod tests {
         let blob: &[u8] = b"somecontent";
         let different_blob: &[u8] = b"someothercontent";
 
-        let mut expected_digest = DigestAlgorithm::Sha256(sha2::Sha256::new());
-        expected_digest.update(different_blob);
-        let expected_digest = expected_digest.digest();
+        let expected_digest = DigestAlgorithm::Sha256.digest(different_blob);
 
-        let mut content_digest = ContentDigest::try_new(&expected_digest)?;
+        let mut content_digest = ContentDigest::new(expected_digest);
         content_digest.update(blob);
         if content_digest.verify().is_ok() {
             panic!("expected try_verify to fail for a different blob");
         }
         Ok(())
     }
+
+    #[test_case(DigestAlgorithm::Sha256 => "sha256:d5a3477d91583e65a7aba6f6db7a53e2de739bc7bf8f4a08f0df0457b637f1fb"; "sha256")]
+    #[test_case(DigestAlgorithm::Sha512 => "sha512:075acbafc43b4285903d2db3db7be7cebe056d50fba6e8a9f9bcdf7f3a2bba841786c29fa385780cd0bb631e0d44be60a863f9a088c16b131ea94f4ca180844d"; "sha512")]
+    fn digest_computes(algorithm: DigestAlgorithm) -> String {
+        algorithm.digest(b"somecontent").to_string()
+    }
+
+    #[test]
+    fn verify_succeeds_with_sha512() -> Fallible<()> {
+        let blob: &[u8] = b"somecontent";
+        let mut content_digest = ContentDigest::new(DigestAlgorithm::Sha512.digest(blob));
+        content_digest.update(blob);
+        content_digest.verify().map_err(Into::into)
+    }
+
+    #[test]
+    fn algorithm_from_name() {
+        for &algorithm in DigestAlgorithm::all() {
+            assert_eq!(
+                algorithm.name().parse::<DigestAlgorithm>().unwrap(),
+                algorithm
+            );
+            let digest = algorithm.digest(b"");
+            assert_eq!(digest.algorithm(), algorithm);
+            assert_eq!(digest.encoded().len(), algorithm.encoded_len());
+            assert_eq!(digest.as_str().parse::<Digest>().unwrap(), digest);
+        }
+    }
+
+    #[test_case("sha256:d5a3477d91583e65a7aba6f6db7a53e2de739bc7bf8f4a08f0df0457b637f1fb" => true; "sha256")]
+    #[test_case("sha512:075acbafc43b4285903d2db3db7be7cebe056d50fba6e8a9f9bcdf7f3a2bba841786c29fa385780cd0bb631e0d44be60a863f9a088c16b131ea94f4ca180844d" => true; "sha512")]
+    #[test_case("sha256:D5A3477D91583E65A7ABA6F6DB7A53E2DE739BC7BF8F4A08F0DF0457B637F1FB" => false; "uppercase")]
+    #[test_case("sha256:d5a3477d91583e65a7aba6f6db7a53e2de739bc7bf8f4a08f0df0457b637f1f" => false; "too short")]
+    #[test_case("sha512:d5a3477d91583e65a7aba6f6db7a53e2de739bc7bf8f4a08f0df0457b637f1fb" => false; "wrong length")]
+    #[test_case("sha256:../../../../../../../../../../../../../../../../../etc/passwd" => false; "traversal")]
+    #[test_case("sha256:" => false; "empty encoded")]
+    #[test_case(":d5a3477d91583e65a7aba6f6db7a53e2de739bc7bf8f4a08f0df0457b637f1fb" => false; "empty algorithm")]
+    #[test_case("SHA256:d5a3477d91583e65a7aba6f6db7a53e2de739bc7bf8f4a08f0df0457b637f1fb" => false; "uppercase algorithm")]
+    #[test_case("md5:d41d8cd98f00b204e9800998ecf8427e" => false; "unknown algorithm")]
+    #[test_case("fakedigest" => false; "no algorithm")]
+    fn digest_parses(digest: &str) -> bool {
+        digest.parse::<Digest>().is_ok()
+    }
+
+    #[test]
+    fn digest_display_and_serde() -> Fallible<()> {
+        let digest = DigestAlgorithm::Sha256.digest(b"somecontent");
+        let expected = "sha256:d5a3477d91583e65a7aba6f6db7a53e2de739bc7bf8f4a08f0df0457b637f1fb";
+        assert_eq!(digest.to_string(), expected);
+        assert_eq!(ContentDigest::new(digest.clone()).to_string(), expected);
+        assert_eq!(serde_json::to_string(&digest)?, format!("\"{}\"", expected));
+        assert_eq!(
+            serde_json::from_str::<Digest>(&format!("\"{}\"", expected))?,
+            digest
+        );
+        assert!(serde_json::from_str::<Digest>("\"sha256:abc\"").is_err());
+        Ok(())
+    }
 }
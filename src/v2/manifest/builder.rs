@@ -0,0 +1,517 @@
+//! Construction of new images and multi-platform indexes, and assembly of
+//! indexes from images already in a repository.
+
+use super::convert::ManifestFormat;
+use super::manifest_schema2::{
+    Config, ImageConfig, ManifestList, ManifestObj, ManifestSchema2Spec, Platform, S2Layer,
+};
+use super::{LayerDiff, RawManifest};
+use crate::errors::{Error, Result};
+use crate::mediatypes::MediaTypes;
+use crate::v2::{Client, Digest, DigestAlgorithm};
+use std::collections::BTreeMap;
+
+/// Builder for a new image.
+///
+/// The config is completed with the diff ID of each added layer, and the
+/// manifest is serialized with the media types of the chosen format.
+#[derive(Clone, Debug)]
+pub struct ImageBuilder {
+    format: ManifestFormat,
+    algorithm: DigestAlgorithm,
+    config: ImageConfig,
+    layers: Vec<S2Layer>,
+    blobs: Vec<(Digest, Vec<u8>)>,
+    annotations: BTreeMap<String, String>,
+    platform: Option<Platform>,
+}
+
+impl ImageBuilder {
+    /// Start building an image with the given config.
+    ///
+    /// The image has no layers yet, and uses the schema 2 format.
+    pub fn new(config: ImageConfig) -> Self {
+        Self {
+            format: ManifestFormat::Schema2,
+            algorithm: DigestAlgorithm::default(),
+            config,
+            layers: Vec::new(),
+            blobs: Vec::new(),
+            annotations: BTreeMap::new(),
+            platform: None,
+        }
+    }
+
+    /// Set the format of the manifest.
+    pub fn format(mut self, format: ManifestFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Set the algorithm of the blob and manifest digests (defaults to SHA-256).
+    ///
+    /// Layers are digested as they are added, so set it before adding them.
+    pub fn algorithm(mut self, algorithm: DigestAlgorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// Add a layer on top of the previous ones, from a tar archive, gzipped or not.
+    pub fn layer(mut self, data: Vec<u8>) -> Result<Self> {
+        let diff = LayerDiff::from_blob(&data)?;
+        let gzip = data.starts_with(&[0x1f, 0x8b]);
+        let digest = self.algorithm.digest(&data);
+        self.config.rootfs.diff_ids.push(diff.diff_id);
+        self.layers.push(S2Layer {
+            media_type: self.format.layer_media_type(gzip).to_string(),
+            size: diff.size,
+            digest: digest.clone(),
+            urls: None,
+            annotations: None,
+        });
+        self.blobs.push((digest, data));
+        Ok(self)
+    }
+
+    /// Add a layer already stored in the repository the image is pushed to.
+    pub fn existing_layer(mut self, layer: S2Layer, diff_id: Digest) -> Self {
+        self.config.rootfs.diff_ids.push(diff_id);
+        self.layers.push(layer);
+        self
+    }
+
+    /// Set an annotation on the manifest.
+    ///
+    /// Annotations are only supported by the OCI format, and dropped otherwise.
+    pub fn annotation(mut self, key: &str, value: &str) -> Self {
+        self.annotations.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    /// Set the platform of the image.
+    ///
+    /// This overrides the architecture, OS and variant of the config, and
+    /// is used to describe the image in indexes.
+    pub fn platform(mut self, platform: Platform) -> Self {
+        self.config.architecture = platform.architecture.clone();
+        self.config.os = platform.os.clone();
+        self.config.variant = platform.variant.clone();
+        self.platform = Some(platform);
+        self
+    }
+
+    /// Serialize the config and manifest of the image.
+    pub fn build(self) -> Result<BuiltImage> {
+        let config_blob = serde_json::to_vec(&self.config)?;
+        let config_digest = self.algorithm.digest(&config_blob);
+        let annotations = match self.format {
+            ManifestFormat::Oci if !self.annotations.is_empty() => Some(self.annotations),
+            _ => None,
+        };
+        let spec = ManifestSchema2Spec {
+            schema_version: 2,
+            media_type: self.format.manifest_media_type().to_string(),
+            config: Config {
+                media_type: self.format.config_media_type().to_string(),
+                size: config_blob.len() as u64,
+                digest: config_digest.clone(),
+            },
+            layers: self.layers,
+            annotations,
+        };
+        let bytes = serde_json::to_vec(&spec)?;
+        let config = &self.config;
+        let platform = self.platform.unwrap_or_else(|| Platform {
+            architecture: config.architecture.clone(),
+            os: config.os.clone(),
+            variant: config.variant.clone(),
+            ..Platform::default()
+        });
+
+        let mut blobs = vec![(config_digest, config_blob)];
+        blobs.extend(self.blobs);
+        Ok(BuiltImage {
+            manifest: RawManifest {
+                digest: self.algorithm.digest(&bytes),
+                media_type: self.format.manifest_media_type(),
+                bytes,
+            },
+            spec,
+            platform,
+            blobs,
+        })
+    }
+}
+
+/// An image built by `ImageBuilder`, ready to be pushed.
+#[derive(Clone, Debug)]
+pub struct BuiltImage {
+    manifest: RawManifest,
+    spec: ManifestSchema2Spec,
+    platform: Platform,
+    blobs: Vec<(Digest, Vec<u8>)>,
+}
+
+impl BuiltImage {
+    /// The serialized manifest.
+    pub fn manifest(&self) -> &RawManifest {
+        &self.manifest
+    }
+
+    /// The manifest.
+    pub fn spec(&self) -> &ManifestSchema2Spec {
+        &self.spec
+    }
+
+    /// The platform of the image.
+    pub fn platform(&self) -> &Platform {
+        &self.platform
+    }
+
+    /// Digest of the manifest.
+    pub fn digest(&self) -> &Digest {
+        &self.manifest.digest
+    }
+
+    /// Descriptor of the image, as listed in indexes.
+    pub fn descriptor(&self) -> ManifestObj {
+        ManifestObj {
+            media_type: self.manifest.media_type.to_string(),
+            size: self.manifest.bytes.len() as u64,
+            digest: self.manifest.digest.clone(),
+            platform: self.platform.clone(),
+            annotations: None,
+        }
+    }
+}
+
+/// Builder for a new multi-platform index.
+#[derive(Clone, Debug)]
+pub struct IndexBuilder {
+    format: ManifestFormat,
+    algorithm: DigestAlgorithm,
+    manifests: Vec<ManifestObj>,
+    images: Vec<BuiltImage>,
+    annotations: BTreeMap<String, String>,
+}
+
+impl Default for IndexBuilder {
+    fn default() -> Self {
+        Self {
+            format: ManifestFormat::Schema2,
+            algorithm: DigestAlgorithm::default(),
+            manifests: Vec::new(),
+            images: Vec::new(),
+            annotations: BTreeMap::new(),
+        }
+    }
+}
+
+impl IndexBuilder {
+    /// Set the format of the index (defaults to a schema 2 manifest list).
+    pub fn format(mut self, format: ManifestFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Set the algorithm of the index digest (defaults to SHA-256).
+    ///
+    /// Images keep the digests they were built with.
+    pub fn algorithm(mut self, algorithm: DigestAlgorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// Add a new image, which is pushed along with the index.
+    pub fn image(mut self, image: BuiltImage) -> Self {
+        self.manifests.push(image.descriptor());
+        self.images.push(image);
+        self
+    }
+
+    /// Add an image already stored in the repository the index is pushed to.
+    pub fn manifest(mut self, descriptor: ManifestObj) -> Self {
+        self.manifests.push(descriptor);
+        self
+    }
+
+    /// Set an annotation on the index.
+    ///
+    /// As with `ImageBuilder::annotation`, it is only kept in the OCI format.
+    pub fn annotation(mut self, key: &str, value: &str) -> Self {
+        self.annotations.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    /// Serialize the index.
+    pub fn build(self) -> Result<BuiltIndex> {
+        let annotations = match self.format {
+            ManifestFormat::Oci if !self.annotations.is_empty() => Some(self.annotations),
+            _ => None,
+        };
+        let list = ManifestList {
+            schema_version: 2,
+            media_type: self.format.index_media_type().to_string(),
+            manifests: self.manifests,
+            annotations,
+        };
+        let bytes = serde_json::to_vec(&list)?;
+        Ok(BuiltIndex {
+            manifest: RawManifest {
+                digest: self.algorithm.digest(&bytes),
+                media_type: self.format.index_media_type(),
+                bytes,
+            },
+            list,
+            images: self.images,
+        })
+    }
+}
+
+/// An index built by `IndexBuilder`, ready to be pushed.
+#[derive(Clone, Debug)]
+pub struct BuiltIndex {
+    manifest: RawManifest,
+    list: ManifestList,
+    images: Vec<BuiltImage>,
+}
+
+impl BuiltIndex {
+    /// The serialized index.
+    pub fn manifest(&self) -> &RawManifest {
+        &self.manifest
+    }
+
+    /// The index.
+    pub fn list(&self) -> &ManifestList {
+        &self.list
+    }
+
+    /// The new images of the index.
+    pub fn images(&self) -> &[BuiltImage] {
+        &self.images
+    }
+
+    /// Digest of the index.
+    pub fn digest(&self) -> &Digest {
+        &self.manifest.digest
+    }
+}
+
+impl Client {
+    /// Push an image built with `ImageBuilder`, returning its manifest digest.
+    ///
+    /// Blobs already present in the repository are skipped, then the
+    /// manifest is pushed with the given reference, either a tag or digest.
+    pub async fn push_image(
+        &self,
+        name: &str,
+        reference: &str,
+        image: &BuiltImage,
+    ) -> Result<Digest> {
+        for (digest, data) in &image.blobs {
+            if self.has_blob(name, digest).await? {
+                trace!("Skipping blob {digest}, already present");
+                continue;
+            }
+            self.push_blob(name, digest, data.clone()).await?;
+        }
+        let manifest = &image.manifest;
+        self.put_manifest(
+            name,
+            reference,
+            &manifest.media_type,
+            manifest.bytes.clone(),
+        )
+        .await
+    }
+
+    /// Push an index built with `IndexBuilder`, returning its digest.
+    ///
+    /// The new images are pushed first, by digest, then the index is pushed
+    /// with the given reference.
+    pub async fn push_index(
+        &self,
+        name: &str,
+        reference: &str,
+        index: &BuiltIndex,
+    ) -> Result<Digest> {
+        for image in &index.images {
+            self.push_image(name, image.digest().as_str(), image)
+                .await?;
+        }
+        let manifest = &index.manifest;
+        self.put_manifest(
+            name,
+            reference,
+            &manifest.media_type,
+            manifest.bytes.clone(),
+        )
+        .await
+    }
+
+    /// Describe an image of the repository, for inclusion in an index.
+    ///
+    /// The platform is read from the config blob of the image.
+    pub async fn describe_image(&self, name: &str, reference: &str) -> Result<ManifestObj> {
+        let raw = self.get_manifest_raw(name, reference).await?;
+        match raw.media_type {
+            MediaTypes::ManifestV2S2 | MediaTypes::OciImageManifest => {}
+            other => return Err(Error::UnsupportedMediaType(other)),
+        }
+        let spec: ManifestSchema2Spec = serde_json::from_slice(&raw.bytes)?;
+        let config = self.get_blob(name, &spec.config.digest).await?;
+        let platform: Platform = serde_json::from_slice(&config)?;
+        Ok(ManifestObj {
+            media_type: raw.media_type.to_string(),
+            size: raw.bytes.len() as u64,
+            digest: raw.digest,
+            platform,
+            annotations: None,
+        })
+    }
+
+    /// Assemble an index from images of the repository and push it with a
+    /// tag, returning its digest.
+    ///
+    /// Each image is described with `describe_image`.
+    pub async fn push_manifest_list(
+        &self,
+        name: &str,
+        tag: &str,
+        images: &[&str],
+        format: ManifestFormat,
+    ) -> Result<Digest> {
+        let mut builder = IndexBuilder::default().format(format);
+        for image in images {
+            builder = builder.manifest(self.describe_image(name, image).await?);
+        }
+        self.push_index(name, tag, &builder.build()?).await
+    }
+
+    /// Add an image of the repository to the index with the given tag,
+    /// replacing the image of the same platform, and push the updated
+    /// index, returning its digest.
+    pub async fn update_manifest_list(&self, name: &str, tag: &str, image: &str) -> Result<Digest> {
+        let raw = self.get_manifest_raw(name, tag).await?;
+        match raw.media_type {
+            MediaTypes::ManifestList | MediaTypes::OciImageIndex => {}
+            other => return Err(Error::UnsupportedMediaType(other)),
+        }
+        let mut list: ManifestList = serde_json::from_slice(&raw.bytes)?;
+        list.media_type = raw.media_type.to_string();
+        if let Some(replaced) = list.set_manifest(self.describe_image(name, image).await?) {
+            trace!("Replacing {} in {name}:{tag}", replaced.digest);
+        }
+        let bytes = serde_json::to_vec(&list)?;
+        self.put_manifest(name, tag, &raw.media_type, bytes).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mediatypes::MediaTypes;
+    use test_case::test_case;
+
+    fn config() -> ImageConfig {
+        ImageConfig {
+            architecture: "amd64".to_string(),
+            os: "linux".to_string(),
+            ..ImageConfig::default()
+        }
+    }
+
+    #[test_case(ManifestFormat::Schema2, "application/vnd.docker.image.rootfs.diff.tar", None ; "schema2")]
+    #[test_case(ManifestFormat::Oci, "application/vnd.oci.image.layer.v1.tar", Some("v1") ; "oci")]
+    fn test_build_image(format: ManifestFormat, layer_type: &str, annotation: Option<&str>) {
+        let layer = b"not really a tar archive".to_vec();
+        let image = ImageBuilder::new(config())
+            .format(format)
+            .layer(layer.clone())
+            .unwrap()
+            .annotation("org.opencontainers.image.version", "v1")
+            .build()
+            .unwrap();
+
+        let manifest: serde_json::Value = serde_json::from_slice(&image.manifest().bytes).unwrap();
+        assert_eq!(
+            manifest["mediaType"],
+            format.manifest_media_type().to_string()
+        );
+        assert_eq!(manifest["layers"][0]["mediaType"], layer_type);
+        assert_eq!(
+            manifest["annotations"]["org.opencontainers.image.version"].as_str(),
+            annotation
+        );
+        assert_eq!(
+            image.digest(),
+            &DigestAlgorithm::Sha256.digest(&image.manifest().bytes)
+        );
+
+        let (config_digest, config) = &image.blobs[0];
+        assert_eq!(&image.spec().config.digest, config_digest);
+        let config: ImageConfig = serde_json::from_slice(config).unwrap();
+        assert_eq!(
+            config.rootfs.diff_ids,
+            vec![DigestAlgorithm::Sha256.digest(&layer)]
+        );
+    }
+
+    #[test]
+    fn test_build_index() {
+        let arm = Platform {
+            architecture: "arm".to_string(),
+            os: "linux".to_string(),
+            variant: Some("v7".to_string()),
+            ..Platform::default()
+        };
+        let index = IndexBuilder::default()
+            .image(ImageBuilder::new(config()).build().unwrap())
+            .image(
+                ImageBuilder::new(config())
+                    .platform(arm.clone())
+                    .build()
+                    .unwrap(),
+            )
+            .build()
+            .unwrap();
+
+        assert_eq!(index.manifest().media_type, MediaTypes::ManifestList);
+        let platforms: Vec<_> = index.list().manifests.iter().map(|m| &m.platform).collect();
+        assert_eq!(platforms[0].architecture, "amd64");
+        assert_eq!(platforms[1], &arm);
+        assert_ne!(index.images()[0].digest(), index.images()[1].digest());
+    }
+
+    #[test]
+    fn test_build_with_algorithm() {
+        let layer = b"not really a tar archive".to_vec();
+        let image = ImageBuilder::new(config())
+            .algorithm(DigestAlgorithm::Sha512)
+            .layer(layer.clone())
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(
+            image.digest(),
+            &DigestAlgorithm::Sha512.digest(&image.manifest().bytes)
+        );
+        assert_eq!(
+            image.spec().layers[0].digest,
+            DigestAlgorithm::Sha512.digest(&layer)
+        );
+        let (config_digest, config) = &image.blobs[0];
+        assert_eq!(config_digest, &DigestAlgorithm::Sha512.digest(config));
+
+        let index = IndexBuilder::default()
+            .algorithm(DigestAlgorithm::Sha512)
+            .image(image)
+            .build()
+            .unwrap();
+        assert_eq!(
+            index.digest(),
+            &DigestAlgorithm::Sha512.digest(&index.manifest().bytes)
+        );
+    }
+}
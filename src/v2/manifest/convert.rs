@@ -0,0 +1,142 @@
+//! Conversion between manifest formats.
+//!
+//! Signed schema 1 manifests are converted to schema 2 by
+//! `ManifestSchema1Signed::to_schema2`, and schema 2 manifests to and from
+//! OCI image manifests by `ManifestSchema2Spec::to_oci` and `to_schema2`.
+
+use crate::errors::Result;
+use crate::mediatypes::MediaTypes;
+use crate::v2::{Digest, DigestAlgorithm};
+use libflate::gzip;
+use std::io::{BufRead, BufReader, Read};
+
+/// Error converting a manifest to another format.
+#[derive(Debug, thiserror::Error)]
+pub enum ConversionError {
+    #[error("media type {0} has no equivalent in the target format")]
+    UnsupportedMediaType(String),
+    #[error("manifest has {layers} layers but {history} history entries")]
+    HistoryMismatch { layers: usize, history: usize },
+    #[error("expected {expected} layer diffs, got {got}")]
+    LayerCount { expected: usize, got: usize },
+}
+
+/// Format of image manifests and indexes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ManifestFormat {
+    /// Docker image manifest, version 2 schema 2, and manifest lists.
+    Schema2,
+    /// OCI image manifest and index.
+    Oci,
+}
+
+impl ManifestFormat {
+    /// Media type of image manifests in this format.
+    pub fn manifest_media_type(self) -> MediaTypes {
+        match self {
+            ManifestFormat::Schema2 => MediaTypes::ManifestV2S2,
+            ManifestFormat::Oci => MediaTypes::OciImageManifest,
+        }
+    }
+
+    /// Media type of multi-platform indexes in this format.
+    pub fn index_media_type(self) -> MediaTypes {
+        match self {
+            ManifestFormat::Schema2 => MediaTypes::ManifestList,
+            ManifestFormat::Oci => MediaTypes::OciImageIndex,
+        }
+    }
+
+    /// Media type of config blobs in this format.
+    pub fn config_media_type(self) -> MediaTypes {
+        match self {
+            ManifestFormat::Schema2 => MediaTypes::ContainerConfigV1,
+            ManifestFormat::Oci => MediaTypes::OciImageConfig,
+        }
+    }
+
+    /// Media type of layer blobs in this format, compressed or not.
+    pub fn layer_media_type(self, gzip: bool) -> MediaTypes {
+        match (self, gzip) {
+            (ManifestFormat::Schema2, true) => MediaTypes::ImageLayerTgz,
+            (ManifestFormat::Schema2, false) => MediaTypes::ImageLayerTar,
+            (ManifestFormat::Oci, true) => MediaTypes::OciImageLayerTgz,
+            (ManifestFormat::Oci, false) => MediaTypes::OciImageLayerTar,
+        }
+    }
+}
+
+/// Size and uncompressed digest of a layer blob.
+///
+/// Converting a schema 1 manifest requires these for each of its layers,
+/// as schema 1 does not record them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LayerDiff {
+    /// Size of the layer blob in bytes.
+    pub size: u64,
+    /// Digest of the uncompressed layer, as listed in the image config.
+    pub diff_id: Digest,
+}
+
+impl LayerDiff {
+    /// Compute the size and diff ID of a layer blob, decompressing it if gzipped.
+    pub fn from_blob(blob: &[u8]) -> Result<Self> {
+        Ok(Self {
+            size: blob.len() as u64,
+            diff_id: diff_id(blob)?,
+        })
+    }
+}
+
+/// Compute the diff ID of a layer read from `layer`, decompressing it if gzipped.
+pub(crate) fn diff_id<'a, R: Read + 'a>(layer: R) -> Result<Digest> {
+    let mut layer = BufReader::new(layer);
+    let mut reader: Box<dyn Read + 'a> = if layer.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+        Box::new(gzip::MultiDecoder::new(layer)?)
+    } else {
+        Box::new(layer)
+    };
+
+    let mut hasher = DigestAlgorithm::default().hasher();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher.finalize())
+}
+
+/// Layer media types of schema 2, and their OCI equivalent.
+static LAYER_MEDIA_TYPES: &[(&str, &str)] = &[
+    (
+        "application/vnd.docker.image.rootfs.diff.tar.gzip",
+        "application/vnd.oci.image.layer.v1.tar+gzip",
+    ),
+    (
+        "application/vnd.docker.image.rootfs.diff.tar",
+        "application/vnd.oci.image.layer.v1.tar",
+    ),
+    (
+        "application/vnd.docker.image.rootfs.foreign.diff.tar.gzip",
+        "application/vnd.oci.image.layer.nondistributable.v1.tar+gzip",
+    ),
+    (
+        "application/vnd.docker.container.image.v1+json",
+        "application/vnd.oci.image.config.v1+json",
+    ),
+];
+
+/// Map a config or layer media type between schema 2 and OCI.
+///
+/// Media types already in the target format are kept as-is.
+pub(crate) fn map_media_type(media_type: &str, to_oci: bool) -> Result<String> {
+    LAYER_MEDIA_TYPES
+        .iter()
+        .map(|&(docker, oci)| if to_oci { (docker, oci) } else { (oci, docker) })
+        .find(|&(from, to)| media_type == from || media_type == to)
+        .map(|(_, to)| to.to_string())
+        .ok_or_else(|| ConversionError::UnsupportedMediaType(media_type.to_string()).into())
+}
@@ -0,0 +1,348 @@
+//! Verification of the libtrust JWS signatures of schema 1 manifests.
+
+// Signatures are produced by libtrust, at
+// https://github.com/docker/libtrust/blob/master/jsonsign.go
+//
+// Each signature covers `<protected>.<payload>`, where the payload is the
+// manifest without its `signatures` entry: its first `formatLength` bytes,
+// followed by the base64url-decoded `formatTail`.
+//
+// The key is either embedded as a JWK, or is the key of the first certificate
+// of an `x5c` chain, which like libtrust `Verify` is not checked against roots.
+
+use crate::errors::Result;
+use crate::v2::{Digest, DigestAlgorithm};
+use rsa::pkcs1v15::Pkcs1v15Sign;
+use rsa::pkcs8::{DecodePublicKey, EncodePublicKey};
+use rsa::signature::Verifier;
+use rsa::traits::PublicKeyParts;
+use sha2::{Digest as _, Sha256, Sha384, Sha512};
+
+/// Error verifying the signatures of a schema 1 manifest.
+#[derive(Debug, thiserror::Error)]
+pub enum SignatureError {
+    #[error("manifest has no signatures")]
+    Missing,
+    #[error("format length {0} exceeds the manifest size")]
+    FormatLength(usize),
+    #[error("signature has no embedded JWK or x5c certificate")]
+    MissingKey,
+    #[error("invalid x5c certificate")]
+    InvalidCertificate,
+    #[error("unsupported key type {0}")]
+    UnsupportedKey(String),
+    #[error("unsupported algorithm {alg} for {key} key")]
+    UnsupportedAlgorithm { alg: String, key: String },
+    #[error("invalid {0} key")]
+    InvalidKey(String),
+    #[error("key ID {stated} does not match key {computed}")]
+    KeyIdMismatch { stated: String, computed: String },
+    #[error("invalid signature for key {0}")]
+    Invalid(String),
+}
+
+/// A JSON Web Key, as embedded in the signature headers.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub(crate) struct Jwk {
+    kty: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    kid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    crv: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    x: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    y: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    n: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    e: Option<String>,
+}
+
+/// Unprotected header of a signature.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub(crate) struct SignatureHeader {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) jwk: Option<Jwk>,
+    /// Certificate chain, as base64-encoded DER, the signing one first.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) x5c: Option<Vec<String>>,
+    pub(crate) alg: String,
+}
+
+impl SignatureHeader {
+    /// Key of the signature, embedded or taken from the signing certificate.
+    fn key(&self) -> Result<Jwk> {
+        match (&self.jwk, self.x5c.as_ref().and_then(|chain| chain.first())) {
+            (Some(jwk), _) => Ok(jwk.clone()),
+            (None, Some(cert)) => Jwk::from_certificate(&base64::decode(cert)?),
+            (None, None) => Err(SignatureError::MissingKey.into()),
+        }
+    }
+}
+
+/// A signature of the manifest.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub(crate) struct Signature {
+    pub(crate) header: SignatureHeader,
+    pub(crate) signature: String,
+    pub(crate) protected: String,
+}
+
+#[derive(Deserialize)]
+struct Signatures {
+    signatures: Vec<Signature>,
+}
+
+#[derive(Deserialize)]
+struct Protected {
+    #[serde(rename = "formatLength")]
+    format_length: usize,
+    #[serde(rename = "formatTail")]
+    format_tail: String,
+}
+
+/// Signatures of a schema 1 manifest, verified against its payload.
+#[derive(Clone, Debug)]
+pub struct Schema1Signatures {
+    payload: Vec<u8>,
+    key_ids: Vec<String>,
+}
+
+impl Schema1Signatures {
+    /// Verify all signatures of a signed schema 1 manifest, given its exact bytes.
+    ///
+    /// Keys are taken from the JWK or `x5c` certificate embedded in each
+    /// signature, so this guarantees the integrity of the manifest, not its
+    /// origin; check the key IDs against trusted ones for that.
+    pub fn verify(bytes: &[u8]) -> Result<Self> {
+        let Signatures { signatures } = serde_json::from_slice(bytes)?;
+        let first = signatures.first().ok_or(SignatureError::Missing)?;
+        let payload = signed_payload_for(bytes, first)?;
+        let encoded_payload = base64::encode_config(&payload, base64::URL_SAFE_NO_PAD);
+
+        let key_ids = signatures
+            .iter()
+            .map(|s| {
+                let signed = format!("{}.{}", s.protected, encoded_payload);
+                let signature = decode(&s.signature)?;
+                s.header
+                    .key()?
+                    .verify(&s.header.alg, signed.as_bytes(), &signature)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        trace!("Verified schema1 signatures of keys {key_ids:?}");
+
+        Ok(Self { payload, key_ids })
+    }
+
+    /// IDs of the keys of the signatures, in libtrust format.
+    pub fn key_ids(&self) -> &[String] {
+        &self.key_ids
+    }
+
+    /// The signed payload, which is the manifest without its signatures.
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    /// Digest of the manifest, as computed by registries.
+    pub fn digest(&self, algorithm: DigestAlgorithm) -> Digest {
+        algorithm.digest(&self.payload)
+    }
+}
+
+/// Reconstruct the payload of a signed schema 1 manifest, without verifying it.
+///
+/// This is the manifest without its signatures, which registries use to
+/// compute its digest.
+pub fn signed_payload(bytes: &[u8]) -> Result<Vec<u8>> {
+    let Signatures { signatures } = serde_json::from_slice(bytes)?;
+    let first = signatures.first().ok_or(SignatureError::Missing)?;
+    signed_payload_for(bytes, first)
+}
+
+fn signed_payload_for(bytes: &[u8], signature: &Signature) -> Result<Vec<u8>> {
+    let protected: Protected = serde_json::from_slice(&decode(&signature.protected)?)?;
+    let head = bytes
+        .get(..protected.format_length)
+        .ok_or(SignatureError::FormatLength(protected.format_length))?;
+    let mut payload = head.to_vec();
+    payload.extend(decode(&protected.format_tail)?);
+    Ok(payload)
+}
+
+fn decode(input: &str) -> Result<Vec<u8>> {
+    Ok(base64::decode_config(input, base64::URL_SAFE_NO_PAD)?)
+}
+
+impl Jwk {
+    /// Build the JWK of the public key of a DER-encoded X.509 certificate.
+    fn from_certificate(der: &[u8]) -> Result<Self> {
+        use p256::elliptic_curve::sec1::ToEncodedPoint;
+        use x509_cert::der::{Decode, Encode};
+
+        let spki = x509_cert::Certificate::from_der(der)
+            .and_then(|cert| cert.tbs_certificate.subject_public_key_info.to_der())
+            .map_err(|_| SignatureError::InvalidCertificate)?;
+        let encode = |b: &[u8]| Some(base64::encode_config(b, base64::URL_SAFE_NO_PAD));
+        let ec = |crv: &str, point: &[u8]| {
+            let (x, y) = point[1..].split_at((point.len() - 1) / 2);
+            Self {
+                kty: "EC".to_string(),
+                crv: Some(crv.to_string()),
+                x: encode(x),
+                y: encode(y),
+                ..Default::default()
+            }
+        };
+
+        if let Ok(key) = p256::PublicKey::from_public_key_der(&spki) {
+            Ok(ec("P-256", key.to_encoded_point(false).as_bytes()))
+        } else if let Ok(key) = p384::PublicKey::from_public_key_der(&spki) {
+            Ok(ec("P-384", key.to_encoded_point(false).as_bytes()))
+        } else if let Ok(key) = p521::PublicKey::from_public_key_der(&spki) {
+            Ok(ec("P-521", key.to_encoded_point(false).as_bytes()))
+        } else if let Ok(key) = rsa::RsaPublicKey::from_public_key_der(&spki) {
+            Ok(Self {
+                kty: "RSA".to_string(),
+                n: encode(&key.n().to_bytes_be()),
+                e: encode(&key.e().to_bytes_be()),
+                ..Default::default()
+            })
+        } else {
+            Err(SignatureError::InvalidCertificate.into())
+        }
+    }
+
+    /// Verify a signature, returning the ID of the key.
+    fn verify(&self, alg: &str, signed: &[u8], signature: &[u8]) -> Result<String> {
+        let field = |value: &Option<String>| match value {
+            Some(v) => decode(v),
+            None => Err(SignatureError::InvalidKey(self.kty.clone()).into()),
+        };
+        let unsupported = || SignatureError::UnsupportedAlgorithm {
+            alg: alg.to_string(),
+            key: self.key_type(),
+        };
+
+        let (der, valid) = match (self.kty.as_str(), self.crv.as_deref()) {
+            ("EC", Some("P-256")) if alg == "ES256" => {
+                let key =
+                    p256::PublicKey::from_sec1_bytes(&ec_point(&field(&self.x)?, &field(&self.y)?))
+                        .map_err(|_| self.invalid_key())?;
+                let valid = p256::ecdsa::Signature::from_slice(signature)
+                    .map(|s| {
+                        p256::ecdsa::VerifyingKey::from(&key)
+                            .verify(signed, &s)
+                            .is_ok()
+                    })
+                    .unwrap_or(false);
+                (key.to_public_key_der(), valid)
+            }
+            ("EC", Some("P-384")) if alg == "ES384" => {
+                let key =
+                    p384::PublicKey::from_sec1_bytes(&ec_point(&field(&self.x)?, &field(&self.y)?))
+                        .map_err(|_| self.invalid_key())?;
+                let valid = p384::ecdsa::Signature::from_slice(signature)
+                    .map(|s| {
+                        p384::ecdsa::VerifyingKey::from(&key)
+                            .verify(signed, &s)
+                            .is_ok()
+                    })
+                    .unwrap_or(false);
+                (key.to_public_key_der(), valid)
+            }
+            ("EC", Some("P-521")) if alg == "ES512" => {
+                let point = ec_point(&field(&self.x)?, &field(&self.y)?);
+                let key =
+                    p521::PublicKey::from_sec1_bytes(&point).map_err(|_| self.invalid_key())?;
+                let verifying_key = p521::ecdsa::VerifyingKey::from_sec1_bytes(&point)
+                    .map_err(|_| self.invalid_key())?;
+                let valid = p521::ecdsa::Signature::from_slice(signature)
+                    .map(|s| verifying_key.verify(signed, &s).is_ok())
+                    .unwrap_or(false);
+                (key.to_public_key_der(), valid)
+            }
+            ("EC", Some(_)) => return Err(unsupported().into()),
+            ("RSA", _) => {
+                let n = rsa::BigUint::from_bytes_be(&field(&self.n)?);
+                let e = rsa::BigUint::from_bytes_be(&field(&self.e)?);
+                let key = rsa::RsaPublicKey::new(n, e).map_err(|_| self.invalid_key())?;
+                let (scheme, hashed) = match alg {
+                    "RS256" => (
+                        Pkcs1v15Sign::new::<Sha256>(),
+                        Sha256::digest(signed).to_vec(),
+                    ),
+                    "RS384" => (
+                        Pkcs1v15Sign::new::<Sha384>(),
+                        Sha384::digest(signed).to_vec(),
+                    ),
+                    "RS512" => (
+                        Pkcs1v15Sign::new::<Sha512>(),
+                        Sha512::digest(signed).to_vec(),
+                    ),
+                    _ => return Err(unsupported().into()),
+                };
+                let valid = key.verify(scheme, &hashed, signature).is_ok();
+                (key.to_public_key_der(), valid)
+            }
+            ("EC", None) => return Err(self.invalid_key().into()),
+            _ => return Err(SignatureError::UnsupportedKey(self.key_type()).into()),
+        };
+
+        let der = der.map_err(|_| self.invalid_key())?;
+        let key_id = key_id(der.as_bytes());
+        if let Some(stated) = self.kid.as_ref().filter(|&kid| *kid != key_id) {
+            return Err(SignatureError::KeyIdMismatch {
+                stated: stated.clone(),
+                computed: key_id,
+            }
+            .into());
+        }
+        match valid {
+            true => Ok(key_id),
+            false => Err(SignatureError::Invalid(key_id).into()),
+        }
+    }
+
+    fn key_type(&self) -> String {
+        match &self.crv {
+            Some(crv) => format!("{} {}", self.kty, crv),
+            None => self.kty.clone(),
+        }
+    }
+
+    fn invalid_key(&self) -> SignatureError {
+        SignatureError::InvalidKey(self.key_type())
+    }
+}
+
+/// Uncompressed SEC1 encoding of a curve point.
+fn ec_point(x: &[u8], y: &[u8]) -> Vec<u8> {
+    let mut point = vec![0x04];
+    point.extend_from_slice(x);
+    point.extend_from_slice(y);
+    point
+}
+
+/// libtrust key ID: the base32-encoded first 240 bits of the SHA-256 of the
+/// DER-encoded public key, in groups of 4 characters separated by `:`.
+fn key_id(der: &[u8]) -> String {
+    const ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+    let hash = Sha256::digest(der);
+    let mut encoded = String::new();
+    for chunk in hash[..30].chunks(5) {
+        let bits = chunk.iter().fold(0u64, |acc, &b| (acc << 8) | u64::from(b));
+        for i in (0..8).rev() {
+            encoded.push(ALPHABET[((bits >> (i * 5)) & 0x1f) as usize] as char);
+        }
+    }
+    encoded
+        .as_bytes()
+        .chunks(4)
+        .map(|c| std::str::from_utf8(c).expect("base32 is ASCII"))
+        .collect::<Vec<_>>()
+        .join(":")
+}
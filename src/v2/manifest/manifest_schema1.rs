@@ -1,3 +1,9 @@
+use super::convert::{ConversionError, LayerDiff};
+use super::jws::Signature;
+use super::manifest_schema2::{Config, History, ManifestSchema2Spec};
+use crate::errors::Result;
+use crate::mediatypes::MediaTypes;
+use crate::v2::{Digest, DigestAlgorithm, LayerDescriptor};
 use std::collections::HashMap;
 
 /// Manifest version 2 schema 1, signed.
@@ -16,15 +22,6 @@ pub struct ManifestSchema1Signed {
     signatures: Vec<Signature>,
 }
 
-#[derive(Debug, Default, Deserialize, Serialize)]
-struct Signature {
-    // TODO(lucab): switch to jsonwebtokens crate
-    // https://github.com/Keats/rust-jwt/pull/23
-    header: serde_json::Value,
-    signature: String,
-    protected: String,
-}
-
 /// Compatibility entry for version 1 manifest interoperability.
 #[derive(Debug, Deserialize, Serialize)]
 struct V1Compat {
@@ -32,17 +29,34 @@ struct V1Compat {
     v1_compat: String,
 }
 
+/// Fields of a v1Compatibility entry used for conversion to schema 2.
+#[derive(Debug, Default, Deserialize)]
+struct V1Image {
+    created: Option<String>,
+    author: Option<String>,
+    comment: Option<String>,
+    #[serde(default)]
+    throwaway: bool,
+    container_config: Option<V1ContainerConfig>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct V1ContainerConfig {
+    #[serde(rename = "Cmd")]
+    cmd: Option<Vec<String>>,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 struct S1Layer {
     #[serde(rename = "blobSum")]
-    blob_sum: String,
+    blob_sum: Digest,
 }
 
 impl ManifestSchema1Signed {
     /// List digests of all layers referenced by this manifest.
     ///
     /// The returned layers list is ordered starting with the base image first.
-    pub fn get_layers(&self) -> Vec<String> {
+    pub fn get_layers(&self) -> Vec<Digest> {
         self.fs_layers
             .iter()
             .rev()
@@ -65,4 +79,99 @@ impl ManifestSchema1Signed {
                 .collect(),
         )
     }
+
+    /// List digests of the layers kept when converting to schema 2.
+    ///
+    /// Layers marked as `throwaway` in their history entry are skipped. The
+    /// returned list is ordered starting with the base image first.
+    pub fn non_empty_layers(&self) -> Result<Vec<Digest>> {
+        Ok(self
+            .v1_images()?
+            .iter()
+            .zip(&self.fs_layers)
+            .rev()
+            .filter(|(image, _)| !image.throwaway)
+            .map(|(_, l)| l.blob_sum.clone())
+            .collect())
+    }
+
+    /// Convert to a schema 2 manifest, returning it along with its config blob.
+    ///
+    /// `layers` holds the size and diff ID of each layer listed by
+    /// `non_empty_layers`, in the same order. The config is built from the
+    /// topmost v1Compatibility entry, like the Docker daemon does.
+    pub fn to_schema2(&self, layers: &[LayerDiff]) -> Result<(ManifestSchema2Spec, Vec<u8>)> {
+        let v1_images = self.v1_images()?;
+        let digests = self.non_empty_layers()?;
+        if digests.len() != layers.len() {
+            return Err(ConversionError::LayerCount {
+                expected: digests.len(),
+                got: layers.len(),
+            }
+            .into());
+        }
+
+        let history: Vec<History> = v1_images
+            .into_iter()
+            .rev()
+            .map(|image| History {
+                created: image.created,
+                created_by: image
+                    .container_config
+                    .and_then(|c| c.cmd)
+                    .unwrap_or_default()
+                    .join(" "),
+                author: image.author,
+                comment: image.comment,
+                empty_layer: image.throwaway,
+            })
+            .collect();
+        let diff_ids: Vec<&Digest> = layers.iter().map(|l| &l.diff_id).collect();
+
+        let mut config: serde_json::Map<String, serde_json::Value> = match self.history.first() {
+            Some(top) => serde_json::from_str(&top.v1_compat)?,
+            None => serde_json::Map::new(),
+        };
+        for key in &["id", "parent", "parent_id", "layer_id", "throwaway", "Size"] {
+            config.remove(*key);
+        }
+        config.insert(
+            "rootfs".to_string(),
+            serde_json::json!({ "type": "layers", "diff_ids": diff_ids }),
+        );
+        config.insert("history".to_string(), serde_json::to_value(history)?);
+        let config = serde_json::to_vec(&config)?;
+
+        let config_descriptor = Config {
+            media_type: MediaTypes::ContainerConfigV1.to_string(),
+            size: config.len() as u64,
+            digest: DigestAlgorithm::default().digest(&config),
+        };
+        let layers = digests
+            .into_iter()
+            .zip(layers)
+            .map(|(digest, l)| LayerDescriptor {
+                digest,
+                size: Some(l.size),
+                media_type: Some(MediaTypes::ImageLayerTgz.to_string()),
+            })
+            .collect();
+        let manifest = ManifestSchema2Spec::from_descriptors(config_descriptor, layers);
+        Ok((manifest, config))
+    }
+
+    /// Parse the history entries, ordered starting with the top layer first.
+    fn v1_images(&self) -> Result<Vec<V1Image>> {
+        if self.history.len() != self.fs_layers.len() {
+            return Err(ConversionError::HistoryMismatch {
+                layers: self.fs_layers.len(),
+                history: self.history.len(),
+            }
+            .into());
+        }
+        self.history
+            .iter()
+            .map(|h| Ok(serde_json::from_str(&h.v1_compat)?))
+            .collect()
+    }
 }
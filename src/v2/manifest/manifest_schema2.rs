@@ -1,32 +1,43 @@
-use crate::errors::{Error, Result};
-use reqwest::Method;
+use super::convert::map_media_type;
+use crate::errors::Result;
+use crate::mediatypes::MediaTypes;
+use crate::v2::{Client, Digest, LayerDescriptor};
+use std::collections::BTreeMap;
+use tokio::sync::OnceCell;
 
 /// Manifest version 2 schema 2.
 ///
 /// Specification is at <https://docs.docker.com/registry/spec/manifest-v2-2/>.
-#[derive(Debug, Default, Deserialize, Serialize)]
+/// OCI image manifests share this layout, with optional annotations.
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct ManifestSchema2Spec {
     #[serde(rename = "schemaVersion")]
-    schema_version: u16,
-    #[serde(rename = "mediaType")]
-    media_type: String,
-    config: Config,
-    layers: Vec<S2Layer>,
+    pub schema_version: u16,
+    #[serde(rename = "mediaType", default)]
+    pub media_type: String,
+    pub config: Config,
+    pub layers: Vec<S2Layer>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<BTreeMap<String, String>>,
 }
 
 /// Super-type for combining a ManifestSchema2 with a ConfigBlob.
-#[derive(Debug, Default)]
+///
+/// Manifests fetched with `ManifestOptions::lazy_config` hold a placeholder
+/// config blob instead, which `config` fetches on first use.
+#[derive(Debug)]
 pub struct ManifestSchema2 {
     pub manifest_spec: ManifestSchema2Spec,
     pub config_blob: ConfigBlob,
 }
 
-#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+/// Descriptor of the config blob of an image.
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Config {
     #[serde(rename = "mediaType")]
     pub media_type: String,
     pub size: u64,
-    pub digest: String,
+    pub digest: Digest,
 }
 
 /// Partial representation of a container image (application/vnd.docker.container.image.v1+json).
@@ -37,47 +48,159 @@ pub struct Config {
 #[derive(Debug, Default, Deserialize, Serialize)]
 pub struct ConfigBlob {
     architecture: String,
+    #[serde(skip)]
+    pending: Option<Box<PendingConfig>>,
 }
 
-#[derive(Debug, Default, Deserialize, Serialize)]
-struct S2Layer {
+/// Location of a config blob not fetched yet, and the blob once fetched.
+#[derive(Debug)]
+struct PendingConfig {
+    client: Client,
+    repo: String,
+    digest: Digest,
+    fetched: OnceCell<ConfigBlob>,
+}
+
+impl ConfigBlob {
+    /// Get the architecture of the image.
+    pub fn architecture(&self) -> &str {
+        &self.architecture
+    }
+
+    /// Fetch the config blob with the given digest from a repository.
+    pub(crate) async fn fetch(client: &Client, repo: &str, digest: &Digest) -> Result<Self> {
+        let blob = client.get_blob(repo, digest).await?;
+        Ok(serde_json::from_slice(&blob)?)
+    }
+}
+
+/// Image configuration, as stored in the config blob of new images.
+///
+/// Covers the fields shared by the Docker and OCI image specs.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ImageConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    pub architecture: String,
+    pub os: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub variant: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub config: Option<RuntimeConfig>,
+    pub rootfs: RootFs,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub history: Vec<History>,
+}
+
+/// Execution parameters of containers run from an image.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct RuntimeConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exposed_ports: Option<BTreeMap<String, serde_json::Value>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub env: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entrypoint: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cmd: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub volumes: Option<BTreeMap<String, serde_json::Value>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub working_dir: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub labels: Option<BTreeMap<String, String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_signal: Option<String>,
+}
+
+/// Layers of the root filesystem of an image, by uncompressed digest.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RootFs {
+    #[serde(rename = "type")]
+    pub fs_type: String,
+    pub diff_ids: Vec<Digest>,
+}
+
+impl Default for RootFs {
+    fn default() -> Self {
+        Self {
+            fs_type: "layers".to_string(),
+            diff_ids: Vec::new(),
+        }
+    }
+}
+
+/// History entry of an image config.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct History {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created: Option<String>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub created_by: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub empty_layer: bool,
+}
+
+/// Descriptor of a layer blob of an image.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct S2Layer {
     #[serde(rename = "mediaType")]
-    media_type: String,
-    size: u64,
-    digest: String,
-    urls: Option<Vec<String>>,
+    pub media_type: String,
+    pub size: u64,
+    pub digest: Digest,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub urls: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<BTreeMap<String, String>>,
 }
 
 /// Manifest List.
-#[derive(Debug, Default, Deserialize, Serialize)]
+///
+/// OCI image indexes share this layout, with optional annotations.
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct ManifestList {
     #[serde(rename = "schemaVersion")]
-    schema_version: u16,
-    #[serde(rename = "mediaType")]
-    media_type: String,
+    pub schema_version: u16,
+    #[serde(rename = "mediaType", default)]
+    pub media_type: String,
     pub manifests: Vec<ManifestObj>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<BTreeMap<String, String>>,
 }
 
 /// Manifest object.
-#[derive(Debug, Default, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct ManifestObj {
     #[serde(rename = "mediaType")]
-    media_type: String,
-    size: u64,
-    pub digest: String,
+    pub media_type: String,
+    pub size: u64,
+    pub digest: Digest,
     pub platform: Platform,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<BTreeMap<String, String>>,
 }
 
 /// Platform-related manifest entries.
-#[derive(Debug, Default, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
 pub struct Platform {
     pub architecture: String,
     pub os: String,
-    #[serde(rename = "os.version")]
+    #[serde(rename = "os.version", skip_serializing_if = "Option::is_none")]
     pub os_version: Option<String>,
-    #[serde(rename = "os.features")]
+    #[serde(rename = "os.features", skip_serializing_if = "Option::is_none")]
     pub os_features: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub variant: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub features: Option<Vec<String>>,
 }
 
@@ -87,48 +210,134 @@ impl ManifestSchema2Spec {
         &self.config
     }
 
-    /// Fetch the config blob for this manifest
-    pub(crate) async fn fetch_config_blob(
-        self,
-        client: crate::v2::Client,
-        repo: String,
-    ) -> Result<ManifestSchema2> {
-        let url = {
-            let ep = format!(
-                "{}/v2/{}/blobs/{}",
-                client.base_url.clone(),
-                repo,
-                self.config.digest
-            );
-            reqwest::Url::parse(&ep)?
-        };
+    /// Get the media type of this manifest.
+    pub fn media_type(&self) -> &str {
+        &self.media_type
+    }
 
-        let r = client
-            .build_reqwest(Method::GET, url.clone())
-            .send()
-            .await?;
+    /// Convert to an OCI image manifest.
+    ///
+    /// Config and layer media types are mapped to their OCI equivalent;
+    /// an OCI manifest is returned unchanged.
+    pub fn to_oci(&self) -> Result<Self> {
+        self.convert(MediaTypes::OciImageManifest, true)
+    }
 
-        let status = r.status();
-        trace!("GET {:?}: {}", url, &status);
+    /// Convert to a Docker schema 2 manifest.
+    ///
+    /// Annotations, which schema 2 does not support, are dropped.
+    pub fn to_schema2(&self) -> Result<Self> {
+        let mut manifest = self.convert(MediaTypes::ManifestV2S2, false)?;
+        manifest.annotations = None;
+        manifest
+            .layers
+            .iter_mut()
+            .for_each(|l| l.annotations = None);
+        Ok(manifest)
+    }
 
-        if !status.is_success() {
-            return Err(Error::UnexpectedHttpStatus(status));
+    /// Build a schema 2 manifest from its config and layer descriptors.
+    pub(super) fn from_descriptors(config: Config, layers: Vec<LayerDescriptor>) -> Self {
+        let layers = layers
+            .into_iter()
+            .map(|l| S2Layer {
+                media_type: l
+                    .media_type
+                    .unwrap_or_else(|| MediaTypes::ImageLayerTgz.to_string()),
+                size: l.size.unwrap_or_default(),
+                digest: l.digest,
+                urls: None,
+                annotations: None,
+            })
+            .collect();
+        Self {
+            schema_version: 2,
+            media_type: MediaTypes::ManifestV2S2.to_string(),
+            config,
+            layers,
+            annotations: None,
         }
+    }
 
-        let config_blob = r.json::<ConfigBlob>().await?;
+    fn convert(&self, media_type: MediaTypes, to_oci: bool) -> Result<Self> {
+        let mut manifest = self.clone();
+        manifest.media_type = media_type.to_string();
+        manifest.config.media_type = map_media_type(&self.config.media_type, to_oci)?;
+        for layer in manifest.layers.iter_mut() {
+            layer.media_type = map_media_type(&layer.media_type, to_oci)?;
+        }
+        Ok(manifest)
+    }
 
-        Ok(ManifestSchema2 {
-            manifest_spec: self,
-            config_blob,
-        })
+    /// List descriptors of all layers referenced by this manifest.
+    ///
+    /// The returned layers list is ordered starting with the base image first.
+    pub(crate) fn layer_descriptors(&self) -> Vec<LayerDescriptor> {
+        self.layers
+            .iter()
+            .map(|l| LayerDescriptor {
+                digest: l.digest.clone(),
+                size: Some(l.size),
+                media_type: Some(l.media_type.clone()),
+            })
+            .collect()
     }
 }
 
 impl ManifestSchema2 {
+    /// Combine a manifest with its config blob.
+    pub fn new(manifest_spec: ManifestSchema2Spec, config_blob: ConfigBlob) -> Self {
+        Self {
+            manifest_spec,
+            config_blob,
+        }
+    }
+
+    /// Wrap a manifest whose config blob is fetched from a repository on first use.
+    pub(crate) fn lazy(manifest_spec: ManifestSchema2Spec, client: Client, repo: String) -> Self {
+        let pending = PendingConfig {
+            client,
+            repo,
+            digest: manifest_spec.config.digest.clone(),
+            fetched: OnceCell::new(),
+        };
+        Self {
+            manifest_spec,
+            config_blob: ConfigBlob {
+                pending: Some(Box::new(pending)),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Get the config blob, fetching it first if the manifest was fetched
+    /// with `ManifestOptions::lazy_config`.
+    pub async fn config(&self) -> Result<&ConfigBlob> {
+        match &self.config_blob.pending {
+            None => Ok(&self.config_blob),
+            Some(pending) => {
+                pending
+                    .fetched
+                    .get_or_try_init(|| {
+                        ConfigBlob::fetch(&pending.client, &pending.repo, &pending.digest)
+                    })
+                    .await
+            }
+        }
+    }
+
+    /// Get the config blob, unless it is yet to be fetched by `config`.
+    pub fn fetched_config(&self) -> Option<&ConfigBlob> {
+        match &self.config_blob.pending {
+            None => Some(&self.config_blob),
+            Some(pending) => pending.fetched.get(),
+        }
+    }
+
     /// List digests of all layers referenced by this manifest.
     ///
     /// The returned layers list is ordered starting with the base image first.
-    pub fn get_layers(&self) -> Vec<String> {
+    pub fn get_layers(&self) -> Vec<Digest> {
         self.manifest_spec
             .layers
             .iter()
@@ -136,9 +345,20 @@ impl ManifestSchema2 {
             .collect()
     }
 
-    /// Get the architecture from the config
+    /// List descriptors of all layers referenced by this manifest.
+    ///
+    /// The returned layers list is ordered starting with the base image first.
+    pub fn get_layer_descriptors(&self) -> Vec<LayerDescriptor> {
+        self.manifest_spec.layer_descriptors()
+    }
+
+    /// Get the architecture from the config.
+    ///
+    /// This is empty for a config yet to be fetched, see `config`.
     pub fn architecture(&self) -> String {
-        self.config_blob.architecture.to_owned()
+        self.fetched_config()
+            .map(|c| c.architecture.to_owned())
+            .unwrap_or_default()
     }
 }
 
@@ -149,7 +369,7 @@ impl ManifestObj {
     }
 
     /// Returns the sha digest of the manifest object
-    pub fn digest(&self) -> String {
+    pub fn digest(&self) -> Digest {
         self.digest.to_owned()
     }
 }
@@ -161,7 +381,44 @@ impl ManifestList {
     }
 
     /// Get the digest for all the manifest images in the ManifestList
-    pub fn get_digests(&self) -> Vec<String> {
+    pub fn get_digests(&self) -> Vec<Digest> {
         self.manifests.iter().map(|mo| mo.digest()).collect()
     }
+
+    /// Find the manifest for a platform.
+    ///
+    /// Without a variant, the first manifest matching the architecture and OS
+    /// is returned, whatever its variant.
+    pub fn find_platform(
+        &self,
+        architecture: &str,
+        os: &str,
+        variant: Option<&str>,
+    ) -> Option<&ManifestObj> {
+        self.manifests.iter().find(|m| {
+            m.platform.architecture == architecture
+                && m.platform.os == os
+                && variant.is_none_or(|v| m.platform.variant.as_deref() == Some(v))
+        })
+    }
+
+    /// Add a manifest to the list, replacing the entry for the same platform.
+    ///
+    /// Platforms match on architecture, OS and variant. The replaced entry
+    /// is returned, if any.
+    pub fn set_manifest(&mut self, manifest: ManifestObj) -> Option<ManifestObj> {
+        let key = |p: &Platform| (p.architecture.clone(), p.os.clone(), p.variant.clone());
+        let platform = key(&manifest.platform);
+        match self
+            .manifests
+            .iter_mut()
+            .find(|m| key(&m.platform) == platform)
+        {
+            Some(existing) => Some(std::mem::replace(existing, manifest)),
+            None => {
+                self.manifests.push(manifest);
+                None
+            }
+        }
+    }
 }
@@ -8,11 +8,22 @@ use std::str::FromStr;
 mod manifest_schema1;
 pub use self::manifest_schema1::*;
 
+mod jws;
+pub use self::jws::{signed_payload, Schema1Signatures, SignatureError};
+
+mod convert;
+pub(crate) use self::convert::diff_id;
+pub use self::convert::{ConversionError, LayerDiff, ManifestFormat};
+
 mod manifest_schema2;
 pub use self::manifest_schema2::{
-    ConfigBlob, ManifestList, ManifestObj, ManifestSchema2, ManifestSchema2Spec, Platform,
+    Config, ConfigBlob, History, ImageConfig, ManifestList, ManifestObj, ManifestSchema2,
+    ManifestSchema2Spec, Platform, RootFs, RuntimeConfig, S2Layer,
 };
 
+mod builder;
+pub use self::builder::{BuiltImage, BuiltIndex, ImageBuilder, IndexBuilder};
+
 impl Client {
     /// Fetch an image manifest.
     ///
@@ -28,17 +39,102 @@ impl Client {
     ///
     /// The name and reference parameters identify the image.
     /// The reference may be either a tag or digest.
+    ///
+    /// The digest is the reference if it is a digest, or the one sent by the
+    /// registry in the `Docker-Content-Digest` header, and `None` without
+    /// either. `get_manifest_and_raw` always computes it from the content.
     pub async fn get_manifest_and_ref(
         &self,
         name: &str,
         reference: &str,
-    ) -> Result<(Manifest, Option<String>)> {
+    ) -> Result<(Manifest, Option<Digest>)> {
+        let (raw, stated_digest) = self.fetch_manifest(name, reference).await?;
+        let manifest = self
+            .parse_manifest(
+                name,
+                raw.media_type.clone(),
+                &raw.bytes,
+                &ManifestOptions::default(),
+            )
+            .await?;
+        Ok((manifest, stated_digest))
+    }
+
+    /// Fetch an image manifest, along with its exact bytes.
+    ///
+    /// The name and reference parameters identify the image.
+    /// The reference may be either a tag or digest.
+    pub async fn get_manifest_and_raw(
+        &self,
+        name: &str,
+        reference: &str,
+    ) -> Result<(Manifest, RawManifest)> {
+        self.get_manifest_with(name, reference, &ManifestOptions::default())
+            .await
+    }
+
+    /// Fetch an image manifest with the given options, along with its exact bytes.
+    ///
+    /// The name and reference parameters identify the image.
+    /// The reference may be either a tag or digest.
+    pub async fn get_manifest_with(
+        &self,
+        name: &str,
+        reference: &str,
+        options: &ManifestOptions,
+    ) -> Result<(Manifest, RawManifest)> {
+        let raw = self.get_manifest_raw(name, reference).await?;
+        let manifest = self
+            .parse_manifest(name, raw.media_type.clone(), &raw.bytes, options)
+            .await?;
+        Ok((manifest, raw))
+    }
+
+    /// Fetch the exact bytes of an image manifest, without parsing them.
+    ///
+    /// The name and reference parameters identify the image.
+    /// The reference may be either a tag or digest. The content is verified
+    /// against the reference if it is a digest, and against the
+    /// `Docker-Content-Digest` header if the registry sent one.
+    pub async fn get_manifest_raw(&self, name: &str, reference: &str) -> Result<RawManifest> {
+        self.fetch_manifest(name, reference)
+            .await
+            .map(|(raw, _)| raw)
+    }
+
+    /// Fetch and verify a manifest, along with the digest stated by the
+    /// reference or the `Docker-Content-Digest` header, if any.
+    async fn fetch_manifest(
+        &self,
+        name: &str,
+        reference: &str,
+    ) -> Result<(RawManifest, Option<Digest>)> {
+        // Manifests fetched by digest are immutable, and can be cached.
+        let by_digest = reference.parse::<Digest>().ok();
+        if let (Some(digest), Some(cache)) = (&by_digest, &self.cache) {
+            if let Some((media_type, bytes)) = cache.get_manifest(digest).await? {
+                let media_type = mediatypes::MediaTypes::from_str(&media_type)?;
+                match verify_manifest(&media_type, &bytes, Some(digest)) {
+                    Ok(_) => {
+                        let raw = RawManifest {
+                            bytes,
+                            media_type,
+                            digest: digest.clone(),
+                        };
+                        return Ok((raw, by_digest));
+                    }
+                    Err(e) => {
+                        warn!("removing corrupted cache entry for manifest {digest}: {e}");
+                        cache.remove(digest).await?;
+                    }
+                }
+            }
+        }
+
         let url = self.build_url(name, reference)?;
 
         let accept_headers = build_accept_headers(&self.accepted_types);
 
-        let client_spare0 = self.clone();
-
         let res = self
             .build_reqwest(Method::GET, url.clone())
             .headers(accept_headers)
@@ -50,12 +146,12 @@ impl Client {
 
         match status {
             StatusCode::OK => {}
-            _ => return Err(Error::UnexpectedHttpStatus(status)),
+            _ => return Err(error_from_response(Method::GET, res).await),
         }
 
         let headers = res.headers();
-        let content_digest = match headers.get("docker-content-digest") {
-            Some(content_digest_value) => Some(content_digest_value.to_str()?.to_string()),
+        let header_digest: Option<Digest> = match headers.get("docker-content-digest") {
+            Some(content_digest_value) => Some(content_digest_value.to_str()?.parse()?),
             None => {
                 debug!("cannot find manifestref in headers");
                 None
@@ -67,31 +163,96 @@ impl Client {
 
         trace!("content-type: {header_content_type:?}, media-type: {media_type:?}");
 
+        let bytes = res.bytes().await?.to_vec();
+        let digest = verify_manifest(
+            &media_type,
+            &bytes,
+            by_digest.iter().chain(header_digest.iter()),
+        )?;
+
+        if let (Some(digest), Some(cache)) = (&by_digest, &self.cache) {
+            if let Err(e) = cache
+                .insert_manifest(digest, &media_type.to_string(), &bytes)
+                .await
+            {
+                warn!("failed to cache manifest {digest}: {e}");
+            }
+        }
+
+        let raw = RawManifest {
+            bytes,
+            media_type,
+            digest,
+        };
+        Ok((raw, by_digest.or(header_digest)))
+    }
+
+    /// Parse a manifest body of the given media type.
+    async fn parse_manifest(
+        &self,
+        name: &str,
+        media_type: mediatypes::MediaTypes,
+        body: &[u8],
+        options: &ManifestOptions,
+    ) -> Result<Manifest> {
         match media_type {
-            mediatypes::MediaTypes::ManifestV2S1Signed => Ok((
-                res.json::<ManifestSchema1Signed>()
-                    .await
-                    .map(Manifest::S1Signed)?,
-                content_digest,
-            )),
-            mediatypes::MediaTypes::ManifestV2S2 => {
-                let m = res.json::<ManifestSchema2Spec>().await?;
-                Ok((
-                    m.fetch_config_blob(client_spare0, name.to_string())
-                        .await
-                        .map(Manifest::S2)?,
-                    content_digest,
-                ))
+            mediatypes::MediaTypes::ManifestV2S1Signed => Ok(serde_json::from_slice::<
+                ManifestSchema1Signed,
+            >(body)
+            .map(Manifest::S1Signed)?),
+            mediatypes::MediaTypes::ManifestV2S2 | mediatypes::MediaTypes::OciImageManifest => {
+                let m = serde_json::from_slice::<ManifestSchema2Spec>(body)?;
+                let m = if options.lazy_config {
+                    ManifestSchema2::lazy(m, self.clone(), name.to_string())
+                } else {
+                    let config_blob = ConfigBlob::fetch(self, name, &m.config.digest).await?;
+                    ManifestSchema2::new(m, config_blob)
+                };
+                Ok(Manifest::S2(m))
+            }
+            mediatypes::MediaTypes::ManifestList | mediatypes::MediaTypes::OciImageIndex => {
+                Ok(serde_json::from_slice::<ManifestList>(body).map(Manifest::ML)?)
             }
-            mediatypes::MediaTypes::ManifestList => Ok((
-                res.json::<ManifestList>().await.map(Manifest::ML)?,
-                content_digest,
-            )),
             unsupported => Err(Error::UnsupportedMediaType(unsupported)),
         }
     }
 
-    fn build_url(&self, name: &str, reference: &str) -> Result<Url> {
+    /// Upload a manifest, returning its digest.
+    ///
+    /// The reference may be either a tag or digest. The bytes are sent as-is,
+    /// with the given media type as `Content-Type`.
+    pub async fn put_manifest(
+        &self,
+        name: &str,
+        reference: &str,
+        media_type: &mediatypes::MediaTypes,
+        bytes: Vec<u8>,
+    ) -> Result<Digest> {
+        let url = self.build_url(name, reference)?;
+        let algorithm = match reference.parse::<Digest>() {
+            Ok(digest) => digest.algorithm(),
+            Err(_) => DigestAlgorithm::default(),
+        };
+        let digest = manifest_digest(media_type, &bytes, algorithm)?;
+        let content_type = header::HeaderValue::from_str(&media_type.to_string())
+            .expect("media types are always valid header values");
+
+        let res = self
+            .build_reqwest(Method::PUT, url)
+            .header(header::CONTENT_TYPE, content_type)
+            .body(bytes)
+            .send()
+            .await?;
+
+        let status = res.status();
+        trace!("PUT '{}' status: {:?}", res.url(), status);
+        match status {
+            StatusCode::CREATED => Ok(digest),
+            _ => Err(error_from_response(Method::PUT, res).await),
+        }
+    }
+
+    pub(crate) fn build_url(&self, name: &str, reference: &str) -> Result<Url> {
         let ep = format!(
             "{}/v2/{}/manifests/{}",
             self.base_url.clone(),
@@ -102,7 +263,7 @@ impl Client {
     }
 
     /// Fetch content digest for a particular tag.
-    pub async fn get_manifestref(&self, name: &str, reference: &str) -> Result<Option<String>> {
+    pub async fn get_manifestref(&self, name: &str, reference: &str) -> Result<Option<Digest>> {
         let url = self.build_url(name, reference)?;
 
         let accept_headers = build_accept_headers(&self.accepted_types);
@@ -118,12 +279,12 @@ impl Client {
 
         match status {
             StatusCode::OK => {}
-            _ => return Err(Error::UnexpectedHttpStatus(status)),
+            _ => return Err(error_from_response(Method::HEAD, res).await),
         }
 
         let headers = res.headers();
         let content_digest = match headers.get("docker-content-digest") {
-            Some(content_digest_value) => Some(content_digest_value.to_str()?.to_string()),
+            Some(content_digest_value) => Some(content_digest_value.to_str()?.parse()?),
             None => {
                 debug!("cannot find manifestref in headers");
                 None
@@ -164,8 +325,7 @@ impl Client {
             .build_reqwest(Method::HEAD, url.clone())
             .headers(accept_headers)
             .send()
-            .await
-            .map_err(Error::from)?;
+            .await?;
 
         let status = r.status();
 
@@ -186,7 +346,7 @@ impl Client {
                 Ok(Some(media_type))
             }
             StatusCode::NOT_FOUND => Ok(None),
-            _ => Err(Error::UnexpectedHttpStatus(status)),
+            _ => Err(error_from_response(Method::HEAD, r).await),
         }
     }
 }
@@ -205,6 +365,49 @@ fn to_mimes(v: &[&str]) -> Vec<mime::Mime> {
     res
 }
 
+/// Compute the digest of a manifest, as registries do.
+///
+/// The digest of a signed schema 1 manifest excludes its signatures.
+pub(crate) fn manifest_digest(
+    media_type: &mediatypes::MediaTypes,
+    bytes: &[u8],
+    algorithm: DigestAlgorithm,
+) -> Result<Digest> {
+    match media_type {
+        mediatypes::MediaTypes::ManifestV2S1Signed => Ok(algorithm.digest(&signed_payload(bytes)?)),
+        _ => Ok(algorithm.digest(bytes)),
+    }
+}
+
+/// Verify a manifest against the expected digests, returning its digest.
+///
+/// Schema1 digests cover the payload without its signatures, which are
+/// verified beforehand. Without expected digests, the digest is computed
+/// with the default algorithm.
+fn verify_manifest<'a, I>(
+    media_type: &mediatypes::MediaTypes,
+    bytes: &[u8],
+    expected: I,
+) -> Result<Digest>
+where
+    I: IntoIterator<Item = &'a Digest>,
+{
+    let signed = match media_type {
+        mediatypes::MediaTypes::ManifestV2S1Signed => Some(Schema1Signatures::verify(bytes)?),
+        _ => None,
+    };
+    let payload = signed.as_ref().map_or(bytes, |s| s.payload());
+
+    let mut verified = None;
+    for expected in expected {
+        let mut content_digest = ContentDigest::new(expected.clone());
+        content_digest.update(payload);
+        content_digest.verify()?;
+        verified.get_or_insert_with(|| expected.clone());
+    }
+    Ok(verified.unwrap_or_else(|| DigestAlgorithm::default().digest(payload)))
+}
+
 // Evaluate the `MediaTypes` from the the request header.
 fn evaluate_media_type(
     content_type: Option<&reqwest::header::HeaderValue>,
@@ -247,7 +450,9 @@ fn evaluate_media_type(
     }
 }
 
-fn build_accept_headers(accepted_types: &[(MediaTypes, Option<f64>)]) -> header::HeaderMap {
+pub(crate) fn build_accept_headers(
+    accepted_types: &[(MediaTypes, Option<f64>)],
+) -> header::HeaderMap {
     let accepted_types_string = accepted_types
         .iter()
         .map(|(ty, q)| {
@@ -271,6 +476,43 @@ fn build_accept_headers(accepted_types: &[(MediaTypes, Option<f64>)]) -> header:
     )])
 }
 
+/// Options for `Client::get_manifest_with`.
+#[derive(Clone, Debug, Default)]
+pub struct ManifestOptions {
+    lazy_config: bool,
+}
+
+impl ManifestOptions {
+    /// Whether to defer fetching the config blob of schema 2 manifests
+    /// until `ManifestSchema2::config` is called (defaults to `false`).
+    ///
+    /// This saves a request per manifest when only layers or digests are
+    /// needed; the architecture is then unknown until the config is fetched,
+    /// e.g. by `Manifest::fetch_layers_digests`.
+    pub fn lazy_config(mut self, lazy: bool) -> Self {
+        self.lazy_config = lazy;
+        self
+    }
+}
+
+/// A manifest as served by the registry.
+///
+/// The bytes are kept exactly as received, so that the manifest can be
+/// pushed again or signed without changing its digest.
+#[derive(Clone, Debug)]
+pub struct RawManifest {
+    /// Content of the manifest.
+    pub bytes: Vec<u8>,
+    /// Media type of the manifest.
+    pub media_type: mediatypes::MediaTypes,
+    /// Digest of the manifest.
+    ///
+    /// This is the requested digest or the one reported by the registry.
+    /// Otherwise it is computed from the content, excluding the signatures
+    /// of schema 1 manifests so that it matches the registry digest.
+    pub digest: Digest,
+}
+
 /// Umbrella type for common actions on the different manifest schema types
 #[derive(Debug)]
 pub enum Manifest {
@@ -289,6 +531,10 @@ pub enum ManifestError {
     LayerDigestsUnsupported(String),
     #[error("manifest {0} does not support the 'architecture' method")]
     ArchitectureNotSupported(String),
+    #[error("manifest {0} does not support the 'layer_descriptors' method")]
+    LayerDescriptorsUnsupported(String),
+    #[error("config blob of the manifest has not been fetched, see ManifestSchema2::config")]
+    ConfigUnavailable,
 }
 
 impl Manifest {
@@ -307,7 +553,7 @@ impl Manifest {
     /// the individual image to get the layers.
     ///
     /// The returned layers list for non ManifestList images is ordered starting with the base image first.
-    pub fn layers_digests(&self, architecture: Option<&str>) -> Result<Vec<String>> {
+    pub fn layers_digests(&self, architecture: Option<&str>) -> Result<Vec<Digest>> {
         match (self, self.architectures(), architecture) {
             (Manifest::S1Signed(m), _, None) => Ok(m.get_layers()),
             (Manifest::S2(m), _, None) => Ok(m.get_layers()),
@@ -330,7 +576,43 @@ impl Manifest {
                 Ok(m.get_layers())
             }
             (Manifest::ML(m), _, _) => Ok(m.get_digests()),
-            _ => Err(ManifestError::LayerDigestsUnsupported(format!("{self:?}")).into()),
+            (_, Err(e), Some(_)) => Err(e),
+        }
+    }
+
+    /// List digests of all layers referenced by this manifest, like `layers_digests`.
+    ///
+    /// The config blob of schema 2 manifests fetched with
+    /// `ManifestOptions::lazy_config` is fetched first if needed to check
+    /// the architecture, where `layers_digests` fails with `ConfigUnavailable`.
+    pub async fn fetch_layers_digests(&self, architecture: Option<&str>) -> Result<Vec<Digest>> {
+        if let (Manifest::S2(m), Some(_)) = (self, architecture) {
+            m.config().await?;
+        }
+        self.layers_digests(architecture)
+    }
+
+    /// List descriptors of all layers referenced by this manifest, if available.
+    ///
+    /// Schema 1 manifests do not carry layer sizes and media types.
+    /// Manifest lists do not reference layers and are not supported.
+    ///
+    /// The returned layers list is ordered starting with the base image first.
+    pub fn layer_descriptors(&self) -> Result<Vec<LayerDescriptor>> {
+        match self {
+            Manifest::S1Signed(m) => Ok(m
+                .get_layers()
+                .into_iter()
+                .map(|digest| LayerDescriptor {
+                    digest,
+                    size: None,
+                    media_type: None,
+                })
+                .collect()),
+            Manifest::S2(m) => Ok(m.get_layer_descriptors()),
+            Manifest::ML(_) => {
+                Err(ManifestError::LayerDescriptorsUnsupported(format!("{self:?}")).into())
+            }
         }
     }
 
@@ -338,7 +620,10 @@ impl Manifest {
     pub fn architectures(&self) -> Result<Vec<String>> {
         match self {
             Manifest::S1Signed(m) => Ok([m.architecture.clone()].to_vec()),
-            Manifest::S2(m) => Ok([m.architecture()].to_vec()),
+            Manifest::S2(m) => {
+                let config = m.fetched_config().ok_or(ManifestError::ConfigUnavailable)?;
+                Ok([config.architecture().to_string()].to_vec())
+            }
             Manifest::ML(m) => Ok(m.architectures()),
         }
     }
@@ -0,0 +1,63 @@
+//! Hooks around every request sent to a registry.
+
+use crate::errors::Error;
+use reqwest::{Method, Url};
+use std::fmt;
+use std::time::Duration;
+
+/// Interceptor applied to every HTTP exchange of a `Client`.
+///
+/// This covers all registry calls (authentication, catalog, tags,
+/// manifests and blobs), including each individual retry attempt and
+/// redirect hop. Middlewares run in the order they were registered for
+/// requests, and in reverse order for responses and errors.
+///
+/// ```rust
+/// # extern crate dkregistry;
+/// # extern crate reqwest;
+/// use dkregistry::v2::{Client, Middleware};
+/// use std::sync::Arc;
+/// use std::time::Duration;
+///
+/// #[derive(Debug)]
+/// struct Tracing;
+///
+/// impl Middleware for Tracing {
+///     fn on_request(&self, request: &mut reqwest::Request) -> Option<reqwest::Response> {
+///         request
+///             .headers_mut()
+///             .insert("traceparent", "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01".parse().unwrap());
+///         None
+///     }
+///
+///     fn on_response(&self, method: &reqwest::Method, url: &reqwest::Url, response: &reqwest::Response, elapsed: Duration) {
+///         println!("{} {} -> {} in {:?}", method, url, response.status(), elapsed);
+///     }
+/// }
+///
+/// let client = Client::configure().middleware(Arc::new(Tracing));
+/// ```
+pub trait Middleware: fmt::Debug + Send + Sync {
+    /// Inspect or modify a request before it is sent.
+    ///
+    /// Returning a response short-circuits the exchange: the request is not
+    /// sent, and the given response is used instead (e.g. a canned or cached
+    /// one, converted from an `http::Response`). Middlewares registered after
+    /// this one do not see the request.
+    fn on_request(&self, _request: &mut reqwest::Request) -> Option<reqwest::Response> {
+        None
+    }
+
+    /// Observe a response, along with the time elapsed since it was requested.
+    fn on_response(
+        &self,
+        _method: &Method,
+        _url: &Url,
+        _response: &reqwest::Response,
+        _elapsed: Duration,
+    ) {
+    }
+
+    /// Observe a request which failed without a response.
+    fn on_error(&self, _method: &Method, _url: &Url, _error: &Error, _elapsed: Duration) {}
+}
@@ -44,11 +44,49 @@ pub mod manifest;
 
 mod tags;
 
+mod pull;
+pub(crate) use self::pull::default_architecture;
+pub use self::pull::{
+    DirectorySink, LayerDescriptor, LayerSink, PullOptions, PullSummary, PulledLayer,
+};
+
 mod blobs;
 
+mod reference;
+pub use self::reference::ReferenceOptions;
+
+mod progress;
+pub use self::progress::{
+    ImageProgress, ImageProgressSnapshot, ProgressEvent, ProgressObserver, TransferDirection,
+};
+
+mod api_errors;
+pub(crate) use self::api_errors::error_from_response;
+pub use self::api_errors::{ApiError, ErrorCode, RegistryError};
+
+mod request;
+pub(crate) use self::request::RequestBuilder;
+
+mod middleware;
+pub use self::middleware::Middleware;
+
+mod transport;
+pub use self::transport::{ReqwestTransport, Transport};
+
+mod retry;
+pub use self::retry::{RetryPolicy, RetryableError};
+
+mod ratelimit;
+pub use self::ratelimit::{RateLimit, RateLimitQuota};
+
+mod cache;
+pub use self::cache::BlobCache;
+
+pub(crate) mod partial;
+
 mod content_digest;
 pub(crate) use self::content_digest::ContentDigest;
-pub use self::content_digest::ContentDigestError;
+pub use self::content_digest::{ContentDigestError, Digest, DigestAlgorithm, DigestHasher};
 
 /// A Client to make outgoing API requests to a registry.
 #[derive(Clone, Debug)]
@@ -58,7 +96,12 @@ pub struct Client {
     user_agent: Option<String>,
     auth: Option<auth::Auth>,
     client: reqwest::Client,
+    transport: std::sync::Arc<dyn Transport>,
     accepted_types: Vec<(MediaTypes, Option<f64>)>,
+    retry_policy: RetryPolicy,
+    middlewares: Vec<std::sync::Arc<dyn Middleware>>,
+    cache: Option<BlobCache>,
+    rate_limits: ratelimit::RateLimits,
 }
 
 impl Client {
@@ -79,7 +122,7 @@ impl Client {
     pub async fn is_v2_supported(&self) -> Result<bool> {
         match self.is_v2_supported_and_authorized().await {
             Ok((v2_supported, _)) => Ok(v2_supported),
-            Err(crate::Error::UnexpectedHttpStatus(_)) => Ok(false),
+            Err(crate::Error::Registry(_)) => Ok(false),
             Err(e) => Err(e),
         }
     }
@@ -104,15 +147,19 @@ impl Client {
             (StatusCode::UNAUTHORIZED, Some(x)) => Ok((x == api_version, false)),
             (s, v) => {
                 trace!("Got unexpected status {s}, header version {v:?}");
-                return Err(crate::Error::UnexpectedHttpStatus(s));
+                return Err(error_from_response(Method::GET, response).await);
             }
         };
 
         b
     }
 
-    /// Takes reqwest's async RequestBuilder and injects an authentication header if a token is present
-    fn build_reqwest(&self, method: Method, url: Url) -> reqwest::RequestBuilder {
+    /// Takes reqwest's async RequestBuilder and injects an authentication header if a token is present.
+    ///
+    /// The returned request is sent over the client `Transport` according to
+    /// the `RetryPolicy`, following redirects without forwarding credentials
+    /// to other origins.
+    fn build_reqwest(&self, method: Method, url: Url) -> RequestBuilder<'_> {
         let mut builder = self.client.request(method, url);
 
         if let Some(auth) = &self.auth {
@@ -123,18 +170,6 @@ impl Client {
             builder = builder.header(reqwest::header::USER_AGENT, ua.as_str());
         };
 
-        builder
+        RequestBuilder::new(self, builder)
     }
 }
-
-#[derive(Debug, Default, Deserialize, Serialize)]
-struct ApiError {
-    code: String,
-    message: String,
-    detail: String,
-}
-
-#[derive(Debug, Default, Deserialize, Serialize)]
-struct Errors {
-    errors: Vec<ApiError>,
-}
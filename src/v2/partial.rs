@@ -0,0 +1,52 @@
+//! Files written to a temporary sibling, and renamed into place once complete.
+//!
+//! Temporary names are unique across processes and tasks, so that concurrent
+//! writers of the same destination never share a temporary file.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Marker in the name of temporary files.
+pub(crate) const TEMP_MARKER: &str = ".tmp-";
+
+/// Counter making temporary file names unique within a process.
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// A unique temporary sibling of `path`.
+pub(crate) fn temp_path(path: &Path) -> PathBuf {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(format!(
+        "{}{}-{}",
+        TEMP_MARKER,
+        std::process::id(),
+        TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    temp.into()
+}
+
+/// Create a temporary sibling of `path`, creating its parent directory.
+pub(crate) async fn create(path: &Path) -> io::Result<(tokio::fs::File, PathBuf)> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let temp = temp_path(path);
+    let file = tokio::fs::File::create(&temp).await?;
+    Ok((file, temp))
+}
+
+/// Sync a fully written temporary file to disk, and rename it to `path`.
+pub(crate) async fn persist(file: tokio::fs::File, temp: &Path, path: &Path) -> io::Result<()> {
+    file.sync_all().await?;
+    drop(file);
+    tokio::fs::rename(temp, path).await
+}
+
+/// Remove a temporary file, which may not exist.
+pub(crate) async fn discard(temp: &Path) {
+    match tokio::fs::remove_file(temp).await {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => debug!("failed to remove {}: {}", temp.display(), e),
+        Ok(()) => {}
+    }
+}
@@ -0,0 +1,302 @@
+//! Progress reporting for blob transfers.
+
+use crate::v2::Digest;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Direction of a blob transfer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TransferDirection {
+    /// Blob downloaded from a registry.
+    Download,
+    /// Blob uploaded to a registry.
+    Upload,
+}
+
+/// Progress of a blob transfer.
+///
+/// Each event refers to the blob by its digest, so that a single observer
+/// can follow several concurrent transfers.
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProgressEvent<'a> {
+    /// The blob is part of a larger transfer (e.g. an image) and will be
+    /// transferred later.
+    Queued {
+        digest: &'a Digest,
+        direction: TransferDirection,
+        total: Option<u64>,
+    },
+    /// The transfer started.
+    Started {
+        digest: &'a Digest,
+        direction: TransferDirection,
+        /// Size of the blob, from its descriptor or the `Content-Length`.
+        total: Option<u64>,
+    },
+    /// More bytes were transferred.
+    Progress {
+        digest: &'a Digest,
+        transferred: u64,
+        total: Option<u64>,
+    },
+    /// The transfer failed after `transferred` bytes and will be retried.
+    Retrying {
+        digest: &'a Digest,
+        transferred: u64,
+        attempt: u32,
+        delay: Duration,
+    },
+    /// The transfer was resumed at `offset` bytes (zero when restarted).
+    Resumed { digest: &'a Digest, offset: u64 },
+    /// The whole blob was transferred and verified against its digest.
+    Completed { digest: &'a Digest, size: u64 },
+    /// The transfer failed.
+    Failed { digest: &'a Digest },
+}
+
+impl ProgressEvent<'_> {
+    /// Digest of the blob this event refers to.
+    pub fn digest(&self) -> &Digest {
+        match self {
+            ProgressEvent::Queued { digest, .. }
+            | ProgressEvent::Started { digest, .. }
+            | ProgressEvent::Progress { digest, .. }
+            | ProgressEvent::Retrying { digest, .. }
+            | ProgressEvent::Resumed { digest, .. }
+            | ProgressEvent::Completed { digest, .. }
+            | ProgressEvent::Failed { digest } => digest,
+        }
+    }
+}
+
+/// Observer of blob transfer progress, e.g. to render progress bars.
+///
+/// Observers are called from the transferring task, so they should
+/// return quickly.
+pub trait ProgressObserver: fmt::Debug + Send + Sync {
+    /// Handle a progress event.
+    fn on_event(&self, event: &ProgressEvent<'_>);
+}
+
+/// Aggregated progress of all blobs of an image.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ImageProgressSnapshot {
+    /// Number of blobs which are part of the transfer.
+    pub blobs: usize,
+    /// Number of blobs which were completely transferred.
+    pub completed: usize,
+    /// Bytes transferred so far, over all blobs.
+    pub transferred: u64,
+    /// Total bytes to transfer, if the size of every blob is known.
+    pub total: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+struct BlobState {
+    transferred: u64,
+    total: Option<u64>,
+    completed: bool,
+}
+
+/// A `ProgressObserver` aggregating the progress of all blobs of an image.
+///
+/// Events are optionally forwarded to another observer, which can query
+/// the aggregated progress with `snapshot`.
+#[derive(Debug, Default)]
+pub struct ImageProgress {
+    blobs: Mutex<HashMap<Digest, BlobState>>,
+    forward: Option<Arc<dyn ProgressObserver>>,
+}
+
+impl ImageProgress {
+    /// Create an aggregator which does not forward events.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create an aggregator forwarding every event to `observer`.
+    pub fn with_observer(observer: Arc<dyn ProgressObserver>) -> Self {
+        Self {
+            blobs: Default::default(),
+            forward: Some(observer),
+        }
+    }
+
+    /// Return the aggregated progress so far.
+    pub fn snapshot(&self) -> ImageProgressSnapshot {
+        let blobs = self.blobs.lock().expect("progress lock is never poisoned");
+        ImageProgressSnapshot {
+            blobs: blobs.len(),
+            completed: blobs.values().filter(|b| b.completed).count(),
+            transferred: blobs.values().map(|b| b.transferred).sum(),
+            total: blobs.values().map(|b| b.total).sum(),
+        }
+    }
+}
+
+impl ProgressObserver for ImageProgress {
+    fn on_event(&self, event: &ProgressEvent<'_>) {
+        {
+            let mut blobs = self.blobs.lock().expect("progress lock is never poisoned");
+            let blob = blobs.entry(event.digest().clone()).or_default();
+            match *event {
+                ProgressEvent::Queued { total, .. } | ProgressEvent::Started { total, .. } => {
+                    blob.total = total.or(blob.total);
+                }
+                ProgressEvent::Progress { transferred, .. } => blob.transferred = transferred,
+                ProgressEvent::Resumed { offset, .. } => blob.transferred = offset,
+                ProgressEvent::Completed { size, .. } => {
+                    blob.transferred = size;
+                    blob.total = Some(size);
+                    blob.completed = true;
+                }
+                ProgressEvent::Retrying { .. } | ProgressEvent::Failed { .. } => {}
+            }
+        }
+        if let Some(forward) = &self.forward {
+            forward.on_event(event);
+        }
+    }
+}
+
+/// Emits progress events for a single blob, if an observer is set.
+#[derive(Clone, Debug)]
+pub(crate) struct BlobProgress {
+    observer: Option<Arc<dyn ProgressObserver>>,
+    digest: Digest,
+    total: Option<u64>,
+}
+
+impl BlobProgress {
+    pub(crate) fn new(observer: Option<Arc<dyn ProgressObserver>>, digest: &Digest) -> Self {
+        Self {
+            observer,
+            digest: digest.clone(),
+            total: None,
+        }
+    }
+
+    fn emit(&self, event: ProgressEvent<'_>) {
+        if let Some(observer) = &self.observer {
+            observer.on_event(&event);
+        }
+    }
+
+    pub(crate) fn queued(&self, direction: TransferDirection, total: Option<u64>) {
+        self.emit(ProgressEvent::Queued {
+            digest: &self.digest,
+            direction,
+            total,
+        });
+    }
+
+    pub(crate) fn started(&mut self, direction: TransferDirection, total: Option<u64>) {
+        self.total = total;
+        self.emit(ProgressEvent::Started {
+            digest: &self.digest,
+            direction,
+            total,
+        });
+    }
+
+    pub(crate) fn progress(&self, transferred: u64) {
+        self.emit(ProgressEvent::Progress {
+            digest: &self.digest,
+            transferred,
+            total: self.total,
+        });
+    }
+
+    pub(crate) fn retrying(&self, transferred: u64, attempt: u32, delay: Duration) {
+        self.emit(ProgressEvent::Retrying {
+            digest: &self.digest,
+            transferred,
+            attempt,
+            delay,
+        });
+    }
+
+    pub(crate) fn resumed(&self, offset: u64) {
+        self.emit(ProgressEvent::Resumed {
+            digest: &self.digest,
+            offset,
+        });
+    }
+
+    pub(crate) fn completed(&self, size: u64) {
+        self.emit(ProgressEvent::Completed {
+            digest: &self.digest,
+            size,
+        });
+    }
+
+    pub(crate) fn failed(&self) {
+        self.emit(ProgressEvent::Failed {
+            digest: &self.digest,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v2::DigestAlgorithm;
+
+    #[test]
+    fn image_progress_aggregates() {
+        let progress = ImageProgress::new();
+        let a = DigestAlgorithm::Sha256.digest(b"a");
+        let b = DigestAlgorithm::Sha256.digest(b"b");
+        let events = [
+            ProgressEvent::Queued {
+                digest: &a,
+                direction: TransferDirection::Download,
+                total: Some(10),
+            },
+            ProgressEvent::Queued {
+                digest: &b,
+                direction: TransferDirection::Download,
+                total: None,
+            },
+            ProgressEvent::Started {
+                digest: &b,
+                direction: TransferDirection::Download,
+                total: Some(20),
+            },
+            ProgressEvent::Progress {
+                digest: &a,
+                transferred: 10,
+                total: Some(10),
+            },
+            ProgressEvent::Completed {
+                digest: &a,
+                size: 10,
+            },
+            ProgressEvent::Progress {
+                digest: &b,
+                transferred: 15,
+                total: Some(20),
+            },
+            ProgressEvent::Resumed {
+                digest: &b,
+                offset: 5,
+            },
+        ];
+        for event in events.iter() {
+            progress.on_event(event);
+        }
+
+        assert_eq!(
+            progress.snapshot(),
+            ImageProgressSnapshot {
+                blobs: 2,
+                completed: 1,
+                transferred: 15,
+                total: Some(30),
+            }
+        );
+    }
+}
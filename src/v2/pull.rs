@@ -0,0 +1,371 @@
+//! Concurrent download of all layers of an image.
+
+use crate::errors::Result;
+use crate::render::{self, LayerRenderer};
+use crate::v2::manifest::{Manifest, ManifestError};
+use crate::v2::partial;
+use crate::v2::progress::BlobProgress;
+use crate::v2::*;
+use futures::future::BoxFuture;
+use futures::stream::{self, TryStreamExt};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::{fmt, io};
+use tokio::io::AsyncWrite;
+
+/// Descriptor of a layer referenced by an image manifest.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LayerDescriptor {
+    /// Digest of the layer blob.
+    pub digest: Digest,
+    /// Size of the layer blob in bytes, if known from the manifest.
+    pub size: Option<u64>,
+    /// Media type of the layer blob, if known from the manifest.
+    pub media_type: Option<String>,
+}
+
+/// Destination for downloaded layers.
+///
+/// For each layer, `open` is called to obtain a writer. Once the whole
+/// blob has been written and verified against its digest and size,
+/// `commit` is called; if the download fails, `abort` is called instead.
+pub trait LayerSink: fmt::Debug + Send + Sync {
+    /// Open a writer for the given layer.
+    fn open<'a>(
+        &'a self,
+        layer: &'a LayerDescriptor,
+    ) -> BoxFuture<'a, io::Result<Box<dyn AsyncWrite + Send + Unpin>>>;
+
+    /// Persist a fully written and verified layer.
+    fn commit<'a>(&'a self, layer: &'a LayerDescriptor) -> BoxFuture<'a, io::Result<()>>;
+
+    /// Discard a partially written layer.
+    fn abort<'a>(&'a self, _layer: &'a LayerDescriptor) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    /// Location of a committed layer, if the sink stores layers as files.
+    fn location(&self, _layer: &LayerDescriptor) -> Option<PathBuf> {
+        None
+    }
+}
+
+/// A `LayerSink` storing each layer as `<dir>/<algorithm>/<hex>`.
+///
+/// Layers are written to a uniquely named temporary sibling file, which is
+/// synced and renamed into place once the content has been verified. A layer
+/// can only be written once at a time through the same sink.
+#[derive(Clone, Debug)]
+pub struct DirectorySink {
+    dir: PathBuf,
+    partials: Arc<Mutex<HashMap<Digest, PathBuf>>>,
+}
+
+impl DirectorySink {
+    /// Create a sink storing layers under the given directory.
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+            partials: Default::default(),
+        }
+    }
+
+    /// Path at which the blob with the given digest is stored.
+    pub fn path_for(&self, digest: &Digest) -> PathBuf {
+        self.dir
+            .join(digest.algorithm().name())
+            .join(digest.encoded())
+    }
+
+    fn take_partial(&self, digest: &Digest) -> Option<PathBuf> {
+        self.partials.lock().expect("poisoned lock").remove(digest)
+    }
+}
+
+impl LayerSink for DirectorySink {
+    fn open<'a>(
+        &'a self,
+        layer: &'a LayerDescriptor,
+    ) -> BoxFuture<'a, io::Result<Box<dyn AsyncWrite + Send + Unpin>>> {
+        Box::pin(async move {
+            let (file, temp) = partial::create(&self.path_for(&layer.digest)).await?;
+            let claimed = match self
+                .partials
+                .lock()
+                .expect("poisoned lock")
+                .entry(layer.digest.clone())
+            {
+                Entry::Occupied(_) => false,
+                Entry::Vacant(entry) => {
+                    entry.insert(temp.clone());
+                    true
+                }
+            };
+            if !claimed {
+                drop(file);
+                partial::discard(&temp).await;
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("layer {} is already being written", layer.digest),
+                ));
+            }
+            Ok(Box::new(file) as Box<dyn AsyncWrite + Send + Unpin>)
+        })
+    }
+
+    fn commit<'a>(&'a self, layer: &'a LayerDescriptor) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            let temp = self
+                .take_partial(&layer.digest)
+                .ok_or_else(|| io::Error::other(format!("layer {} not open", layer.digest)))?;
+            let persisted = async {
+                let file = tokio::fs::OpenOptions::new()
+                    .write(true)
+                    .open(&temp)
+                    .await?;
+                partial::persist(file, &temp, &self.path_for(&layer.digest)).await
+            }
+            .await;
+            if persisted.is_err() {
+                partial::discard(&temp).await;
+            }
+            persisted
+        })
+    }
+
+    fn abort<'a>(&'a self, layer: &'a LayerDescriptor) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            if let Some(temp) = self.take_partial(&layer.digest) {
+                partial::discard(&temp).await;
+            }
+            Ok(())
+        })
+    }
+
+    fn location(&self, layer: &LayerDescriptor) -> Option<PathBuf> {
+        Some(self.path_for(&layer.digest))
+    }
+}
+
+/// Options for `Client::pull`.
+#[derive(Clone, Debug)]
+pub struct PullOptions {
+    concurrency: usize,
+    architecture: String,
+    os: String,
+    progress: Option<Arc<dyn ProgressObserver>>,
+}
+
+impl Default for PullOptions {
+    fn default() -> Self {
+        Self {
+            concurrency: 4,
+            architecture: default_architecture().to_string(),
+            os: "linux".to_string(),
+            progress: None,
+        }
+    }
+}
+
+impl PullOptions {
+    /// Set the maximum number of layers downloaded at the same time.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Set the architecture to pick from manifest lists (defaults to the host one).
+    pub fn architecture(mut self, architecture: &str) -> Self {
+        self.architecture = architecture.to_string();
+        self
+    }
+
+    /// Set the OS to pick from manifest lists (defaults to `linux`).
+    pub fn os(mut self, os: &str) -> Self {
+        self.os = os.to_string();
+        self
+    }
+
+    /// Set an observer for the progress of layer downloads.
+    ///
+    /// All layers are reported as queued before the first download starts;
+    /// use `ImageProgress` to aggregate them.
+    pub fn progress(mut self, observer: Arc<dyn ProgressObserver>) -> Self {
+        self.progress = Some(observer);
+        self
+    }
+}
+
+/// Host architecture, in the naming used by image platforms.
+pub(crate) fn default_architecture() -> &'static str {
+    match std::env::consts::ARCH {
+        "x86_64" => "amd64",
+        "x86" => "386",
+        "aarch64" => "arm64",
+        // `ARCH` does not tell the endianness of these apart.
+        "powerpc64" if cfg!(target_endian = "little") => "ppc64le",
+        "powerpc64" => "ppc64",
+        "mips64" if cfg!(target_endian = "little") => "mips64le",
+        other => other,
+    }
+}
+
+/// A layer downloaded by `Client::pull`.
+#[derive(Clone, Debug)]
+pub struct PulledLayer {
+    /// Descriptor of the layer, as referenced by the manifest.
+    pub descriptor: LayerDescriptor,
+    /// Number of bytes downloaded and verified.
+    pub size: u64,
+    /// Location of the layer, if the sink stores layers as files.
+    pub path: Option<PathBuf>,
+}
+
+/// Summary of a completed `Client::pull`.
+#[derive(Clone, Debug)]
+pub struct PullSummary {
+    /// Digest of the image manifest which was pulled, if known.
+    pub manifest_digest: Option<Digest>,
+    /// Downloaded layers, ordered starting with the base layer first.
+    ///
+    /// Layers referenced more than once by the manifest are only listed once.
+    pub layers: Vec<PulledLayer>,
+}
+
+impl Client {
+    /// Download all layers of an image into a `LayerSink`.
+    ///
+    /// Manifest lists are resolved to the image matching the architecture and
+    /// OS in `options`. Up to `options.concurrency` layers are downloaded at the
+    /// same time, each one being streamed to the sink and verified against the
+    /// digest and size from the manifest.
+    pub async fn pull<S: LayerSink>(
+        &self,
+        name: &str,
+        reference: &str,
+        sink: &S,
+        options: &PullOptions,
+    ) -> Result<PullSummary> {
+        let (manifest, manifest_digest) = self.get_manifest_and_ref(name, reference).await?;
+        let (manifest, manifest_digest) = match manifest {
+            Manifest::ML(list) => {
+                let digest = list
+                    .find_platform(&options.architecture, &options.os, None)
+                    .map(|m| m.digest())
+                    .ok_or(ManifestError::ArchitectureMismatch)?;
+                trace!("Resolved manifest list {reference} to {digest}");
+                let (manifest, _) = self.get_manifest_and_ref(name, digest.as_str()).await?;
+                (manifest, Some(digest))
+            }
+            m => (m, manifest_digest),
+        };
+
+        let mut seen = HashSet::new();
+        let descriptors = manifest
+            .layer_descriptors()?
+            .into_iter()
+            .filter(|d| seen.insert(d.digest.clone()))
+            .collect::<Vec<_>>();
+        trace!("Pulling {} layer(s) of {name}", descriptors.len());
+        for descriptor in &descriptors {
+            BlobProgress::new(options.progress.clone(), &descriptor.digest)
+                .queued(TransferDirection::Download, descriptor.size);
+        }
+
+        let layers = stream::iter(descriptors.into_iter().map(Ok))
+            .map_ok(|descriptor| {
+                let progress = BlobProgress::new(options.progress.clone(), &descriptor.digest);
+                self.pull_layer(name, descriptor, sink, progress)
+            })
+            .try_buffered(options.concurrency)
+            .try_collect()
+            .await?;
+
+        Ok(PullSummary {
+            manifest_digest,
+            layers,
+        })
+    }
+
+    /// Download all layers of an image as files into a directory.
+    ///
+    /// See `DirectorySink` for the resulting layout.
+    pub async fn pull_to_dir<P: AsRef<Path>>(
+        &self,
+        name: &str,
+        reference: &str,
+        dir: P,
+        options: &PullOptions,
+    ) -> Result<PullSummary> {
+        self.pull(name, reference, &DirectorySink::new(dir), options)
+            .await
+    }
+
+    /// Render layers of an image to the target directory of `renderer`.
+    ///
+    /// Layers are applied in order, lower layers coming first. Each one is
+    /// downloaded and verified to a temporary file while the previous one is
+    /// being unpacked, so that no layer is held in memory.
+    pub async fn render_layers(
+        &self,
+        name: &str,
+        layers: &[LayerDescriptor],
+        renderer: &LayerRenderer,
+    ) -> Result<()> {
+        let spools = layers.iter().map(|descriptor| async move {
+            let mut file = tokio::fs::File::from_std(tempfile::tempfile()?);
+            let progress = BlobProgress::new(None, &descriptor.digest);
+            self.copy_blob(
+                name,
+                &descriptor.digest,
+                descriptor.size,
+                &mut file,
+                progress,
+            )
+            .await?;
+            trace!("Downloaded layer {} for rendering", descriptor.digest);
+            Ok(file)
+        });
+        render::render_spooled(renderer, spools).await
+    }
+
+    async fn pull_layer<S: LayerSink>(
+        &self,
+        name: &str,
+        descriptor: LayerDescriptor,
+        sink: &S,
+        progress: BlobProgress,
+    ) -> Result<PulledLayer> {
+        let mut writer = sink.open(&descriptor).await?;
+        let written = self
+            .copy_blob(
+                name,
+                &descriptor.digest,
+                descriptor.size,
+                &mut writer,
+                progress,
+            )
+            .await;
+        drop(writer);
+
+        let size = match written {
+            Ok(size) => size,
+            Err(e) => {
+                if let Err(abort_err) = sink.abort(&descriptor).await {
+                    warn!("failed to discard layer {}: {abort_err}", descriptor.digest);
+                }
+                return Err(e);
+            }
+        };
+        sink.commit(&descriptor).await?;
+        trace!("Pulled layer {} ({size} bytes)", descriptor.digest);
+
+        Ok(PulledLayer {
+            path: sink.location(&descriptor),
+            descriptor,
+            size,
+        })
+    }
+}
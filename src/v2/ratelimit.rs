@@ -0,0 +1,193 @@
+//! Registry rate-limit introspection.
+//!
+//! Docker Hub (and other registries following the same convention) report
+//! pull quotas via `RateLimit-Limit`, `RateLimit-Remaining` and
+//! `Docker-RateLimit-Source` headers, as described at
+//! <https://docs.docker.com/docker-hub/download-rate-limit/>.
+
+use crate::errors::Result;
+use crate::v2::*;
+use reqwest::{header, Url};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+static HEADER_LIMIT: &str = "ratelimit-limit";
+static HEADER_REMAINING: &str = "ratelimit-remaining";
+static HEADER_SOURCE: &str = "docker-ratelimit-source";
+
+/// A request quota, as advertised by a registry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimitQuota {
+    /// Number of requests.
+    pub count: u64,
+    /// Time window the quota applies to, if advertised.
+    pub window: Option<Duration>,
+}
+
+/// Rate-limit state observed on a registry response.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RateLimit {
+    /// Total quota for the current window.
+    pub limit: Option<RateLimitQuota>,
+    /// Remaining quota for the current window.
+    pub remaining: Option<RateLimitQuota>,
+    /// Entity the quota is accounted to (e.g. client IP or user ID).
+    pub source: Option<String>,
+    /// When the response carrying these values was received.
+    pub observed_at: SystemTime,
+}
+
+impl RateLimit {
+    /// Parse rate-limit headers, returning `None` if there are none.
+    pub fn from_headers(headers: &header::HeaderMap) -> Option<Self> {
+        let limit = headers.get(HEADER_LIMIT).and_then(parse_quota);
+        let remaining = headers.get(HEADER_REMAINING).and_then(parse_quota);
+        let source = headers
+            .get(HEADER_SOURCE)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        if limit.is_none() && remaining.is_none() && source.is_none() {
+            return None;
+        }
+        Some(Self {
+            limit,
+            remaining,
+            source,
+            observed_at: SystemTime::now(),
+        })
+    }
+
+    /// Whether the remaining quota is exhausted.
+    pub fn is_exhausted(&self) -> bool {
+        matches!(self.remaining, Some(RateLimitQuota { count: 0, .. }))
+    }
+}
+
+/// Parse a quota in `<count>[;w=<seconds>]` format.
+///
+/// Multiple comma-separated policies may be advertised; the first one wins.
+fn parse_quota(value: &header::HeaderValue) -> Option<RateLimitQuota> {
+    let policy = value.to_str().ok()?.split(',').next()?;
+    let mut params = policy.split(';').map(str::trim);
+    let count = params.next()?.parse().ok()?;
+    let window = params
+        .filter_map(|p| p.strip_prefix("w="))
+        .find_map(|w| w.parse().ok())
+        .map(Duration::from_secs);
+    Some(RateLimitQuota { count, window })
+}
+
+/// Latest rate-limit values, per registry host.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct RateLimits(Arc<Mutex<HashMap<String, RateLimit>>>);
+
+impl RateLimits {
+    /// Record rate-limit headers from a response, if any.
+    pub(crate) fn record(&self, url: &Url, headers: &header::HeaderMap) {
+        if let Some(rate_limit) = RateLimit::from_headers(headers) {
+            trace!("Rate limit for {}: {:?}", host_key(url), rate_limit);
+            self.0
+                .lock()
+                .expect("rate-limit lock is never poisoned")
+                .insert(host_key(url), rate_limit);
+        }
+    }
+
+    fn get(&self, host: &str) -> Option<RateLimit> {
+        self.0
+            .lock()
+            .expect("rate-limit lock is never poisoned")
+            .get(host)
+            .cloned()
+    }
+
+    fn all(&self) -> HashMap<String, RateLimit> {
+        self.0
+            .lock()
+            .expect("rate-limit lock is never poisoned")
+            .clone()
+    }
+}
+
+fn host_key(url: &Url) -> String {
+    match (url.host_str(), url.port()) {
+        (Some(host), Some(port)) => format!("{host}:{port}"),
+        (Some(host), None) => host.to_string(),
+        (None, _) => String::new(),
+    }
+}
+
+impl Client {
+    /// Return the latest rate-limit values observed for this client's registry.
+    pub fn rate_limit(&self) -> Option<RateLimit> {
+        let url = Url::parse(&self.base_url).ok()?;
+        self.rate_limits.get(&host_key(&url))
+    }
+
+    /// Return the latest rate-limit values observed for every host contacted
+    /// by this client (including e.g. blob storage redirect targets).
+    pub fn rate_limits(&self) -> HashMap<String, RateLimit> {
+        self.rate_limits.all()
+    }
+
+    /// Query the current rate-limit status for a repository.
+    ///
+    /// This performs a `HEAD` request on the `latest` manifest, which
+    /// registries do not account against the pull quota.
+    pub async fn rate_limit_status(&self, repo: &str) -> Result<Option<RateLimit>> {
+        let url = self.build_url(repo, "latest")?;
+        let res = self
+            .build_reqwest(Method::HEAD, url)
+            .headers(manifest::build_accept_headers(&self.accepted_types))
+            .send()
+            .await?;
+
+        let status = res.status();
+        trace!("HEAD '{}' status: {:?}", res.url(), status);
+
+        match RateLimit::from_headers(res.headers()) {
+            Some(rate_limit) => Ok(Some(rate_limit)),
+            None if status.is_success() => Ok(None),
+            None => Err(error_from_response(Method::HEAD, res).await),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("100;w=21600" => Some(RateLimitQuota { count: 100, window: Some(Duration::from_secs(21600)) }); "docker hub")]
+    #[test_case("76" => Some(RateLimitQuota { count: 76, window: None }); "no window")]
+    #[test_case("10, 10;w=1, 50;w=60" => Some(RateLimitQuota { count: 10, window: None }); "multiple policies")]
+    #[test_case("lots" => None; "invalid")]
+    fn quota_parses(value: &str) -> Option<RateLimitQuota> {
+        parse_quota(&header::HeaderValue::from_str(value).unwrap())
+    }
+
+    #[test]
+    fn record_per_host() {
+        let rate_limits = RateLimits::default();
+        let mut headers = header::HeaderMap::new();
+        headers.insert(HEADER_LIMIT, "100;w=21600".parse().unwrap());
+        headers.insert(HEADER_REMAINING, "0;w=21600".parse().unwrap());
+        headers.insert(HEADER_SOURCE, "192.0.2.1".parse().unwrap());
+
+        let url =
+            Url::parse("https://registry-1.docker.io/v2/library/busybox/manifests/latest").unwrap();
+        rate_limits.record(&url, &headers);
+        rate_limits.record(
+            &Url::parse("http://localhost:5000/v2/").unwrap(),
+            &header::HeaderMap::new(),
+        );
+
+        let all = rate_limits.all();
+        assert_eq!(all.len(), 1);
+        let rate_limit = &all["registry-1.docker.io"];
+        assert!(rate_limit.is_exhausted());
+        assert_eq!(rate_limit.limit.unwrap().count, 100);
+        assert_eq!(rate_limit.source.as_deref(), Some("192.0.2.1"));
+    }
+}
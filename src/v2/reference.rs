@@ -0,0 +1,131 @@
+//! Clients and calls addressed by image reference.
+
+use crate::errors::{Error, Result};
+use crate::reference::Reference;
+use crate::v2::manifest::Manifest;
+use crate::v2::*;
+use std::path::{Path, PathBuf};
+
+/// Options for `Client::for_reference`.
+#[derive(Debug, Default)]
+pub struct ReferenceOptions {
+    config: Config,
+    insecure_registries: Vec<String>,
+    credentials_file: Option<PathBuf>,
+    push: bool,
+}
+
+impl ReferenceOptions {
+    /// Set the base configuration of the client.
+    ///
+    /// Its registry is replaced by the one of the reference.
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Add a registry to reach over plain HTTP.
+    pub fn insecure_registry(mut self, registry: &str) -> Self {
+        self.insecure_registries.push(registry.to_string());
+        self
+    }
+
+    /// Read credentials from a docker-client config file.
+    ///
+    /// Defaults to `$DOCKER_CONFIG/config.json`, or `~/.docker/config.json`
+    /// when `DOCKER_CONFIG` is unset. Credentials set on the base
+    /// configuration take precedence, and a missing file or entry is not an
+    /// error.
+    pub fn credentials_file<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.credentials_file = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Whether to request push access to the repository, in addition to pull.
+    pub fn push(mut self, push: bool) -> Self {
+        self.push = push;
+        self
+    }
+}
+
+/// The config file of the docker client, `$DOCKER_CONFIG/config.json` or
+/// `~/.docker/config.json`.
+fn default_credentials_file() -> Option<PathBuf> {
+    let non_empty = |var| std::env::var_os(var).filter(|v| !v.is_empty());
+    match non_empty("DOCKER_CONFIG") {
+        Some(dir) => Some(PathBuf::from(dir).join("config.json")),
+        None => non_empty("HOME")
+            .or_else(|| non_empty("USERPROFILE"))
+            .map(|home| PathBuf::from(home).join(".docker").join("config.json")),
+    }
+}
+
+impl Client {
+    /// Build a client for the registry and repository of a reference.
+    ///
+    /// Unless the registry allows anonymous access, the client is
+    /// authenticated with the scope `repository:<repo>:pull` (or
+    /// `pull,push`), using the credentials found for the registry.
+    pub async fn for_reference(reference: &Reference, options: ReferenceOptions) -> Result<Self> {
+        let registry = reference.registry();
+        let mut config = options.config.registry(&registry);
+        if options.insecure_registries.contains(&registry) {
+            config = config.insecure_registry(true);
+        }
+        if let Some(path) = options
+            .credentials_file
+            .or_else(default_credentials_file)
+            .filter(|_| !config.has_credentials())
+        {
+            match tokio::fs::read(&path).await {
+                Ok(content) => config = config.read_credentials(content.as_slice()),
+                Err(e) => trace!("Skipping credentials file {}: {e}", path.display()),
+            }
+        }
+
+        let client = config.build()?;
+        if client.is_auth().await? {
+            return Ok(client);
+        }
+        let actions = if options.push { "pull,push" } else { "pull" };
+        let scope = format!("repository:{}:{actions}", reference.repository());
+        client.authenticate(&[&scope]).await
+    }
+
+    /// Fetch the image manifest of a reference.
+    ///
+    /// The reference must be on the registry of this client.
+    pub async fn get_manifest_for(&self, reference: &Reference) -> Result<Manifest> {
+        self.check_registry(reference)?;
+        self.get_manifest(&reference.repository(), &reference.version())
+            .await
+    }
+
+    /// Download all layers of the image of a reference into a `LayerSink`.
+    ///
+    /// The reference must be on the registry of this client; see `pull`.
+    pub async fn pull_for<S: LayerSink>(
+        &self,
+        reference: &Reference,
+        sink: &S,
+        options: &PullOptions,
+    ) -> Result<PullSummary> {
+        self.check_registry(reference)?;
+        self.pull(&reference.repository(), &reference.version(), sink, options)
+            .await
+    }
+
+    fn check_registry(&self, reference: &Reference) -> Result<()> {
+        let registry = self
+            .base_url
+            .split_once("://")
+            .map_or(self.base_url.as_str(), |(_, host)| host);
+        match registry == reference.registry() {
+            true => Ok(()),
+            false => Err(Error::RegistryMismatch {
+                registry: registry.to_string(),
+                reference: reference.to_string(),
+            }),
+        }
+    }
+}
@@ -0,0 +1,264 @@
+use crate::errors::{Error, Result};
+use crate::v2::*;
+use reqwest::header::{self, HeaderMap, HeaderName, HeaderValue};
+use std::time::Instant;
+
+/// Maximum number of redirects followed for a single request.
+pub(crate) const MAX_REDIRECTS: usize = 10;
+
+/// A request to the registry, sent according to the `Client` settings.
+///
+/// This wraps reqwest's async `RequestBuilder` so that every registry call
+/// goes through the same sending logic (e.g. the retry policy).
+#[derive(Debug)]
+pub(crate) struct RequestBuilder<'a> {
+    client: &'a Client,
+    inner: reqwest::RequestBuilder,
+    follow_redirects: bool,
+}
+
+impl<'a> RequestBuilder<'a> {
+    pub(crate) fn new(client: &'a Client, inner: reqwest::RequestBuilder) -> Self {
+        Self {
+            client,
+            inner,
+            follow_redirects: true,
+        }
+    }
+
+    /// Add a header to this request.
+    pub(crate) fn header(mut self, key: HeaderName, value: HeaderValue) -> Self {
+        self.inner = self.inner.header(key, value);
+        self
+    }
+
+    /// Merge headers into this request.
+    pub(crate) fn headers(mut self, headers: HeaderMap) -> Self {
+        self.inner = self.inner.headers(headers);
+        self
+    }
+
+    /// Set the body of this request.
+    pub(crate) fn body<T: Into<reqwest::Body>>(mut self, body: T) -> Self {
+        self.inner = self.inner.body(body);
+        self
+    }
+
+    /// Set whether redirects are followed (the default) or returned as-is.
+    pub(crate) fn follow_redirects(mut self, follow_redirects: bool) -> Self {
+        self.follow_redirects = follow_redirects;
+        self
+    }
+
+    /// Send the request, retrying transient failures as per the client `RetryPolicy`.
+    ///
+    /// Rate-limit headers of every response are recorded on the client.
+    pub(crate) async fn send(self) -> Result<reqwest::Response> {
+        let request = self.inner.build()?;
+        let policy = &self.client.retry_policy;
+        let mut attempt = 1;
+        loop {
+            let req = match request.try_clone() {
+                Some(r) => r,
+                // Streaming bodies cannot be replayed.
+                None => return self.client.execute(request, self.follow_redirects).await,
+            };
+
+            let result = self.client.execute(req, self.follow_redirects).await;
+            let delay = match &result {
+                Ok(resp) => policy.delay_for_response(resp, attempt),
+                Err(Error::Reqwest(err)) => policy.delay_for_error(err, attempt),
+                Err(_) => None,
+            };
+            let delay = match delay {
+                Some(d) => d,
+                None => return result,
+            };
+
+            match &result {
+                Ok(resp) => debug!(
+                    "{} returned {}, retrying in {:?} (attempt {}/{})",
+                    resp.url(),
+                    resp.status(),
+                    delay,
+                    attempt,
+                    policy.attempts()
+                ),
+                Err(err) => debug!(
+                    "request failed: {}, retrying in {:?} (attempt {}/{})",
+                    err,
+                    delay,
+                    attempt,
+                    policy.attempts()
+                ),
+            };
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+impl Client {
+    /// Execute a single attempt of a request, optionally following redirects.
+    ///
+    /// Credentials are only forwarded to redirect targets on the same origin,
+    /// so that they are not leaked to (and rejected by) external blob storage.
+    async fn execute(
+        &self,
+        mut request: reqwest::Request,
+        follow_redirects: bool,
+    ) -> Result<reqwest::Response> {
+        for _ in 0..=MAX_REDIRECTS {
+            let next = match follow_redirects {
+                true => request.try_clone(),
+                false => None,
+            };
+
+            let resp = self.round_trip(request).await?;
+            self.rate_limits.record(resp.url(), resp.headers());
+
+            let (next, location) = match (next, redirect_location(&resp)) {
+                (Some(next), Some(location)) => (next, location),
+                _ => return Ok(resp),
+            };
+            debug!(
+                "{} redirected ({}) to {}",
+                resp.url(),
+                resp.status(),
+                location
+            );
+            request = redirect_request(next, resp.status(), location);
+        }
+        Err(Error::TooManyRedirects(MAX_REDIRECTS))
+    }
+
+    /// Perform a single HTTP exchange over the client transport, passing it
+    /// through the client middlewares.
+    async fn round_trip(&self, mut request: reqwest::Request) -> Result<reqwest::Response> {
+        let mut canned = None;
+        let mut applied = 0;
+        for middleware in &self.middlewares {
+            applied += 1;
+            canned = middleware.on_request(&mut request);
+            if canned.is_some() {
+                break;
+            }
+        }
+
+        let method = request.method().clone();
+        let url = request.url().clone();
+        let start = Instant::now();
+        let result = match canned {
+            Some(resp) => {
+                trace!("{method} {url} short-circuited by middleware");
+                Ok(resp)
+            }
+            None => self.transport.execute(request).await,
+        };
+        let elapsed = start.elapsed();
+
+        for middleware in self.middlewares[..applied].iter().rev() {
+            match &result {
+                Ok(resp) => middleware.on_response(&method, &url, resp, elapsed),
+                Err(err) => middleware.on_error(&method, &url, err, elapsed),
+            }
+        }
+        result
+    }
+}
+
+/// Return the target of a redirect response, if any.
+pub(crate) fn redirect_location(resp: &reqwest::Response) -> Option<Url> {
+    match resp.status() {
+        StatusCode::MOVED_PERMANENTLY
+        | StatusCode::FOUND
+        | StatusCode::SEE_OTHER
+        | StatusCode::TEMPORARY_REDIRECT
+        | StatusCode::PERMANENT_REDIRECT => {}
+        _ => return None,
+    };
+    let location = resp.headers().get(header::LOCATION)?.to_str().ok()?;
+    resp.url().join(location).ok()
+}
+
+/// Build the request following a redirect to `location`.
+fn redirect_request(
+    mut previous: reqwest::Request,
+    status: StatusCode,
+    location: Url,
+) -> reqwest::Request {
+    let method = match (status, previous.method()) {
+        (_, &Method::HEAD) => Method::HEAD,
+        (StatusCode::SEE_OTHER, _) => Method::GET,
+        (StatusCode::MOVED_PERMANENTLY, &Method::POST) | (StatusCode::FOUND, &Method::POST) => {
+            Method::GET
+        }
+        (_, m) => m.clone(),
+    };
+
+    let mut headers = std::mem::take(previous.headers_mut());
+    if previous.url().origin() != location.origin() {
+        trace!("Not forwarding credentials to {}", location);
+        headers.remove(header::AUTHORIZATION);
+        headers.remove(header::PROXY_AUTHORIZATION);
+        headers.remove(header::COOKIE);
+    }
+    let keep_body = method == previous.method();
+    if !keep_body {
+        headers.remove(header::CONTENT_TYPE);
+        headers.remove(header::CONTENT_LENGTH);
+    }
+
+    let mut request = reqwest::Request::new(method, location);
+    *request.headers_mut() = headers;
+    *request.timeout_mut() = previous.timeout().cloned();
+    if keep_body {
+        *request.body_mut() = previous.body_mut().take();
+    }
+    request
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn request(method: Method, url: &str) -> reqwest::Request {
+        let mut request = reqwest::Request::new(method, Url::parse(url).unwrap());
+        let headers = request.headers_mut();
+        headers.insert(header::AUTHORIZATION, "Bearer xyz".parse().unwrap());
+        headers.insert(header::ACCEPT, "*/*".parse().unwrap());
+        request
+    }
+
+    #[test_case("https://registry.example.com/storage/abc" => true; "same origin")]
+    #[test_case("https://storage.example.net/abc?sig=1" => false; "other host")]
+    #[test_case("http://registry.example.com/storage/abc" => false; "other scheme")]
+    #[test_case("https://registry.example.com:8443/abc" => false; "other port")]
+    fn redirect_keeps_credentials(location: &str) -> bool {
+        let previous = request(
+            Method::GET,
+            "https://registry.example.com/v2/foo/blobs/sha256:abc",
+        );
+        let next = redirect_request(
+            previous,
+            StatusCode::TEMPORARY_REDIRECT,
+            Url::parse(location).unwrap(),
+        );
+        assert_eq!(next.url().as_str(), location);
+        assert!(next.headers().contains_key(header::ACCEPT));
+        next.headers().contains_key(header::AUTHORIZATION)
+    }
+
+    #[test_case(StatusCode::SEE_OTHER, Method::PUT => Method::GET; "see other")]
+    #[test_case(StatusCode::FOUND, Method::POST => Method::GET; "found post")]
+    #[test_case(StatusCode::TEMPORARY_REDIRECT, Method::POST => Method::POST; "temporary post")]
+    #[test_case(StatusCode::SEE_OTHER, Method::HEAD => Method::HEAD; "see other head")]
+    fn redirect_method(status: StatusCode, method: Method) -> Method {
+        let previous = request(method, "https://registry.example.com/v2/foo/blobs/uploads/");
+        let location = Url::parse("https://registry.example.com/v2/foo/blobs/uploads/1").unwrap();
+        redirect_request(previous, status, location)
+            .method()
+            .clone()
+    }
+}
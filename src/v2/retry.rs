@@ -0,0 +1,225 @@
+//! Retry policy for registry requests.
+
+use reqwest::{header, StatusCode};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, SystemTime};
+
+/// Classes of transport errors which may be retried.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RetryableError {
+    /// Failure while establishing a connection.
+    Connect,
+    /// Request or response timed out.
+    Timeout,
+    /// Failure while sending the request, e.g. a connection reset.
+    Request,
+    /// Failure while receiving the response body.
+    Body,
+}
+
+/// Policy for retrying transient failures with exponential backoff.
+///
+/// The default policy performs a single attempt, i.e. it never retries.
+/// Retries are enabled by raising `max_attempts`:
+///
+/// ```rust
+/// # extern crate dkregistry;
+/// use dkregistry::v2::{Client, RetryPolicy};
+/// use std::time::Duration;
+///
+/// let policy = RetryPolicy::default()
+///     .max_attempts(5)
+///     .base_backoff(Duration::from_millis(200));
+/// let client = Client::configure().retry_policy(policy);
+/// ```
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    base_backoff: Duration,
+    max_backoff: Duration,
+    jitter: bool,
+    statuses: Vec<StatusCode>,
+    errors: Vec<RetryableError>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 1,
+            base_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            jitter: true,
+            statuses: vec![
+                StatusCode::REQUEST_TIMEOUT,
+                StatusCode::TOO_MANY_REQUESTS,
+                StatusCode::INTERNAL_SERVER_ERROR,
+                StatusCode::BAD_GATEWAY,
+                StatusCode::SERVICE_UNAVAILABLE,
+                StatusCode::GATEWAY_TIMEOUT,
+            ],
+            errors: vec![
+                RetryableError::Connect,
+                RetryableError::Timeout,
+                RetryableError::Request,
+                RetryableError::Body,
+            ],
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Set the maximum number of attempts, including the first one.
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Set the backoff before the first retry; it doubles on each further retry.
+    pub fn base_backoff(mut self, base_backoff: Duration) -> Self {
+        self.base_backoff = base_backoff;
+        self
+    }
+
+    /// Set the upper bound for a single backoff.
+    ///
+    /// This also bounds `Retry-After`: if a registry asks to wait longer,
+    /// its response is returned to the caller instead of being retried.
+    pub fn max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    /// Set whether to randomize backoffs, to avoid retrying in lockstep.
+    pub fn jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Set the HTTP statuses which are retried.
+    pub fn retryable_statuses(mut self, statuses: Vec<StatusCode>) -> Self {
+        self.statuses = statuses;
+        self
+    }
+
+    /// Set the classes of transport errors which are retried.
+    pub fn retryable_errors(mut self, errors: Vec<RetryableError>) -> Self {
+        self.errors = errors;
+        self
+    }
+
+    /// Return the maximum number of attempts.
+    pub fn attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    /// Whether a transport error belongs to one of the retryable classes.
+    pub fn is_retryable_error(&self, err: &reqwest::Error) -> bool {
+        let class = if err.is_connect() {
+            RetryableError::Connect
+        } else if err.is_timeout() {
+            RetryableError::Timeout
+        } else if err.is_body() || err.is_decode() {
+            RetryableError::Body
+        } else if err.is_request() {
+            RetryableError::Request
+        } else {
+            return false;
+        };
+        self.errors.contains(&class)
+    }
+
+    /// Delay before retrying after a failed `attempt` (starting at 1), if any.
+    pub(crate) fn delay_for_error(&self, err: &reqwest::Error, attempt: u32) -> Option<Duration> {
+        if attempt >= self.max_attempts || !self.is_retryable_error(err) {
+            return None;
+        }
+        Some(self.backoff(attempt))
+    }
+
+    /// Delay before retrying after `attempt` (starting at 1) got `response`, if any.
+    pub(crate) fn delay_for_response(
+        &self,
+        response: &reqwest::Response,
+        attempt: u32,
+    ) -> Option<Duration> {
+        if attempt >= self.max_attempts || !self.statuses.contains(&response.status()) {
+            return None;
+        }
+        match retry_after(response.headers()) {
+            Some(wait) if wait > self.max_backoff => {
+                debug!("Retry-After of {wait:?} exceeds maximum backoff, not retrying");
+                None
+            }
+            Some(wait) => Some(wait),
+            None => Some(self.backoff(attempt)),
+        }
+    }
+
+    fn backoff(&self, attempt: u32) -> Duration {
+        let exp = self
+            .base_backoff
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)));
+        let backoff = exp.min(self.max_backoff);
+        if !self.jitter {
+            return backoff;
+        }
+
+        // Equal jitter: keep half of the backoff, randomize the other half.
+        let random = RandomState::new().build_hasher().finish();
+        let half = backoff / 2;
+        half + half.mul_f64((random % 1000) as f64 / 1000.0)
+    }
+}
+
+/// Parse a `Retry-After` header, either in delay-seconds or HTTP-date form.
+fn retry_after(headers: &header::HeaderMap) -> Option<Duration> {
+    let value = headers.get(header::RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case(1 => Duration::from_millis(100); "first retry")]
+    #[test_case(2 => Duration::from_millis(200); "second retry")]
+    #[test_case(4 => Duration::from_millis(800); "fourth retry")]
+    #[test_case(8 => Duration::from_secs(1); "capped")]
+    fn backoff_without_jitter(attempt: u32) -> Duration {
+        RetryPolicy::default()
+            .base_backoff(Duration::from_millis(100))
+            .max_backoff(Duration::from_secs(1))
+            .jitter(false)
+            .backoff(attempt)
+    }
+
+    #[test]
+    fn backoff_with_jitter() {
+        let policy = RetryPolicy::default()
+            .base_backoff(Duration::from_millis(100))
+            .jitter(true);
+        for attempt in 1..5 {
+            let max = Duration::from_millis(100 * 2u64.pow(attempt - 1));
+            let backoff = policy.backoff(attempt);
+            assert!(backoff >= max / 2 && backoff <= max, "{:?}", backoff);
+        }
+    }
+
+    #[test_case("120" => Some(Duration::from_secs(120)); "seconds")]
+    #[test_case("Wed, 21 Oct 2015 07:28:00 GMT" => Some(Duration::ZERO); "past date")]
+    #[test_case("soon" => None; "invalid")]
+    fn parse_retry_after(value: &str) -> Option<Duration> {
+        let mut headers = header::HeaderMap::new();
+        headers.insert(header::RETRY_AFTER, value.parse().unwrap());
+        retry_after(&headers)
+    }
+}
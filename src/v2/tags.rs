@@ -58,10 +58,15 @@ impl Client {
 
         let resp = self
             .build_reqwest(Method::GET, url.clone())
-            .header(header::ACCEPT, "application/json")
+            .header(
+                header::ACCEPT,
+                header::HeaderValue::from_static("application/json"),
+            )
             .send()
-            .await?
-            .error_for_status()?;
+            .await?;
+        if !resp.status().is_success() {
+            return Err(error_from_response(Method::GET, resp).await);
+        }
 
         // ensure the CONTENT_TYPE header is application/json
         let ct_hdr = resp.headers().get(header::CONTENT_TYPE).cloned();
@@ -0,0 +1,72 @@
+//! HTTP transport used to exchange requests with a registry.
+
+use crate::errors::Result;
+use futures::future::BoxFuture;
+use std::fmt;
+
+/// Transport executing the HTTP requests of a `Client`.
+///
+/// The client implements the registry protocol (authentication, retries,
+/// redirects, manifests, blobs, pagination) on top of a transport, which
+/// only performs single HTTP exchanges. Transports must not follow
+/// redirects themselves.
+///
+/// By default requests are sent with `reqwest` (see `ReqwestTransport`).
+/// Custom transports can e.g. serve responses from memory, or reach a
+/// registry through a Unix domain socket. Responses can be built from an
+/// `http::Response`, setting their URL with `reqwest::ResponseBuilderExt`:
+///
+/// ```rust
+/// # extern crate dkregistry;
+/// # extern crate futures;
+/// # extern crate http;
+/// # extern crate reqwest;
+/// use dkregistry::errors::Result;
+/// use dkregistry::v2::{Client, Transport};
+/// use futures::future::BoxFuture;
+/// use reqwest::ResponseBuilderExt;
+/// use std::sync::Arc;
+///
+/// #[derive(Debug)]
+/// struct AlwaysV2;
+///
+/// impl Transport for AlwaysV2 {
+///     fn execute(&self, request: reqwest::Request) -> BoxFuture<'_, Result<reqwest::Response>> {
+///         Box::pin(async move {
+///             let response = http::Response::builder()
+///                 .url(request.url().clone())
+///                 .header("Docker-Distribution-API-Version", "registry/2.0")
+///                 .body("")
+///                 .unwrap();
+///             Ok(response.into())
+///         })
+///     }
+/// }
+///
+/// let client = Client::configure().transport(Arc::new(AlwaysV2));
+/// ```
+pub trait Transport: fmt::Debug + Send + Sync {
+    /// Perform a single HTTP exchange.
+    fn execute(&self, request: reqwest::Request) -> BoxFuture<'_, Result<reqwest::Response>>;
+}
+
+/// Default transport, sending requests with a `reqwest::Client`.
+///
+/// The `reqwest::Client` must be configured not to follow redirects.
+#[derive(Clone, Debug)]
+pub struct ReqwestTransport {
+    client: reqwest::Client,
+}
+
+impl ReqwestTransport {
+    /// Create a transport sending requests with the given client.
+    pub fn new(client: reqwest::Client) -> Self {
+        Self { client }
+    }
+}
+
+impl Transport for ReqwestTransport {
+    fn execute(&self, request: reqwest::Request) -> BoxFuture<'_, Result<reqwest::Response>> {
+        Box::pin(async move { Ok(self.client.execute(request).await?) })
+    }
+}
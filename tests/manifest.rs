@@ -30,7 +30,7 @@ fn deserialize_manifest_v2s2_config(
     let config_blob = {
         let f = fs::File::open(format!(
             "tests/fixtures/quay.io_v2_openshift-release-dev_ocp-release_manifests_4.1.0-rc.9/{}",
-            &manifest_spec.config().digest.replace(":", "_")
+            manifest_spec.config().digest.to_string().replace(":", "_")
         ))
         .expect("Missing fixture");
         serde_json::from_reader::<_, dkregistry::v2::manifest::ConfigBlob>(f)?
@@ -106,3 +106,169 @@ fn test_labels_manifest_v2s1_signed() {
     assert_eq!(expected_labels_0, labels_0);
     assert_eq!(None, manif.get_labels(1));
 }
+
+#[test]
+fn test_verify_manifest_v2s1_signatures() {
+    use dkregistry::v2::manifest::Schema1Signatures;
+
+    for (fixture, key_ids) in [
+        (
+            "tests/fixtures/quayio_steveej_cincinnati-test-labels_dkregistry-test.json",
+            vec!["O66X:ZF3C:6AWO:CLIU:W6H2:7D2C:KYOO:65OM:RBER:WMAQ:LQWE:NEVJ"],
+        ),
+        (
+            "tests/fixtures/quayio_coreos_etcd_latest.json",
+            vec!["APPZ:T7DD:SEFA:4SW2:K7ZW:YZK5:PEST:4XSB:NQSH:XF6R:WN57:UF3M"],
+        ),
+        // Signed with the key of an x5c certificate chain instead of a JWK.
+        (
+            "tests/fixtures/manifest_v2_s1_x5c.json",
+            vec!["A5MU:TFSO:ZVF7:5ENV:NIZY:BOT6:BYG3:HDAM:Z47W:PFIT:VOPI:4JWW"],
+        ),
+    ] {
+        let bytes = fs::read(fixture).expect("Missing fixture");
+        let verified = Schema1Signatures::verify(&bytes).unwrap();
+        assert_eq!(verified.key_ids(), key_ids.as_slice(), "{}", fixture);
+    }
+}
+
+#[test]
+fn test_verify_manifest_v2s1_tampered() {
+    use dkregistry::errors::Error;
+    use dkregistry::v2::manifest::{signed_payload, Schema1Signatures, SignatureError};
+
+    let fixture = "tests/fixtures/quayio_steveej_cincinnati-test-labels_dkregistry-test.json";
+    let bytes = fs::read(fixture).expect("Missing fixture");
+    let payload = signed_payload(&bytes).unwrap();
+    assert!(bytes.starts_with(&payload[..payload.len() - 2]));
+
+    // Changing the signed part of the manifest invalidates the signature.
+    let tampered = String::from_utf8(bytes.clone())
+        .unwrap()
+        .replacen("\"amd64\"", "\"arm64\"", 1);
+    match Schema1Signatures::verify(tampered.as_bytes()) {
+        Err(Error::Signature(SignatureError::Invalid(_))) => {}
+        r => panic!("expected an invalid signature, got {:?}", r),
+    }
+
+    // The stated key ID must match the embedded key.
+    let mismatched = String::from_utf8(bytes)
+        .unwrap()
+        .replacen("O66X:ZF3C", "AAAA:ZF3C", 1);
+    match Schema1Signatures::verify(mismatched.as_bytes()) {
+        Err(Error::Signature(SignatureError::KeyIdMismatch { .. })) => {}
+        r => panic!("expected a key ID mismatch, got {:?}", r),
+    }
+
+    // Certificates of x5c chains must be valid.
+    let bytes = fs::read("tests/fixtures/manifest_v2_s1_x5c.json").expect("Missing fixture");
+    let corrupted = String::from_utf8(bytes).unwrap().replacen(
+        "\"x5c\": [\n               \"",
+        "\"x5c\": [\n               \"AAAA",
+        1,
+    );
+    match Schema1Signatures::verify(corrupted.as_bytes()) {
+        Err(Error::Signature(SignatureError::InvalidCertificate)) => {}
+        r => panic!("expected an invalid certificate, got {:?}", r),
+    }
+
+    // Reformatted manifests no longer match their signed length.
+    let bytes = fs::read("tests/fixtures/manifest_v2_s1.json").expect("Missing fixture");
+    match Schema1Signatures::verify(&bytes) {
+        Err(Error::Signature(SignatureError::FormatLength(6628))) => {}
+        r => panic!("expected a format length error, got {:?}", r),
+    }
+}
+
+#[test]
+fn test_convert_manifest_v2s1_to_v2s2() {
+    use dkregistry::v2::manifest::{LayerDiff, ManifestSchema1Signed};
+    use dkregistry::v2::DigestAlgorithm;
+
+    let f =
+        fs::File::open("tests/fixtures/quayio_coreos_etcd_latest.json").expect("Missing fixture");
+    let manif: ManifestSchema1Signed = serde_json::from_reader(f).unwrap();
+
+    // The two topmost layers are throwaway ones.
+    let layers = manif.non_empty_layers().unwrap();
+    assert_eq!(layers.len(), 6);
+    assert_eq!(
+        layers.last().unwrap().to_string(),
+        "sha256:b5fe2ffa645a65600cd5f5ddda9a38626a4f59dae815e4b07395cfb0b7eb8af4"
+    );
+
+    let diffs: Vec<LayerDiff> = (0..layers.len())
+        .map(|i| LayerDiff {
+            size: i as u64,
+            diff_id: DigestAlgorithm::Sha256.digest(&[i as u8]),
+        })
+        .collect();
+    assert!(manif.to_schema2(&diffs[1..]).is_err());
+    let (spec, config) = manif.to_schema2(&diffs).unwrap();
+
+    assert_eq!(
+        spec.media_type(),
+        "application/vnd.docker.distribution.manifest.v2+json"
+    );
+    assert_eq!(spec.config().size, config.len() as u64);
+    assert_eq!(
+        spec.config().digest,
+        DigestAlgorithm::Sha256.digest(&config)
+    );
+    let value = serde_json::to_value(&spec).unwrap();
+    let digests: Vec<&str> = value["layers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|l| l["digest"].as_str().unwrap())
+        .collect();
+    let expected: Vec<String> = layers.iter().map(|d| d.to_string()).collect();
+    assert_eq!(digests, expected);
+
+    let config: serde_json::Value = serde_json::from_slice(&config).unwrap();
+    assert_eq!(config["architecture"], "amd64");
+    assert_eq!(config["config"]["Cmd"][0], "/usr/local/bin/etcd");
+    assert!(config.get("id").is_none());
+    assert_eq!(config["rootfs"]["type"], "layers");
+    assert_eq!(
+        config["rootfs"]["diff_ids"][0],
+        diffs[0].diff_id.to_string().as_str()
+    );
+    let history = config["history"].as_array().unwrap();
+    assert_eq!(history.len(), 8);
+    assert_eq!(
+        history.iter().filter(|h| h["empty_layer"] == true).count(),
+        2
+    );
+}
+
+#[test]
+fn test_convert_manifest_v2s2_oci() {
+    use dkregistry::v2::manifest::ManifestSchema2Spec;
+
+    let f = fs::File::open("tests/fixtures/manifest_v2_s2.json").expect("Missing fixture");
+    let spec: ManifestSchema2Spec = serde_json::from_reader(f).unwrap();
+
+    let oci = spec.to_oci().unwrap();
+    assert_eq!(
+        oci.media_type(),
+        "application/vnd.oci.image.manifest.v1+json"
+    );
+    assert_eq!(
+        oci.config().media_type,
+        "application/vnd.oci.image.config.v1+json"
+    );
+    let value = serde_json::to_value(&oci).unwrap();
+    for layer in value["layers"].as_array().unwrap() {
+        assert_eq!(
+            layer["mediaType"],
+            "application/vnd.oci.image.layer.v1.tar+gzip"
+        );
+    }
+
+    let back = oci.to_schema2().unwrap();
+    assert_eq!(
+        serde_json::to_value(&back).unwrap(),
+        serde_json::to_value(&spec).unwrap()
+    );
+}
@@ -0,0 +1,43 @@
+use dkregistry::mediatypes::MediaTypes;
+use std::str::FromStr;
+
+#[test]
+fn media_types_roundtrip() {
+    for media_type in &[
+        MediaTypes::ManifestV2S1Signed,
+        MediaTypes::ManifestV2S2,
+        MediaTypes::ManifestList,
+        MediaTypes::ContainerConfigV1,
+        MediaTypes::ImageLayerTar,
+        MediaTypes::OciImageManifest,
+        MediaTypes::OciImageIndex,
+        MediaTypes::OciImageConfig,
+        MediaTypes::OciImageLayerTgz,
+        MediaTypes::OciImageLayerTar,
+        MediaTypes::ApplicationJson,
+    ] {
+        let mime = media_type.to_mime();
+        assert_eq!(&MediaTypes::from_mime(&mime).unwrap(), media_type);
+        assert_eq!(
+            &MediaTypes::from_str(&media_type.to_string()).unwrap(),
+            media_type
+        );
+    }
+}
+
+#[test]
+fn uncompressed_layers_from_mime() {
+    for (mime, expected) in &[
+        (
+            "application/vnd.docker.image.rootfs.diff.tar",
+            MediaTypes::ImageLayerTar,
+        ),
+        (
+            "application/vnd.oci.image.layer.v1.tar",
+            MediaTypes::OciImageLayerTar,
+        ),
+    ] {
+        let mime: mime::Mime = mime.parse().unwrap();
+        assert_eq!(&MediaTypes::from_mime(&mime).unwrap(), expected);
+    }
+}
@@ -0,0 +1,169 @@
+extern crate dkregistry;
+extern crate mockito;
+extern crate tokio;
+
+use self::mockito::mock;
+use self::tokio::runtime::Runtime;
+use dkregistry::errors::Error;
+use dkregistry::v2::{Digest, ErrorCode};
+use futures::stream::StreamExt;
+
+type Fallible<T> = Result<T, Box<dyn std::error::Error>>;
+
+#[test]
+fn test_manifest_unknown() -> Fallible<()> {
+    let addr = mockito::server_address().to_string();
+    let name = "my-repo/missing";
+    let ep = format!("/v2/{}/manifests/nope", name);
+    let _m = mock("GET", ep.as_str())
+        .with_status(404)
+        .with_header("Content-Type", "application/json")
+        .with_body(
+            r#"{"errors":[{"code":"MANIFEST_UNKNOWN","message":"manifest unknown","detail":{"Tag":"nope"}}]}"#,
+        )
+        .create();
+
+    let runtime = Runtime::new()?;
+    let dclient = dkregistry::v2::Client::configure()
+        .registry(&addr)
+        .insecure_registry(true)
+        .build()?;
+
+    let err = match runtime.block_on(dclient.get_manifest(name, "nope")) {
+        Err(Error::Registry(err)) => err,
+        r => return Err(format!("expected Registry error, got {:?}", r).into()),
+    };
+    assert_eq!(err.method, reqwest::Method::GET);
+    assert_eq!(err.url.path(), ep);
+    assert_eq!(err.repository.as_deref(), Some(name));
+    assert_eq!(err.status, reqwest::StatusCode::NOT_FOUND);
+    assert!(err.has_code(&ErrorCode::ManifestUnknown));
+    assert_eq!(err.errors[0].message, "manifest unknown");
+    assert!(err
+        .to_string()
+        .ends_with("MANIFEST_UNKNOWN: manifest unknown"));
+
+    mockito::reset();
+    Ok(())
+}
+
+#[test]
+fn test_blob_denied() -> Fallible<()> {
+    let addr = mockito::server_address().to_string();
+    let name = "my-repo/private";
+    let digest: Digest =
+        "sha256:0000000000000000000000000000000000000000000000000000000000000000".parse()?;
+    let ep = format!("/v2/{}/blobs/{}", name, digest);
+    let _m = mock("GET", ep.as_str())
+        .with_status(403)
+        .with_body(r#"{"errors":[{"code":"DENIED","message":"requested access to the resource is denied"}]}"#)
+        .create();
+
+    let runtime = Runtime::new()?;
+    let dclient = dkregistry::v2::Client::configure()
+        .registry(&addr)
+        .insecure_registry(true)
+        .build()?;
+
+    match runtime.block_on(dclient.get_blob(name, &digest)) {
+        Err(Error::Registry(err)) => {
+            assert!(err.has_code(&ErrorCode::Denied));
+            assert_eq!(err.status, reqwest::StatusCode::FORBIDDEN);
+        }
+        r => return Err(format!("expected Registry error, got {:?}", r).into()),
+    };
+
+    mockito::reset();
+    Ok(())
+}
+
+#[test]
+fn test_tags_unparsable_error_body() -> Fallible<()> {
+    let addr = mockito::server_address().to_string();
+    let name = "my-repo/broken";
+    let ep = format!("/v2/{}/tags/list", name);
+    let _m = mock("GET", ep.as_str())
+        .with_status(502)
+        .with_body("<html>Bad Gateway</html>")
+        .create();
+
+    let runtime = Runtime::new()?;
+    let dclient = dkregistry::v2::Client::configure()
+        .registry(&addr)
+        .insecure_registry(true)
+        .build()?;
+
+    let mut tags = Box::pin(dclient.get_tags(name, None));
+    let res = runtime.block_on(tags.next());
+    match res {
+        Some(Err(Error::Registry(err))) => {
+            assert_eq!(err.status, reqwest::StatusCode::BAD_GATEWAY);
+            assert_eq!(err.repository.as_deref(), Some(name));
+            assert!(err.errors.is_empty());
+        }
+        r => return Err(format!("expected Registry error, got {:?}", r).into()),
+    };
+
+    mockito::reset();
+    Ok(())
+}
+
+#[test]
+fn test_v2_endpoint_denied() -> Fallible<()> {
+    let addr = mockito::server_address().to_string();
+    let _m = mock("GET", "/v2/")
+        .with_status(403)
+        .with_body(r#"{"errors":[{"code":"DENIED","message":"access denied"}]}"#)
+        .create();
+
+    let runtime = Runtime::new()?;
+    let dclient = dkregistry::v2::Client::configure()
+        .registry(&addr)
+        .insecure_registry(true)
+        .build()?;
+
+    match runtime.block_on(dclient.is_auth()) {
+        Err(Error::Registry(err)) => {
+            assert!(err.has_code(&ErrorCode::Denied));
+            assert_eq!(err.status, reqwest::StatusCode::FORBIDDEN);
+            assert_eq!(err.repository, None);
+        }
+        r => return Err(format!("expected Registry error, got {:?}", r).into()),
+    };
+    match runtime.block_on(dclient.is_v2_supported_and_authorized()) {
+        Err(Error::Registry(err)) => assert!(err.has_code(&ErrorCode::Denied)),
+        r => return Err(format!("expected Registry error, got {:?}", r).into()),
+    };
+    assert!(!runtime.block_on(dclient.is_v2_supported())?);
+
+    mockito::reset();
+    Ok(())
+}
+
+#[test]
+fn test_has_blob_denied() -> Fallible<()> {
+    let addr = mockito::server_address().to_string();
+    let name = "my-repo/head-denied";
+    let digest: Digest =
+        "sha256:0000000000000000000000000000000000000000000000000000000000000000".parse()?;
+    let ep = format!("/v2/{}/blobs/{}", name, digest);
+    let _m = mock("HEAD", ep.as_str()).with_status(403).create();
+
+    let runtime = Runtime::new()?;
+    let dclient = dkregistry::v2::Client::configure()
+        .registry(&addr)
+        .insecure_registry(true)
+        .build()?;
+
+    match runtime.block_on(dclient.has_blob(name, &digest)) {
+        Err(Error::Registry(err)) => {
+            assert_eq!(err.method, reqwest::Method::HEAD);
+            assert_eq!(err.status, reqwest::StatusCode::FORBIDDEN);
+            assert_eq!(err.repository.as_deref(), Some(name));
+        }
+        r => return Err(format!("expected Registry error, got {:?}", r).into()),
+    };
+
+    mockito::reset();
+    Ok(())
+}
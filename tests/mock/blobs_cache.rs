@@ -0,0 +1,183 @@
+extern crate dkregistry;
+extern crate mockito;
+extern crate tokio;
+
+use self::mockito::mock;
+use self::tokio::runtime::Runtime;
+use dkregistry::v2::{BlobCache, Digest, DigestAlgorithm};
+use futures::stream::TryStreamExt;
+
+type Fallible<T> = Result<T, Box<dyn std::error::Error>>;
+
+fn digest(blob: &[u8]) -> Digest {
+    DigestAlgorithm::Sha256.digest(blob)
+}
+
+#[test]
+fn test_blob_cached() -> Fallible<()> {
+    let addr = mockito::server_address().to_string();
+    let name = "my-repo/cached";
+    let blob = b"cached blob";
+    let streamed = b"streamed blob";
+
+    let m_blob = mock(
+        "GET",
+        format!("/v2/{}/blobs/{}", name, digest(blob)).as_str(),
+    )
+    .with_status(200)
+    .with_body(blob)
+    .expect(1)
+    .create();
+    let m_streamed = mock(
+        "GET",
+        format!("/v2/{}/blobs/{}", name, digest(streamed)).as_str(),
+    )
+    .with_status(200)
+    .with_body(streamed)
+    .expect(1)
+    .create();
+
+    let dir = tempfile::tempdir()?;
+    let runtime = Runtime::new()?;
+    let dclient = dkregistry::v2::Client::configure()
+        .registry(&addr)
+        .insecure_registry(true)
+        .blob_cache(BlobCache::new(dir.path()))
+        .build()?;
+
+    for _ in 0..2 {
+        let result = runtime.block_on(dclient.get_blob(name, &digest(blob)))?;
+        assert_eq!(blob, result.as_slice());
+
+        let stream = runtime.block_on(dclient.get_blob_stream(name, &digest(streamed)))?;
+        let result: Vec<Vec<u8>> = runtime.block_on(stream.map_ok(|c| c.to_vec()).try_collect())?;
+        assert_eq!(streamed.to_vec(), result.concat());
+    }
+    let range = runtime.block_on(dclient.get_blob_range(name, &digest(blob), 7..))?;
+    assert_eq!(b"blob", range.as_slice());
+
+    m_blob.assert();
+    m_streamed.assert();
+
+    mockito::reset();
+    Ok(())
+}
+
+#[test]
+fn test_corrupted_blob_evicted() -> Fallible<()> {
+    let addr = mockito::server_address().to_string();
+    let name = "my-repo/corrupted";
+    let blob = b"pristine blob";
+
+    let m_blob = mock(
+        "GET",
+        format!("/v2/{}/blobs/{}", name, digest(blob)).as_str(),
+    )
+    .with_status(200)
+    .with_body(blob)
+    .expect(0)
+    .create();
+
+    let dir = tempfile::tempdir()?;
+    let cache = BlobCache::new(dir.path());
+    let runtime = Runtime::new()?;
+    runtime.block_on(cache.insert(&digest(blob), blob))?;
+    let entry = dir.path().join("blobs/sha256").join(digest(blob).encoded());
+    std::fs::write(&entry, b"tampered blob")?;
+
+    let dclient = dkregistry::v2::Client::configure()
+        .registry(&addr)
+        .insecure_registry(true)
+        .blob_cache(cache.clone())
+        .build()?;
+
+    let range = runtime.block_on(dclient.get_blob_range(name, &digest(blob), 2..=5))?;
+    assert_eq!(b"mper", range.as_slice());
+
+    let stream = runtime.block_on(dclient.get_blob_stream(name, &digest(blob)))?;
+    let result: Result<Vec<_>, _> = runtime.block_on(stream.try_collect());
+    assert!(result.is_err());
+    assert!(!cache.contains(&digest(blob)));
+
+    m_blob.assert();
+
+    mockito::reset();
+    Ok(())
+}
+
+#[test]
+fn test_manifest_by_digest_cached() -> Fallible<()> {
+    let addr = mockito::server_address().to_string();
+    let name = "my-repo/cached-manifest";
+    let manifest = br#"{"schemaVersion":2,"mediaType":"application/vnd.docker.distribution.manifest.list.v2+json","manifests":[]}"#;
+
+    let m_manifest = mock(
+        "GET",
+        format!("/v2/{}/manifests/{}", name, digest(manifest)).as_str(),
+    )
+    .with_status(200)
+    .with_header(
+        "Content-Type",
+        "application/vnd.docker.distribution.manifest.list.v2+json",
+    )
+    .with_body(&manifest[..])
+    .expect(1)
+    .create();
+
+    let dir = tempfile::tempdir()?;
+    let runtime = Runtime::new()?;
+    let dclient = dkregistry::v2::Client::configure()
+        .registry(&addr)
+        .insecure_registry(true)
+        .blob_cache(BlobCache::new(dir.path()))
+        .build()?;
+
+    for _ in 0..2 {
+        let result = runtime.block_on(dclient.get_manifest(name, digest(manifest).as_str()))?;
+        assert!(matches!(result, dkregistry::v2::manifest::Manifest::ML(_)));
+    }
+    m_manifest.assert();
+
+    mockito::reset();
+    Ok(())
+}
+
+#[test]
+fn test_schema1_manifest_by_digest_cached() -> Fallible<()> {
+    use dkregistry::v2::manifest::signed_payload;
+
+    let addr = mockito::server_address().to_string();
+    let name = "my-repo/cached-schema1";
+    let manifest =
+        std::fs::read("tests/fixtures/quayio_steveej_cincinnati-test-labels_dkregistry-test.json")?;
+    // Schema 1 manifests are cached by the digest of their payload.
+    let digest = digest(&signed_payload(&manifest)?);
+
+    let m_manifest = mock("GET", format!("/v2/{}/manifests/{}", name, digest).as_str())
+        .with_status(200)
+        .with_header(
+            "Content-Type",
+            "application/vnd.docker.distribution.manifest.v1+prettyjws",
+        )
+        .with_body(&manifest)
+        .expect(1)
+        .create();
+
+    let dir = tempfile::tempdir()?;
+    let runtime = Runtime::new()?;
+    let dclient = dkregistry::v2::Client::configure()
+        .registry(&addr)
+        .insecure_registry(true)
+        .blob_cache(BlobCache::new(dir.path()))
+        .build()?;
+
+    for _ in 0..2 {
+        let raw = runtime.block_on(dclient.get_manifest_raw(name, digest.as_str()))?;
+        assert_eq!(raw.bytes, manifest);
+        assert_eq!(raw.digest, digest);
+    }
+    m_manifest.assert();
+
+    mockito::reset();
+    Ok(())
+}
@@ -1,11 +1,10 @@
 extern crate dkregistry;
 extern crate mockito;
-extern crate sha2;
 extern crate tokio;
 
 use self::mockito::mock;
 use self::tokio::runtime::Runtime;
-use crate::mock::blobs_download::sha2::Digest;
+use dkregistry::v2::DigestAlgorithm;
 use futures::stream::StreamExt;
 use futures::FutureExt;
 
@@ -14,7 +13,7 @@ type Fallible<T> = Result<T, Box<dyn std::error::Error>>;
 #[test]
 fn test_blobs_has_layer() {
     let name = "my-repo/my-image";
-    let digest = "fakedigest";
+    let digest = DigestAlgorithm::Sha256.digest(b"layer");
     let binary_digest = "binarydigest";
 
     let ep = format!("/v2/{}/blobs/{}", name, digest);
@@ -34,7 +33,7 @@ fn test_blobs_has_layer() {
         .build()
         .unwrap();
 
-    let futcheck = dclient.has_blob(name, digest);
+    let futcheck = dclient.has_blob(name, &digest);
 
     let res = runtime.block_on(futcheck).unwrap();
     assert_eq!(res, true);
@@ -45,7 +44,7 @@ fn test_blobs_has_layer() {
 #[test]
 fn test_blobs_hasnot_layer() {
     let name = "my-repo/my-image";
-    let digest = "fakedigest";
+    let digest = DigestAlgorithm::Sha256.digest(b"layer");
 
     let ep = format!("/v2/{}/blobs/{}", name, digest);
     let addr = mockito::server_address().to_string();
@@ -60,7 +59,7 @@ fn test_blobs_hasnot_layer() {
         .build()
         .unwrap();
 
-    let futcheck = dclient.has_blob(name, digest);
+    let futcheck = dclient.has_blob(name, &digest);
 
     let res = runtime.block_on(futcheck).unwrap();
     assert_eq!(res, false);
@@ -74,7 +73,7 @@ fn get_blobs_succeeds_with_consistent_layer() -> Fallible<()> {
 
     let name = "my-repo/my-image";
     let blob = b"hello";
-    let digest = format!("sha256:{:x}", sha2::Sha256::digest(blob));
+    let digest = DigestAlgorithm::Sha256.digest(blob);
 
     let ep = format!("/v2/{}/blobs/{}", &name, &digest);
     let _m = mock("GET", ep.as_str())
@@ -107,7 +106,7 @@ fn get_blobs_fails_with_inconsistent_layer() -> Fallible<()> {
     let name = "my-repo/my-image";
     let blob = b"hello";
     let blob2 = b"hello2";
-    let digest = format!("sha256:{:x}", sha2::Sha256::digest(blob));
+    let digest = DigestAlgorithm::Sha256.digest(blob);
 
     let ep = format!("/v2/{}/blobs/{}", &name, &digest);
     let _m = mock("GET", ep.as_str())
@@ -140,7 +139,7 @@ fn get_blobs_stream() -> Fallible<()> {
 
     let name = "my-repo/my-image";
     let blob = b"hello";
-    let digest = format!("sha256:{:x}", sha2::Sha256::digest(blob));
+    let digest = DigestAlgorithm::Sha256.digest(blob);
 
     let ep = format!("/v2/{}/blobs/{}", &name, &digest);
     let _m = mock("GET", ep.as_str())
@@ -168,3 +167,122 @@ fn get_blobs_stream() -> Fallible<()> {
     mockito::reset();
     Ok(())
 }
+
+#[test]
+fn get_blobs_stream_collect() -> Fallible<()> {
+    let addr = mockito::server_address().to_string();
+
+    let name = "my-repo/stream-collect";
+    let blob = b"hello";
+    let digest = DigestAlgorithm::Sha256.digest(blob);
+
+    let ep = format!("/v2/{}/blobs/{}", name, digest);
+    let _m = mock("GET", ep.as_str())
+        .with_status(200)
+        .with_body(blob)
+        .create();
+
+    let runtime = Runtime::new()?;
+    let dclient = dkregistry::v2::Client::configure()
+        .registry(&addr)
+        .insecure_registry(true)
+        .build()?;
+
+    let blob_resp = runtime.block_on(dclient.get_blob_response(name, &digest))?;
+    let chunks: Vec<_> = runtime.block_on(blob_resp.stream().collect());
+    let mut received_blob = Vec::new();
+    for chunk in chunks {
+        received_blob.extend_from_slice(&chunk?);
+    }
+    assert_eq!(blob.to_vec(), received_blob);
+
+    mockito::reset();
+    Ok(())
+}
+
+#[test]
+fn download_blob_to_writer() -> Fallible<()> {
+    let addr = mockito::server_address().to_string();
+
+    let name = "my-repo/download-to";
+    let blob = b"downloaded to a writer";
+    let digest = DigestAlgorithm::Sha256.digest(blob);
+
+    let ep = format!("/v2/{}/blobs/{}", name, digest);
+    let _m = mock("GET", ep.as_str())
+        .with_status(200)
+        .with_body(blob)
+        .create();
+
+    let runtime = Runtime::new()?;
+    let dclient = dkregistry::v2::Client::configure()
+        .registry(&addr)
+        .insecure_registry(true)
+        .build()?;
+
+    let mut written = Vec::new();
+    let size = runtime.block_on(dclient.download_blob_to(name, &digest, &mut written))?;
+    assert_eq!(size, blob.len() as u64);
+    assert_eq!(written, blob);
+
+    mockito::reset();
+    Ok(())
+}
+
+#[test]
+fn download_blob_to_path() -> Fallible<()> {
+    let addr = mockito::server_address().to_string();
+
+    let name = "my-repo/download-to-path";
+    let blob = b"downloaded to a file";
+    let digest = DigestAlgorithm::Sha256.digest(blob);
+
+    let ep = format!("/v2/{}/blobs/{}", name, digest);
+    let _m = mock("GET", ep.as_str())
+        .with_status(200)
+        .with_body(blob)
+        .create();
+
+    let dir = tempfile::tempdir()?;
+    let runtime = Runtime::new()?;
+    let dclient = dkregistry::v2::Client::configure()
+        .registry(&addr)
+        .insecure_registry(true)
+        .build()?;
+
+    let path = dir.path().join("blob");
+    let size = runtime.block_on(dclient.download_blob_to_path(
+        name,
+        &digest,
+        Some(blob.len() as u64),
+        &path,
+    ))?;
+    assert_eq!(size, blob.len() as u64);
+    assert_eq!(std::fs::read(&path)?, blob);
+    assert_eq!(std::fs::read_dir(dir.path())?.count(), 1);
+
+    // A size not matching the descriptor leaves no file behind.
+    let other = dir.path().join("other");
+    let res = runtime.block_on(dclient.download_blob_to_path(name, &digest, Some(3), &other));
+    match res {
+        Err(dkregistry::errors::Error::SizeMismatch { expected: 3, .. }) => {}
+        r => panic!("unexpected result {:?}", r),
+    }
+    assert!(!other.exists());
+    assert_eq!(std::fs::read_dir(dir.path())?.count(), 1);
+
+    // Concurrent downloads to the same path do not share a partial file.
+    let size = Some(blob.len() as u64);
+    let (first, second) = runtime.block_on(async {
+        futures::join!(
+            dclient.download_blob_to_path(name, &digest, size, &path),
+            dclient.download_blob_to_path(name, &digest, size, &path)
+        )
+    });
+    assert_eq!((first?, second?), (blob.len() as u64, blob.len() as u64));
+    assert_eq!(std::fs::read(&path)?, blob);
+    assert_eq!(std::fs::read_dir(dir.path())?.count(), 1);
+
+    mockito::reset();
+    Ok(())
+}
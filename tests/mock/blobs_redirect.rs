@@ -0,0 +1,156 @@
+extern crate dkregistry;
+extern crate mockito;
+extern crate tokio;
+
+use self::mockito::{mock, Matcher};
+use self::tokio::runtime::Runtime;
+use dkregistry::v2::DigestAlgorithm;
+
+type Fallible<T> = Result<T, Box<dyn std::error::Error>>;
+
+// base64("user:pass")
+static BASIC_AUTH: &str = "Basic dXNlcjpwYXNz";
+
+/// Build a client authenticated with basic auth against the mock registry.
+fn authenticated_client(runtime: &Runtime) -> Fallible<dkregistry::v2::Client> {
+    let addr = mockito::server_address().to_string();
+    let _m = mock("GET", "/v2/")
+        .with_status(401)
+        .with_header("WWW-Authenticate", "Basic realm=\"mock\"")
+        .create();
+
+    let dclient = dkregistry::v2::Client::configure()
+        .registry(&addr)
+        .insecure_registry(true)
+        .username(Some("user".to_string()))
+        .password(Some("pass".to_string()))
+        .build()?;
+    Ok(runtime.block_on(dclient.authenticate(&[]))?)
+}
+
+/// URL of the mock server on a different origin than the registry one.
+fn storage_url(path: &str) -> String {
+    format!(
+        "http://localhost:{}{}",
+        mockito::server_address().port(),
+        path
+    )
+}
+
+#[test]
+fn get_blob_cross_origin_redirect() -> Fallible<()> {
+    let name = "my-repo/redirected";
+    let blob = b"hello redirected world";
+    let digest = DigestAlgorithm::Sha256.digest(blob);
+    let storage_path = format!("/storage/{}?signature=abc", digest);
+
+    let runtime = Runtime::new()?;
+    let dclient = authenticated_client(&runtime)?;
+
+    let ep = format!("/v2/{}/blobs/{}", name, digest);
+    let m_registry = mock("GET", ep.as_str())
+        .match_header("authorization", BASIC_AUTH)
+        .with_status(307)
+        .with_header("Location", &storage_url(&storage_path))
+        .create();
+    let m_storage = mock("GET", storage_path.as_str())
+        .match_header("authorization", Matcher::Missing)
+        .with_status(200)
+        .with_body(blob)
+        .create();
+
+    let resp = runtime.block_on(dclient.get_blob_response(name, &digest))?;
+    assert_eq!(resp.url().as_str(), storage_url(&storage_path));
+    let result = runtime.block_on(resp.bytes())?;
+    assert_eq!(blob, result.as_slice());
+
+    m_registry.assert();
+    m_storage.assert();
+
+    mockito::reset();
+    Ok(())
+}
+
+#[test]
+fn get_blob_same_origin_redirect() -> Fallible<()> {
+    let name = "my-repo/moved";
+    let blob = b"hello moved world";
+    let digest = DigestAlgorithm::Sha256.digest(blob);
+    let storage_path = format!("/v2/{}/blobs/{}", "my-repo/storage", digest);
+
+    let runtime = Runtime::new()?;
+    let dclient = authenticated_client(&runtime)?;
+
+    let ep = format!("/v2/{}/blobs/{}", name, digest);
+    let _m_registry = mock("GET", ep.as_str())
+        .with_status(302)
+        .with_header("Location", &storage_path)
+        .create();
+    let m_storage = mock("GET", storage_path.as_str())
+        .match_header("authorization", BASIC_AUTH)
+        .with_status(200)
+        .with_body(blob)
+        .create();
+
+    let result = runtime.block_on(dclient.get_blob(name, &digest))?;
+    assert_eq!(blob, result.as_slice());
+    m_storage.assert();
+
+    mockito::reset();
+    Ok(())
+}
+
+#[test]
+fn get_blob_url_without_download() -> Fallible<()> {
+    let name = "my-repo/presigned";
+    let blob = b"hello presigned world";
+    let digest = DigestAlgorithm::Sha256.digest(blob);
+    let storage_path = format!("/storage/{}?signature=def", digest);
+
+    let runtime = Runtime::new()?;
+    let dclient = authenticated_client(&runtime)?;
+
+    let ep = format!("/v2/{}/blobs/{}", name, digest);
+    let _m_registry = mock("GET", ep.as_str())
+        .with_status(307)
+        .with_header("Location", &storage_url(&storage_path))
+        .create();
+    let m_storage = mock("GET", storage_path.as_str())
+        .with_status(200)
+        .with_body(blob)
+        .expect(0)
+        .create();
+
+    let url = runtime.block_on(dclient.get_blob_url(name, &digest))?;
+    assert_eq!(url.as_str(), storage_url(&storage_path));
+    m_storage.assert();
+
+    mockito::reset();
+    Ok(())
+}
+
+#[test]
+fn get_blob_url_served_by_registry() -> Fallible<()> {
+    let addr = mockito::server_address().to_string();
+    let name = "my-repo/direct";
+    let blob = b"hello direct world";
+    let digest = DigestAlgorithm::Sha256.digest(blob);
+
+    let ep = format!("/v2/{}/blobs/{}", name, digest);
+    let _m = mock("GET", ep.as_str())
+        .with_status(200)
+        .with_body(blob)
+        .create();
+
+    let runtime = Runtime::new()?;
+    let dclient = dkregistry::v2::Client::configure()
+        .registry(&addr)
+        .insecure_registry(true)
+        .build()?;
+
+    let url = runtime.block_on(dclient.get_blob_url(name, &digest))?;
+    assert_eq!(url.as_str(), format!("http://{}{}", addr, ep));
+
+    mockito::reset();
+    Ok(())
+}
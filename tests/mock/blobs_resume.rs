@@ -0,0 +1,151 @@
+extern crate dkregistry;
+extern crate mockito;
+extern crate tokio;
+
+use self::mockito::mock;
+use self::tokio::runtime::Runtime;
+use dkregistry::v2::DigestAlgorithm;
+use dkregistry::v2::RetryPolicy;
+use futures::stream::TryStreamExt;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::time::Duration;
+
+type Fallible<T> = Result<T, Box<dyn std::error::Error>>;
+
+#[test]
+fn get_blob_range() -> Fallible<()> {
+    let addr = mockito::server_address().to_string();
+
+    let name = "my-repo/ranged";
+    let blob = b"hello world";
+    let digest = DigestAlgorithm::Sha256.digest(blob);
+
+    let ep = format!("/v2/{}/blobs/{}", name, digest);
+    let _m = mock("GET", ep.as_str())
+        .match_header("range", "bytes=6-10")
+        .with_status(206)
+        .with_header("Content-Range", "bytes 6-10/11")
+        .with_body(&blob[6..])
+        .create();
+
+    let runtime = Runtime::new()?;
+    let dclient = dkregistry::v2::Client::configure()
+        .registry(&addr)
+        .insecure_registry(true)
+        .build()?;
+
+    let result = runtime.block_on(dclient.get_blob_range(name, &digest, 6..11))?;
+    assert_eq!(b"world", result.as_slice());
+
+    mockito::reset();
+    Ok(())
+}
+
+#[test]
+fn get_blob_range_unsupported() -> Fallible<()> {
+    let addr = mockito::server_address().to_string();
+
+    let name = "my-repo/not-ranged";
+    let blob = b"hello world";
+    let digest = DigestAlgorithm::Sha256.digest(blob);
+
+    let ep = format!("/v2/{}/blobs/{}", name, digest);
+    let _m = mock("GET", ep.as_str())
+        .with_status(200)
+        .with_body(blob)
+        .create();
+
+    let runtime = Runtime::new()?;
+    let dclient = dkregistry::v2::Client::configure()
+        .registry(&addr)
+        .insecure_registry(true)
+        .build()?;
+
+    match runtime.block_on(dclient.get_blob_range(name, &digest, 6..)) {
+        Err(dkregistry::errors::Error::RangeUnsupported) => {}
+        r => return Err(format!("expected RangeUnsupported, got {:?}", r).into()),
+    };
+
+    mockito::reset();
+    Ok(())
+}
+
+/// Serve a blob over two connections: the first one drops after `cut` bytes,
+/// the second one must be a range request for the remainder.
+fn serve_interrupted_blob(blob: &'static [u8], cut: usize) -> Fallible<String> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?.to_string();
+
+    std::thread::spawn(move || {
+        let read_request = |stream: &std::net::TcpStream| -> Vec<String> {
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut lines = vec![];
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    return lines;
+                }
+                lines.push(line.trim().to_lowercase());
+            }
+        };
+
+        let (mut stream, _) = listener.accept().unwrap();
+        read_request(&stream);
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nAccept-Ranges: bytes\r\n\r\n",
+            blob.len()
+        )
+        .unwrap();
+        stream.write_all(&blob[..cut]).unwrap();
+        drop(stream);
+
+        let (mut stream, _) = listener.accept().unwrap();
+        let request = read_request(&stream);
+        assert!(request.contains(&format!("range: bytes={}-", cut)));
+        write!(
+            stream,
+            "HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+            blob.len() - cut,
+            cut,
+            blob.len() - 1,
+            blob.len()
+        )
+        .unwrap();
+        stream.write_all(&blob[cut..]).unwrap();
+    });
+
+    Ok(addr)
+}
+
+#[test]
+fn get_blob_stream_resumes() -> Fallible<()> {
+    let blob: &'static [u8] = b"hello resumable world";
+    let digest = DigestAlgorithm::Sha256.digest(blob);
+    let addr = serve_interrupted_blob(blob, 6)?;
+
+    let runtime = Runtime::new()?;
+    let dclient = dkregistry::v2::Client::configure()
+        .registry(&addr)
+        .insecure_registry(true)
+        .retry_policy(
+            RetryPolicy::default()
+                .max_attempts(2)
+                .base_backoff(Duration::from_millis(10)),
+        )
+        .build()?;
+
+    let chunks = runtime.block_on(async {
+        dclient
+            .get_blob_stream("my-repo/resumed", &digest)
+            .await?
+            .map_ok(|chunk| chunk.to_vec())
+            .try_concat()
+            .await
+    })?;
+    assert_eq!(blob, chunks.as_slice());
+
+    Ok(())
+}
@@ -0,0 +1,161 @@
+extern crate dkregistry;
+extern crate mockito;
+extern crate tokio;
+
+use self::mockito::{mock, Matcher};
+use self::tokio::runtime::Runtime;
+use crate::mock::mock_image;
+use dkregistry::reference::Reference;
+use dkregistry::v2::{Client, DirectorySink, PullOptions, ReferenceOptions};
+use std::str::FromStr;
+
+type Fallible<T> = Result<T, Box<dyn std::error::Error>>;
+
+// base64("user:pass")
+static BASIC_AUTH: &str = "Basic dXNlcjpwYXNz";
+
+static CONFIG: &[u8] = br#"{"architecture":"amd64","os":"linux"}"#;
+
+/// Mock a schema 2 image without layers, expecting the given authorization.
+fn mock_authorized_image(name: &str, reference: &str, authorization: &str) -> Vec<mockito::Mock> {
+    let (manifest, config) = mock_image(name, reference, CONFIG, &[]);
+    vec![
+        manifest
+            .match_header("authorization", authorization)
+            .expect(1)
+            .create(),
+        config.match_header("authorization", authorization).create(),
+    ]
+}
+
+/// Write a docker-client config file with credentials for the mock registry.
+fn credentials_file(dir: &tempfile::TempDir) -> Fallible<std::path::PathBuf> {
+    let path = dir.path().join("config.json");
+    let config = format!(
+        r#"{{"auths":{{"http://{}":{{"auth":"dXNlcjpwYXNz"}}}}}}"#,
+        mockito::server_address()
+    );
+    std::fs::write(&path, config)?;
+    Ok(path)
+}
+
+#[test]
+fn test_for_reference_basic_auth() -> Fallible<()> {
+    let addr = mockito::server_address().to_string();
+    let name = "my-repo/basic";
+    let reference = Reference::from_str(&format!("{}/{}:v1", addr, name))?;
+    let dir = tempfile::tempdir()?;
+
+    let _m_v2 = mock("GET", "/v2/")
+        .with_status(401)
+        .with_header("WWW-Authenticate", "Basic realm=\"mock\"")
+        .create();
+    let m_image = mock_authorized_image(name, "v1", BASIC_AUTH);
+
+    let options = ReferenceOptions::default()
+        .insecure_registry(&addr)
+        .credentials_file(credentials_file(&dir)?);
+    let runtime = Runtime::new()?;
+    let dclient = runtime.block_on(Client::for_reference(&reference, options))?;
+    let manifest = runtime.block_on(dclient.get_manifest_for(&reference))?;
+    assert!(manifest.layers_digests(None)?.is_empty());
+
+    m_image[0].assert();
+
+    mockito::reset();
+    Ok(())
+}
+
+#[test]
+fn test_for_reference_default_credentials_file() -> Fallible<()> {
+    let addr = mockito::server_address().to_string();
+    let name = "my-repo/default-credentials";
+    let reference = Reference::from_str(&format!("{}/{}:v1", addr, name))?;
+    let dir = tempfile::tempdir()?;
+    credentials_file(&dir)?;
+
+    let _m_v2 = mock("GET", "/v2/")
+        .with_status(401)
+        .with_header("WWW-Authenticate", "Basic realm=\"mock\"")
+        .create();
+    let m_image = mock_authorized_image(name, "v1", BASIC_AUTH);
+
+    std::env::set_var("DOCKER_CONFIG", dir.path());
+    let options = ReferenceOptions::default().insecure_registry(&addr);
+    let runtime = Runtime::new()?;
+    let dclient = runtime.block_on(Client::for_reference(&reference, options));
+    std::env::remove_var("DOCKER_CONFIG");
+    let manifest = runtime.block_on(dclient?.get_manifest_for(&reference))?;
+    assert!(manifest.layers_digests(None)?.is_empty());
+
+    m_image[0].assert();
+
+    mockito::reset();
+    Ok(())
+}
+
+#[test]
+fn test_for_reference_bearer_scope() -> Fallible<()> {
+    let addr = mockito::server_address().to_string();
+    let name = "my-repo/bearer";
+    let reference = Reference::from_str(&format!("{}/{}", addr, name))?;
+
+    let _m_v2 = mock("GET", "/v2/")
+        .with_status(401)
+        .with_header(
+            "WWW-Authenticate",
+            &format!("Bearer realm=\"http://{}/token\",service=\"mock\"", addr),
+        )
+        .create();
+    let m_token = mock("GET", "/token")
+        .match_query(Matcher::AllOf(vec![
+            Matcher::UrlEncoded("service".into(), "mock".into()),
+            Matcher::UrlEncoded("scope".into(), format!("repository:{}:pull,push", name)),
+        ]))
+        .with_status(200)
+        .with_body(r#"{"token":"secret-token"}"#)
+        .expect(1)
+        .create();
+    let m_image = mock_authorized_image(name, "latest", "Bearer secret-token");
+
+    let options = ReferenceOptions::default()
+        .config(Client::configure().insecure_registry(true))
+        .push(true);
+    let dir = tempfile::tempdir()?;
+    let runtime = Runtime::new()?;
+    let dclient = runtime.block_on(Client::for_reference(&reference, options))?;
+    let summary = runtime.block_on(dclient.pull_for(
+        &reference,
+        &DirectorySink::new(dir.path()),
+        &PullOptions::default(),
+    ))?;
+    assert!(summary.layers.is_empty());
+
+    m_token.assert();
+    m_image[0].assert();
+
+    mockito::reset();
+    Ok(())
+}
+
+#[test]
+fn test_reference_on_other_registry() -> Fallible<()> {
+    let addr = mockito::server_address().to_string();
+    let reference = Reference::from_str(&format!("{}/my-repo/other", addr))?;
+    let elsewhere = Reference::from_str("quay.io/my-repo/other")?;
+
+    let _m_v2 = mock("GET", "/v2/").with_status(200).create();
+
+    let options = ReferenceOptions::default().insecure_registry(&addr);
+    let runtime = Runtime::new()?;
+    let dclient = runtime.block_on(Client::for_reference(&reference, options))?;
+    match runtime.block_on(dclient.get_manifest_for(&elsewhere)) {
+        Err(dkregistry::errors::Error::RegistryMismatch { registry, .. }) => {
+            assert_eq!(registry, addr)
+        }
+        r => return Err(format!("expected RegistryMismatch, got {:?}", r).into()),
+    }
+
+    mockito::reset();
+    Ok(())
+}
@@ -0,0 +1,274 @@
+extern crate dkregistry;
+extern crate mockito;
+extern crate tokio;
+
+use self::mockito::mock;
+use self::tokio::runtime::Runtime;
+use dkregistry::mediatypes::MediaTypes;
+use dkregistry::v2::DigestAlgorithm;
+use futures::stream::TryStreamExt;
+
+type Fallible<T> = Result<T, Box<dyn std::error::Error>>;
+
+const MANIFEST_LIST: &[u8] = br#"{"schemaVersion":2,"mediaType":"application/vnd.docker.distribution.manifest.list.v2+json","manifests":[]}"#;
+
+fn client(addr: &str) -> dkregistry::v2::Client {
+    dkregistry::v2::Client::configure()
+        .registry(addr)
+        .insecure_registry(true)
+        .build()
+        .unwrap()
+}
+
+#[test]
+fn test_blob_sha512() -> Fallible<()> {
+    let addr = mockito::server_address().to_string();
+    let name = "my-repo/sha512";
+    let blob = b"hello";
+    let digest = DigestAlgorithm::Sha512.digest(blob);
+
+    let _m = mock("GET", format!("/v2/{}/blobs/{}", name, digest).as_str())
+        .with_status(200)
+        .with_body(&blob[..])
+        .expect(2)
+        .create();
+
+    let runtime = Runtime::new()?;
+    let dclient = client(&addr);
+
+    assert_eq!(runtime.block_on(dclient.get_blob(name, &digest))?, blob);
+    let streamed: Vec<u8> = runtime.block_on(async {
+        dclient
+            .get_blob_stream(name, &digest)
+            .await?
+            .map_ok(|chunk| chunk.to_vec())
+            .try_concat()
+            .await
+    })?;
+    assert_eq!(streamed, blob);
+
+    mockito::reset();
+    Ok(())
+}
+
+#[test]
+fn test_manifest_by_digest_sha512() -> Fallible<()> {
+    let addr = mockito::server_address().to_string();
+    let name = "my-repo/sha512-manifest";
+    let digest = DigestAlgorithm::Sha512.digest(MANIFEST_LIST);
+
+    let _m = mock("GET", format!("/v2/{}/manifests/{}", name, digest).as_str())
+        .with_status(200)
+        .with_header(
+            "Content-Type",
+            "application/vnd.docker.distribution.manifest.list.v2+json",
+        )
+        .with_body(MANIFEST_LIST)
+        .create();
+
+    let runtime = Runtime::new()?;
+    let manifest = runtime.block_on(client(&addr).get_manifest(name, digest.as_str()))?;
+    assert!(matches!(
+        manifest,
+        dkregistry::v2::manifest::Manifest::ML(_)
+    ));
+
+    mockito::reset();
+    Ok(())
+}
+
+#[test]
+fn test_manifest_by_digest_mismatch() -> Fallible<()> {
+    let addr = mockito::server_address().to_string();
+    let name = "my-repo/tampered-manifest";
+    let digest = DigestAlgorithm::Sha256.digest(b"another manifest");
+
+    let _m = mock("GET", format!("/v2/{}/manifests/{}", name, digest).as_str())
+        .with_status(200)
+        .with_header(
+            "Content-Type",
+            "application/vnd.docker.distribution.manifest.list.v2+json",
+        )
+        .with_body(MANIFEST_LIST)
+        .create();
+
+    let runtime = Runtime::new()?;
+    let err = runtime
+        .block_on(client(&addr).get_manifest(name, digest.as_str()))
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        dkregistry::errors::Error::ContentDigestParse(_)
+    ));
+
+    mockito::reset();
+    Ok(())
+}
+
+#[test]
+fn test_manifest_with_malformed_digest() -> Fallible<()> {
+    let addr = mockito::server_address().to_string();
+    let name = "my-repo/malformed-digest";
+    let manifest = br#"{"schemaVersion":2,"mediaType":"application/vnd.docker.distribution.manifest.v2+json","config":{"mediaType":"application/vnd.docker.container.image.v1+json","size":2,"digest":"sha256:abc"},"layers":[]}"#;
+
+    let _m = mock("GET", format!("/v2/{}/manifests/latest", name).as_str())
+        .with_status(200)
+        .with_header(
+            "Content-Type",
+            "application/vnd.docker.distribution.manifest.v2+json",
+        )
+        .with_body(&manifest[..])
+        .create();
+    let m_config = mock("GET", format!("/v2/{}/blobs/sha256:abc", name).as_str())
+        .expect(0)
+        .create();
+
+    let runtime = Runtime::new()?;
+    let err = runtime
+        .block_on(client(&addr).get_manifest(name, "latest"))
+        .unwrap_err();
+    assert!(matches!(err, dkregistry::errors::Error::Json(_)));
+    m_config.assert();
+
+    mockito::reset();
+    Ok(())
+}
+
+#[test]
+fn test_manifest_raw() -> Fallible<()> {
+    let addr = mockito::server_address().to_string();
+    let name = "my-repo/raw-manifest";
+    // Formatting is kept as served, and covered by the digest.
+    let manifest = b"{\n  \"schemaVersion\": 2,\n  \"mediaType\": \"application/vnd.docker.distribution.manifest.list.v2+json\",\n  \"manifests\": []\n}\n";
+    let digest = DigestAlgorithm::Sha256.digest(manifest);
+
+    let _m = mock("GET", format!("/v2/{}/manifests/latest", name).as_str())
+        .with_status(200)
+        .with_header(
+            "Content-Type",
+            "application/vnd.docker.distribution.manifest.list.v2+json",
+        )
+        .with_header("Docker-Content-Digest", digest.as_str())
+        .with_body(&manifest[..])
+        .create();
+
+    let runtime = Runtime::new()?;
+    let dclient = client(&addr);
+
+    let raw = runtime.block_on(dclient.get_manifest_raw(name, "latest"))?;
+    assert_eq!(raw.bytes, &manifest[..]);
+    assert_eq!(raw.media_type, MediaTypes::ManifestList);
+    assert_eq!(raw.digest, digest);
+
+    let (manifest, raw) = runtime.block_on(dclient.get_manifest_and_raw(name, "latest"))?;
+    assert!(matches!(
+        manifest,
+        dkregistry::v2::manifest::Manifest::ML(_)
+    ));
+    assert_eq!(raw.digest, digest);
+
+    mockito::reset();
+    Ok(())
+}
+
+#[test]
+fn test_manifest_and_ref_without_header() -> Fallible<()> {
+    let addr = mockito::server_address().to_string();
+    let name = "my-repo/no-digest-header";
+
+    let _m = mock("GET", format!("/v2/{}/manifests/latest", name).as_str())
+        .with_status(200)
+        .with_header(
+            "Content-Type",
+            "application/vnd.docker.distribution.manifest.list.v2+json",
+        )
+        .with_body(MANIFEST_LIST)
+        .create();
+
+    let runtime = Runtime::new()?;
+    let dclient = client(&addr);
+
+    // Without a header, the registry states no digest for a tag.
+    let (_, digest) = runtime.block_on(dclient.get_manifest_and_ref(name, "latest"))?;
+    assert_eq!(digest, None);
+    let (_, raw) = runtime.block_on(dclient.get_manifest_and_raw(name, "latest"))?;
+    assert_eq!(raw.digest, DigestAlgorithm::Sha256.digest(MANIFEST_LIST));
+
+    mockito::reset();
+    Ok(())
+}
+
+#[test]
+fn test_manifest_header_digest_mismatch() -> Fallible<()> {
+    let addr = mockito::server_address().to_string();
+    let name = "my-repo/wrong-header";
+    let digest = DigestAlgorithm::Sha256.digest(b"another manifest");
+
+    let _m = mock("GET", format!("/v2/{}/manifests/latest", name).as_str())
+        .with_status(200)
+        .with_header(
+            "Content-Type",
+            "application/vnd.docker.distribution.manifest.list.v2+json",
+        )
+        .with_header("Docker-Content-Digest", digest.as_str())
+        .with_body(MANIFEST_LIST)
+        .create();
+
+    let runtime = Runtime::new()?;
+    let err = runtime
+        .block_on(client(&addr).get_manifest_raw(name, "latest"))
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        dkregistry::errors::Error::ContentDigestParse(_)
+    ));
+
+    mockito::reset();
+    Ok(())
+}
+
+#[test]
+fn test_manifest_schema1_signed() -> Fallible<()> {
+    use dkregistry::v2::manifest::{signed_payload, SignatureError};
+
+    let addr = mockito::server_address().to_string();
+    let name = "my-repo/schema1";
+    let bytes =
+        std::fs::read("tests/fixtures/quayio_steveej_cincinnati-test-labels_dkregistry-test.json")?;
+    // The digest covers the manifest without its signatures.
+    let digest = DigestAlgorithm::Sha256.digest(&signed_payload(&bytes)?);
+    let tampered = String::from_utf8(bytes.clone())?.replacen("\"amd64\"", "\"arm64\"", 1);
+
+    let _m = mock("GET", format!("/v2/{}/manifests/{}", name, digest).as_str())
+        .with_status(200)
+        .with_header(
+            "Content-Type",
+            "application/vnd.docker.distribution.manifest.v1+prettyjws",
+        )
+        .with_body(&bytes)
+        .create();
+    let _m_tampered = mock("GET", format!("/v2/{}/manifests/tampered", name).as_str())
+        .with_status(200)
+        .with_header(
+            "Content-Type",
+            "application/vnd.docker.distribution.manifest.v1+prettyjws",
+        )
+        .with_body(&tampered)
+        .create();
+
+    let runtime = Runtime::new()?;
+    let dclient = client(&addr);
+
+    let raw = runtime.block_on(dclient.get_manifest_raw(name, digest.as_str()))?;
+    assert_eq!(raw.media_type, MediaTypes::ManifestV2S1Signed);
+    assert_eq!(raw.digest, digest);
+    assert_eq!(raw.bytes, bytes);
+
+    match runtime.block_on(dclient.get_manifest_raw(name, "tampered")) {
+        Err(dkregistry::errors::Error::Signature(SignatureError::Invalid(_))) => {}
+        r => return Err(format!("expected an invalid signature, got {:?}", r).into()),
+    }
+
+    mockito::reset();
+    Ok(())
+}
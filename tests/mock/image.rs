@@ -0,0 +1,471 @@
+extern crate dkregistry;
+extern crate mockito;
+extern crate tokio;
+
+use self::mockito::{mock, Matcher};
+use self::tokio::runtime::Runtime;
+use crate::mock::{digest, schema2_manifest};
+use dkregistry::image::{
+    self, BlobStream, CopyOptions, ImageDestination, ImageSource, ManifestFormat,
+};
+use dkregistry::mediatypes::MediaTypes;
+use dkregistry::reference::ImageName;
+use dkregistry::v2::manifest::RawManifest;
+use dkregistry::v2::{Client, Digest};
+use futures::stream::{self, StreamExt, TryStreamExt};
+use std::str::FromStr;
+
+type Fallible<T> = Result<T, Box<dyn std::error::Error>>;
+
+const CONFIG: &[u8] = br#"{"architecture":"amd64","os":"linux"}"#;
+const LAYER: &[u8] = b"not really a tar archive";
+
+fn blob(data: &[u8]) -> BlobStream<'static> {
+    stream::once(futures::future::ok(data.to_vec().into())).boxed()
+}
+
+async fn read_blob(source: &dyn ImageSource, digest: &Digest) -> Fallible<Vec<u8>> {
+    let chunks: Vec<Vec<u8>> = source
+        .get_blob(digest)
+        .await?
+        .map_ok(|c| c.to_vec())
+        .try_collect()
+        .await?;
+    Ok(chunks.concat())
+}
+
+fn manifest() -> Vec<u8> {
+    let layer = (
+        "application/vnd.docker.image.rootfs.diff.tar",
+        LAYER,
+        LAYER.len() as u64,
+    );
+    schema2_manifest(CONFIG, &[layer]).into_bytes()
+}
+
+/// A schema 1 manifest of LAYER below an empty one, with a signature that
+/// covers the whole manifest but is not verified when reading directories.
+fn manifest_schema1() -> Vec<u8> {
+    let empty = r##"{"id":"b","parent":"a","created":"2020-01-02T00:00:00Z","container_config":{"Cmd":["/bin/sh","-c","#(nop) CMD [\"sh\"]"]},"throwaway":true}"##;
+    let base = r##"{"id":"a","created":"2020-01-01T00:00:00Z","container_config":{"Cmd":["/bin/sh","-c","#(nop) ADD file:abc in /"]}}"##;
+    let payload = serde_json::json!({
+        "schemaVersion": 1,
+        "name": "my-repo/legacy",
+        "tag": "v1",
+        "architecture": "amd64",
+        "fsLayers": [{"blobSum": digest(b"")}, {"blobSum": digest(LAYER)}],
+        "history": [
+            {"v1Compatibility": format!(r#"{{"architecture":"amd64","os":"linux",{}"#, &empty[1..])},
+            {"v1Compatibility": base},
+        ],
+    })
+    .to_string();
+    let protected = format!(
+        r#"{{"formatLength":{},"formatTail":"fQ"}}"#,
+        payload.len() - 1
+    );
+    format!(
+        r#"{},"signatures":[{{"header":{{"alg":"ES256"}},"signature":"","protected":"{}"}}]}}"#,
+        &payload[..payload.len() - 1],
+        base64::encode_config(protected, base64::URL_SAFE_NO_PAD)
+    )
+    .into_bytes()
+}
+
+fn config() -> dkregistry::v2::Config {
+    Client::configure().insecure_registry(true)
+}
+
+async fn open(name: &str) -> dkregistry::errors::Result<Box<dyn ImageSource>> {
+    image::open_source(&ImageName::from_str(name)?, config()).await
+}
+
+async fn create(name: &str) -> dkregistry::errors::Result<Box<dyn ImageDestination>> {
+    image::open_destination(&ImageName::from_str(name)?, config()).await
+}
+
+#[test]
+fn test_copy_between_transports() -> Fallible<()> {
+    let addr = mockito::server_address().to_string();
+    let name = "my-repo/copied";
+    let manifest = manifest();
+    let list = format!(
+        r#"{{"schemaVersion":2,"mediaType":"application/vnd.docker.distribution.manifest.list.v2+json","manifests":[{{"mediaType":"application/vnd.docker.distribution.manifest.v2+json","size":{},"digest":"{}","platform":{{"architecture":"amd64","os":"linux"}}}}]}}"#,
+        manifest.len(),
+        digest(&manifest)
+    );
+
+    let _m_v2 = mock("GET", "/v2/").with_status(200).create();
+    let _m_list = mock("GET", format!("/v2/{}/manifests/latest", name).as_str())
+        .with_status(200)
+        .with_header(
+            "Content-Type",
+            "application/vnd.docker.distribution.manifest.list.v2+json",
+        )
+        .with_body(&list)
+        .create();
+    let _m_manifest = mock(
+        "GET",
+        format!("/v2/{}/manifests/{}", name, digest(&manifest)).as_str(),
+    )
+    .with_status(200)
+    .with_header(
+        "Content-Type",
+        "application/vnd.docker.distribution.manifest.v2+json",
+    )
+    .with_body(&manifest)
+    .create();
+    let _m_blobs = [CONFIG, LAYER]
+        .iter()
+        .map(|blob| {
+            mock(
+                "GET",
+                format!("/v2/{}/blobs/{}", name, digest(blob)).as_str(),
+            )
+            .with_status(200)
+            .with_body(blob)
+            .create()
+        })
+        .collect::<Vec<_>>();
+
+    let dir = tempfile::tempdir()?;
+    let path = |p: &str| dir.path().join(p).display().to_string();
+    let options = CopyOptions::default().architecture("amd64").os("linux");
+    let runtime = Runtime::new()?;
+
+    runtime.block_on(async {
+        // Registry to OCI layout, resolving the manifest list.
+        let source = open(&format!("docker://{}/{}", addr, name)).await?;
+        let destination = create(&format!("oci:{}:v1", path("layout"))).await?;
+        let copied = image::copy(source.as_ref(), destination.as_ref(), &options).await?;
+        assert_eq!(copied, digest(&manifest));
+        let index = std::fs::read_to_string(dir.path().join("layout/index.json"))?;
+        assert!(index.contains(r#""org.opencontainers.image.ref.name":"v1""#));
+
+        // OCI layout to directory, keeping the manifest as-is.
+        let source = open(&format!("oci:{}:v1", path("layout"))).await?;
+        let destination = create(&format!("dir:{}", path("dir"))).await?;
+        image::copy(source.as_ref(), destination.as_ref(), &options).await?;
+        assert_eq!(
+            std::fs::read(dir.path().join("dir/manifest.json"))?,
+            manifest
+        );
+        assert_eq!(
+            std::fs::read(dir.path().join("dir").join(digest(LAYER).encoded()))?,
+            LAYER
+        );
+
+        // Directory to docker-archive, and back to an OCI archive.
+        let archive = format!("docker-archive:{}:my-repo/copied:v1", path("image.tar"));
+        let source = open(&format!("dir:{}", path("dir"))).await?;
+        let destination = create(&archive).await?;
+        image::copy(source.as_ref(), destination.as_ref(), &options).await?;
+
+        let source = open(&archive).await?;
+        let destination = create(&format!("oci-archive:{}", path("oci.tar"))).await?;
+        let copied = image::copy(source.as_ref(), destination.as_ref(), &options).await?;
+
+        let source = open(&format!("oci-archive:{}", path("oci.tar"))).await?;
+        let raw = source.get_manifest(None).await?;
+        assert_eq!(raw.digest, copied);
+        assert_eq!(raw.media_type, MediaTypes::ManifestV2S2);
+        assert_eq!(read_blob(source.as_ref(), &digest(CONFIG)).await?, CONFIG);
+        assert_eq!(read_blob(source.as_ref(), &digest(LAYER)).await?, LAYER);
+
+        Ok::<_, Box<dyn std::error::Error>>(())
+    })?;
+
+    mockito::reset();
+    Ok(())
+}
+
+#[test]
+fn test_copy_to_registry() -> Fallible<()> {
+    let addr = mockito::server_address().to_string();
+    let name = "my-repo/pushed";
+    let manifest = manifest();
+    let upload = format!("/v2/{}/blobs/uploads/1234", name);
+
+    let _m_v2 = mock("GET", "/v2/").with_status(200).create();
+    let _m_has_config = mock(
+        "HEAD",
+        format!("/v2/{}/blobs/{}", name, digest(CONFIG)).as_str(),
+    )
+    .with_status(404)
+    .create();
+    let _m_has_layer = mock(
+        "HEAD",
+        format!("/v2/{}/blobs/{}", name, digest(LAYER)).as_str(),
+    )
+    .with_status(200)
+    .create();
+    let m_post = mock("POST", format!("/v2/{}/blobs/uploads/", name).as_str())
+        .with_status(202)
+        .with_header("Location", &upload)
+        .expect(1)
+        .create();
+    let m_put = mock("PUT", upload.as_str())
+        .match_query(Matcher::UrlEncoded(
+            "digest".into(),
+            digest(CONFIG).to_string(),
+        ))
+        .match_body(std::str::from_utf8(CONFIG)?)
+        .with_status(201)
+        .expect(1)
+        .create();
+    let m_manifest = mock("PUT", format!("/v2/{}/manifests/v1", name).as_str())
+        .match_header(
+            "Content-Type",
+            "application/vnd.docker.distribution.manifest.v2+json",
+        )
+        .match_body(Matcher::Exact(String::from_utf8(manifest.clone())?))
+        .with_status(201)
+        .expect(1)
+        .create();
+
+    let dir = tempfile::tempdir()?;
+    let runtime = Runtime::new()?;
+    runtime.block_on(async {
+        let layout = dir.path().display().to_string();
+        let source = create(&format!("dir:{}", layout)).await?;
+        source.put_blob(&digest(CONFIG), blob(CONFIG)).await?;
+        source.put_blob(&digest(LAYER), blob(LAYER)).await?;
+        source
+            .put_manifest(&RawManifest {
+                digest: digest(&manifest),
+                media_type: MediaTypes::ManifestV2S2,
+                bytes: manifest.clone(),
+            })
+            .await?;
+
+        let source = open(&format!("dir:{}", layout)).await?;
+        let destination = create(&format!("docker://{}/{}:v1", addr, name)).await?;
+        let copied = image::copy(
+            source.as_ref(),
+            destination.as_ref(),
+            &CopyOptions::default(),
+        )
+        .await?;
+        assert_eq!(copied, digest(&manifest));
+        Ok::<_, Box<dyn std::error::Error>>(())
+    })?;
+
+    m_post.assert();
+    m_put.assert();
+    m_manifest.assert();
+
+    mockito::reset();
+    Ok(())
+}
+
+#[test]
+fn test_copy_converting_formats() -> Fallible<()> {
+    let dir = tempfile::tempdir()?;
+    let path = |p: &str| dir.path().join(p).display().to_string();
+    let legacy = manifest_schema1();
+    let runtime = Runtime::new()?;
+
+    runtime.block_on(async {
+        let source = create(&format!("dir:{}", path("legacy"))).await?;
+        source.put_blob(&digest(LAYER), blob(LAYER)).await?;
+        source
+            .put_manifest(&RawManifest {
+                digest: digest(&legacy),
+                media_type: MediaTypes::ManifestV2S1Signed,
+                bytes: legacy.clone(),
+            })
+            .await?;
+
+        // Schema 1 to OCI, synthesizing the config and dropping the empty layer.
+        let source = open(&format!("dir:{}", path("legacy"))).await?;
+        let destination = create(&format!("oci:{}:v1", path("layout"))).await?;
+        let options = CopyOptions::default().format(ManifestFormat::Oci);
+        let copied = image::copy(source.as_ref(), destination.as_ref(), &options).await?;
+
+        let source = open(&format!("oci:{}:v1", path("layout"))).await?;
+        let raw = source.get_manifest(None).await?;
+        assert_eq!(raw.digest, copied);
+        assert_eq!(raw.media_type, MediaTypes::OciImageManifest);
+        let oci: serde_json::Value = serde_json::from_slice(&raw.bytes)?;
+        assert_eq!(oci["layers"].as_array().map(Vec::len), Some(1));
+        assert_eq!(
+            oci["layers"][0]["mediaType"],
+            "application/vnd.oci.image.layer.v1.tar+gzip"
+        );
+        assert_eq!(
+            oci["config"]["mediaType"],
+            "application/vnd.oci.image.config.v1+json"
+        );
+        let config_digest = Digest::from_str(oci["config"]["digest"].as_str().unwrap_or(""))?;
+        let config: serde_json::Value =
+            serde_json::from_slice(&read_blob(source.as_ref(), &config_digest).await?)?;
+        assert_eq!(config["architecture"], "amd64");
+        assert_eq!(config["rootfs"]["diff_ids"][0], digest(LAYER).to_string());
+        assert_eq!(
+            config["history"][0]["created_by"],
+            "/bin/sh -c #(nop) ADD file:abc in /"
+        );
+        assert_eq!(config["history"][1]["empty_layer"], true);
+
+        // OCI back to schema 2, for registries that only accept Docker types.
+        let destination = create(&format!("dir:{}", path("schema2"))).await?;
+        let options = CopyOptions::default().format(ManifestFormat::Schema2);
+        image::copy(source.as_ref(), destination.as_ref(), &options).await?;
+        let source = open(&format!("dir:{}", path("schema2"))).await?;
+        let raw = source.get_manifest(None).await?;
+        assert_eq!(raw.media_type, MediaTypes::ManifestV2S2);
+        assert_eq!(read_blob(source.as_ref(), &digest(LAYER)).await?, LAYER);
+
+        Ok::<_, Box<dyn std::error::Error>>(())
+    })?;
+
+    Ok(())
+}
+
+#[test]
+fn test_copy_schema1_to_oci_layout() -> Fallible<()> {
+    let dir = tempfile::tempdir()?;
+    let path = |p: &str| dir.path().join(p).display().to_string();
+    let legacy = manifest_schema1();
+    let runtime = Runtime::new()?;
+
+    runtime.block_on(async {
+        let source = create(&format!("dir:{}", path("legacy"))).await?;
+        source.put_blob(&digest(LAYER), blob(LAYER)).await?;
+        source
+            .put_manifest(&RawManifest {
+                digest: digest(&legacy),
+                media_type: MediaTypes::ManifestV2S1Signed,
+                bytes: legacy.clone(),
+            })
+            .await?;
+
+        // OCI layouts cannot hold signed schema 1 manifests, which are converted.
+        let source = open(&format!("dir:{}", path("legacy"))).await?;
+        let destination = create(&format!("oci:{}:v1", path("layout"))).await?;
+        let copied = image::copy(
+            source.as_ref(),
+            destination.as_ref(),
+            &CopyOptions::default(),
+        )
+        .await?;
+
+        let source = open(&format!("oci:{}:v1", path("layout"))).await?;
+        let raw = source.get_manifest(None).await?;
+        assert_eq!(raw.media_type, MediaTypes::OciImageManifest);
+        assert_eq!(raw.digest, copied);
+        assert_eq!(digest(&raw.bytes), copied);
+
+        Ok::<_, Box<dyn std::error::Error>>(())
+    })?;
+
+    Ok(())
+}
+
+#[test]
+fn test_copy_from_oci_index_variant() -> Fallible<()> {
+    let addr = mockito::server_address().to_string();
+    let name = "my-repo/indexed";
+    let manifest = manifest();
+    let entry = |digest: &Digest, variant: &str| {
+        format!(
+            r#"{{"mediaType":"application/vnd.oci.image.manifest.v1+json","size":{},"digest":"{}","platform":{{"architecture":"arm64","os":"linux","variant":"{}"}}}}"#,
+            manifest.len(),
+            digest,
+            variant
+        )
+    };
+    let index = format!(
+        r#"{{"schemaVersion":2,"mediaType":"application/vnd.oci.image.index.v1+json","manifests":[{},{}]}}"#,
+        entry(&digest(b"other variant"), "v7"),
+        entry(&digest(&manifest), "v8"),
+    );
+
+    let _m_v2 = mock("GET", "/v2/").with_status(200).create();
+    let _m_index = mock("GET", format!("/v2/{}/manifests/latest", name).as_str())
+        .with_status(200)
+        .with_header("Content-Type", "application/vnd.oci.image.index.v1+json")
+        .with_body(&index)
+        .create();
+    let m_manifest = mock(
+        "GET",
+        format!("/v2/{}/manifests/{}", name, digest(&manifest)).as_str(),
+    )
+    .with_status(200)
+    .with_header(
+        "Content-Type",
+        "application/vnd.docker.distribution.manifest.v2+json",
+    )
+    .with_body(&manifest)
+    .expect(1)
+    .create();
+    let _m_blobs = [CONFIG, LAYER]
+        .iter()
+        .map(|blob| {
+            mock(
+                "GET",
+                format!("/v2/{}/blobs/{}", name, digest(blob)).as_str(),
+            )
+            .with_status(200)
+            .with_body(blob)
+            .create()
+        })
+        .collect::<Vec<_>>();
+
+    let dir = tempfile::tempdir()?;
+    let options = CopyOptions::default()
+        .architecture("arm64")
+        .os("linux")
+        .variant("v8");
+    let runtime = Runtime::new()?;
+
+    let copied = runtime.block_on(async {
+        let source = open(&format!("docker://{}/{}", addr, name)).await?;
+        let target = dir.path().join("dir").display().to_string();
+        let destination = create(&format!("dir:{}", target)).await?;
+        image::copy(source.as_ref(), destination.as_ref(), &options).await
+    })?;
+    assert_eq!(copied, digest(&manifest));
+    m_manifest.assert();
+
+    mockito::reset();
+    Ok(())
+}
+
+#[test]
+fn test_copy_corrupted_blob() -> Fallible<()> {
+    let dir = tempfile::tempdir()?;
+    let manifest = manifest();
+    let runtime = Runtime::new()?;
+
+    let res = runtime.block_on(async {
+        let layout = dir.path().join("layout").display().to_string();
+        let source = create(&format!("oci:{}", layout)).await?;
+        source.put_blob(&digest(CONFIG), blob(CONFIG)).await?;
+        source.put_blob(&digest(LAYER), blob(b"tampered")).await?;
+        source
+            .put_manifest(&RawManifest {
+                digest: digest(&manifest),
+                media_type: MediaTypes::ManifestV2S2,
+                bytes: manifest.clone(),
+            })
+            .await?;
+
+        let source = open(&format!("oci:{}", layout)).await?;
+        let target = dir.path().join("dir").display().to_string();
+        let destination = create(&format!("dir:{}", target)).await?;
+        image::copy(
+            source.as_ref(),
+            destination.as_ref(),
+            &CopyOptions::default(),
+        )
+        .await
+    });
+    match res {
+        Err(dkregistry::errors::Error::SizeMismatch { .. }) => {}
+        r => return Err(format!("expected SizeMismatch, got {:?}", r).into()),
+    }
+    assert!(!dir.path().join("dir/manifest.json").exists());
+
+    Ok(())
+}
@@ -0,0 +1,78 @@
+extern crate dkregistry;
+extern crate mockito;
+extern crate tokio;
+
+use self::tokio::runtime::Runtime;
+use crate::mock::mock_image;
+use dkregistry::v2::manifest::{Manifest, ManifestOptions};
+use dkregistry::v2::Client;
+
+type Fallible<T> = Result<T, Box<dyn std::error::Error>>;
+
+static CONFIG: &[u8] = br#"{"architecture":"arm64","os":"linux"}"#;
+
+/// Mock a schema 2 image, expecting its config blob to be fetched `config_hits` times.
+fn mock_counted_image(name: &str, config_hits: usize) -> Vec<mockito::Mock> {
+    let (manifest, config) = mock_image(name, "latest", CONFIG, &[]);
+    vec![manifest.create(), config.expect(config_hits).create()]
+}
+
+fn client() -> Fallible<Client> {
+    Ok(Client::configure()
+        .registry(&mockito::server_address().to_string())
+        .insecure_registry(true)
+        .build()?)
+}
+
+#[test]
+fn test_manifest_eager_config() -> Fallible<()> {
+    let name = "my-repo/eager";
+    let mocks = mock_counted_image(name, 1);
+
+    let runtime = Runtime::new()?;
+    let manifest = runtime.block_on(client()?.get_manifest(name, "latest"))?;
+    assert_eq!(manifest.architectures()?, vec!["arm64".to_string()]);
+
+    mocks[1].assert();
+    mockito::reset();
+    Ok(())
+}
+
+#[test]
+fn test_manifest_lazy_config() -> Fallible<()> {
+    let name = "my-repo/lazy";
+    // The config is fetched once per manifest, on first use.
+    let mocks = mock_counted_image(name, 2);
+
+    let runtime = Runtime::new()?;
+    let options = ManifestOptions::default().lazy_config(true);
+    let (manifest, _) = runtime.block_on(client()?.get_manifest_with(name, "latest", &options))?;
+    let m = match manifest {
+        Manifest::S2(m) => m,
+        m => return Err(format!("expected a schema 2 manifest, got {:?}", m).into()),
+    };
+    assert!(m.fetched_config().is_none());
+    assert_eq!(m.architecture(), "");
+    let manifest = Manifest::S2(m);
+    assert!(manifest.architectures().is_err());
+    assert!(manifest.layers_digests(Some("arm64")).is_err());
+
+    // Checking the architecture of layers fetches the config.
+    let layers = runtime.block_on(manifest.fetch_layers_digests(Some("arm64")))?;
+    assert!(layers.is_empty());
+    assert!(runtime
+        .block_on(manifest.fetch_layers_digests(Some("amd64")))
+        .is_err());
+    assert_eq!(manifest.architectures()?, vec!["arm64".to_string()]);
+
+    let (manifest, _) = runtime.block_on(client()?.get_manifest_with(name, "latest", &options))?;
+    if let Manifest::S2(m) = manifest {
+        runtime.block_on(m.config())?;
+        runtime.block_on(m.config())?;
+        assert_eq!(m.architecture(), "arm64");
+    }
+
+    mocks[1].assert();
+    mockito::reset();
+    Ok(())
+}
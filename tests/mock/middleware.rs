@@ -0,0 +1,109 @@
+extern crate dkregistry;
+extern crate http;
+extern crate mockito;
+extern crate tokio;
+
+use self::mockito::mock;
+use self::tokio::runtime::Runtime;
+use dkregistry::v2::Middleware;
+use reqwest::{Method, Url};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+type Fallible<T> = Result<T, Box<dyn std::error::Error>>;
+
+/// Middleware adding a header to requests and recording responses.
+#[derive(Debug, Default)]
+struct Recorder {
+    seen: Mutex<Vec<(Method, String, u16)>>,
+}
+
+impl Middleware for Recorder {
+    fn on_request(&self, request: &mut reqwest::Request) -> Option<reqwest::Response> {
+        request
+            .headers_mut()
+            .insert("x-trace-id", "abc123".parse().unwrap());
+        None
+    }
+
+    fn on_response(
+        &self,
+        method: &Method,
+        url: &Url,
+        response: &reqwest::Response,
+        _elapsed: Duration,
+    ) {
+        self.seen.lock().unwrap().push((
+            method.clone(),
+            url.path().to_string(),
+            response.status().as_u16(),
+        ));
+    }
+}
+
+/// Middleware answering `/v2/` requests without contacting the registry.
+#[derive(Debug)]
+struct Canned;
+
+impl Middleware for Canned {
+    fn on_request(&self, request: &mut reqwest::Request) -> Option<reqwest::Response> {
+        if request.url().path() != "/v2/" {
+            return None;
+        }
+        let response = http::Response::builder()
+            .status(200)
+            .header("Docker-Distribution-API-Version", "registry/2.0")
+            .body("")
+            .unwrap();
+        Some(response.into())
+    }
+}
+
+#[test]
+fn test_middleware_observes_requests() -> Fallible<()> {
+    let addr = mockito::server_address().to_string();
+    let _m = mock("GET", "/v2/")
+        .match_header("x-trace-id", "abc123")
+        .with_status(200)
+        .with_header("Docker-Distribution-API-Version", "registry/2.0")
+        .create();
+
+    let recorder = Arc::new(Recorder::default());
+    let runtime = Runtime::new()?;
+    let dclient = dkregistry::v2::Client::configure()
+        .registry(&addr)
+        .insecure_registry(true)
+        .middleware(recorder.clone())
+        .build()?;
+
+    assert!(runtime.block_on(dclient.is_v2_supported())?);
+    assert_eq!(
+        *recorder.seen.lock().unwrap(),
+        vec![(Method::GET, "/v2/".to_string(), 200)]
+    );
+
+    mockito::reset();
+    Ok(())
+}
+
+#[test]
+fn test_middleware_short_circuit() -> Fallible<()> {
+    let addr = mockito::server_address().to_string();
+    let m = mock("GET", "/v2/").with_status(500).expect(0).create();
+
+    let recorder = Arc::new(Recorder::default());
+    let runtime = Runtime::new()?;
+    let dclient = dkregistry::v2::Client::configure()
+        .registry(&addr)
+        .insecure_registry(true)
+        .middleware(recorder.clone())
+        .middleware(Arc::new(Canned))
+        .build()?;
+
+    assert!(runtime.block_on(dclient.is_v2_supported())?);
+    assert_eq!(recorder.seen.lock().unwrap().len(), 1);
+    m.assert();
+
+    mockito::reset();
+    Ok(())
+}
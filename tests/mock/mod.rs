@@ -1,6 +1,82 @@
+mod api_errors;
 mod api_version;
 mod base_client;
+mod blobs_cache;
 mod blobs_download;
+mod blobs_redirect;
+mod blobs_resume;
 mod catalog;
+mod client_reference;
+mod digests;
+mod image;
+mod manifest_config;
+mod middleware;
+mod pull;
+mod push_image;
+mod ratelimit;
+mod retry;
 mod tags_dockerv2;
 mod tags_quay;
+
+use dkregistry::v2::{Digest, DigestAlgorithm};
+
+/// Media type of schema 2 image manifests.
+pub(crate) const MANIFEST_V2: &str = "application/vnd.docker.distribution.manifest.v2+json";
+
+/// A layer of a mocked image: its media type, content, and the size stated
+/// in the manifest.
+pub(crate) type MockLayer<'a> = (&'a str, &'a [u8], u64);
+
+pub(crate) fn digest(blob: &[u8]) -> Digest {
+    DigestAlgorithm::Sha256.digest(blob)
+}
+
+/// Serialize a schema 2 manifest of a config blob and layers.
+pub(crate) fn schema2_manifest(config: &[u8], layers: &[MockLayer]) -> String {
+    let layers = layers
+        .iter()
+        .map(|(media_type, blob, size)| {
+            format!(
+                r#"{{"mediaType":"{}","size":{},"digest":"{}"}}"#,
+                media_type,
+                size,
+                digest(blob)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+    format!(
+        r#"{{"schemaVersion":2,"mediaType":"{}","config":{{"mediaType":"application/vnd.docker.container.image.v1+json","size":{},"digest":"{}"}},"layers":[{}]}}"#,
+        MANIFEST_V2,
+        config.len(),
+        digest(config),
+        layers
+    )
+}
+
+/// Mock a schema 2 image under `reference`, returning the mocks of its
+/// manifest and config blob.
+///
+/// The mocks are not created yet, so that tests can add their own matchers
+/// and expectations.
+pub(crate) fn mock_image(
+    name: &str,
+    reference: &str,
+    config: &[u8],
+    layers: &[MockLayer],
+) -> (mockito::Mock, mockito::Mock) {
+    let manifest = mockito::mock(
+        "GET",
+        format!("/v2/{}/manifests/{}", name, reference).as_str(),
+    )
+    .with_status(200)
+    .with_header("Content-Type", MANIFEST_V2)
+    .with_body(schema2_manifest(config, layers));
+    let config = mockito::mock(
+        "GET",
+        format!("/v2/{}/blobs/{}", name, digest(config)).as_str(),
+    )
+    .with_status(200)
+    .with_body(config);
+    (manifest, config)
+}
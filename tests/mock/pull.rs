@@ -0,0 +1,282 @@
+extern crate dkregistry;
+extern crate mockito;
+extern crate tokio;
+
+use self::mockito::mock;
+use self::tokio::runtime::Runtime;
+use crate::mock::{digest, mock_image};
+use dkregistry::render::LayerRenderer;
+use dkregistry::v2::{
+    Digest, ImageProgress, ImageProgressSnapshot, ProgressEvent, ProgressObserver, PullOptions,
+};
+use std::sync::{Arc, Mutex};
+
+type Fallible<T> = Result<T, Box<dyn std::error::Error>>;
+
+const GZIP_LAYER: &str = "application/vnd.docker.image.rootfs.diff.tar.gzip";
+
+/// Mock a schema 2 image with the given gzipped layers, returning the mocks.
+fn mock_pulled_image(name: &str, layers: &[(&[u8], u64)]) -> Vec<mockito::Mock> {
+    let config = br#"{"architecture":"amd64"}"#;
+    let descriptors = layers
+        .iter()
+        .map(|(blob, size)| (GZIP_LAYER, *blob, *size))
+        .collect::<Vec<_>>();
+    let (manifest, config) = mock_image(name, "latest", config, &descriptors);
+
+    let mut mocks = vec![manifest.create(), config.create()];
+    let mut unique = layers.iter().map(|(blob, _)| *blob).collect::<Vec<_>>();
+    unique.sort();
+    unique.dedup();
+    for blob in unique {
+        mocks.push(
+            mock(
+                "GET",
+                format!("/v2/{}/blobs/{}", name, digest(blob)).as_str(),
+            )
+            .with_status(200)
+            .with_body(blob)
+            .expect(1)
+            .create(),
+        );
+    }
+    mocks
+}
+
+#[test]
+fn test_pull_to_dir() -> Fallible<()> {
+    let name = "my-repo/pulled";
+    let layers: &[(&[u8], u64)] = &[(b"base layer", 10), (b"top layer", 9), (b"base layer", 10)];
+    let mocks = mock_pulled_image(name, layers);
+
+    let addr = mockito::server_address().to_string();
+    let runtime = Runtime::new()?;
+    let dclient = dkregistry::v2::Client::configure()
+        .registry(&addr)
+        .insecure_registry(true)
+        .build()?;
+
+    let dir = tempfile::tempdir()?;
+    let options = PullOptions::default().concurrency(2);
+    let summary = runtime.block_on(dclient.pull_to_dir(name, "latest", dir.path(), &options))?;
+
+    assert_eq!(summary.layers.len(), 2);
+    for (pulled, (blob, size)) in summary.layers.iter().zip(layers) {
+        assert_eq!(pulled.descriptor.digest, digest(blob));
+        assert_eq!(pulled.size, *size);
+        let path = pulled.path.as_ref().expect("missing layer path");
+        assert!(path.starts_with(dir.path()));
+        assert_eq!(std::fs::read(path)?, blob.to_vec());
+    }
+
+    for m in mocks {
+        m.assert();
+    }
+    mockito::reset();
+    Ok(())
+}
+
+#[test]
+fn test_pull_size_mismatch() -> Fallible<()> {
+    let name = "my-repo/pulled-truncated";
+    let _mocks = mock_pulled_image(name, &[(b"some layer", 42)]);
+
+    let addr = mockito::server_address().to_string();
+    let runtime = Runtime::new()?;
+    let dclient = dkregistry::v2::Client::configure()
+        .registry(&addr)
+        .insecure_registry(true)
+        .build()?;
+
+    let dir = tempfile::tempdir()?;
+    let res =
+        runtime.block_on(dclient.pull_to_dir(name, "latest", dir.path(), &PullOptions::default()));
+    match res {
+        Err(dkregistry::errors::Error::SizeMismatch { expected: 42, .. }) => {}
+        r => return Err(format!("expected SizeMismatch, got {:?}", r).into()),
+    };
+    assert_eq!(std::fs::read_dir(dir.path().join("sha256"))?.count(), 0);
+
+    mockito::reset();
+    Ok(())
+}
+
+/// Observer recording the kind of every event, per blob.
+#[derive(Debug, Default)]
+struct EventLog(Mutex<Vec<(Digest, &'static str)>>);
+
+impl ProgressObserver for EventLog {
+    fn on_event(&self, event: &ProgressEvent<'_>) {
+        let kind = match event {
+            ProgressEvent::Queued { .. } => "queued",
+            ProgressEvent::Started { .. } => "started",
+            ProgressEvent::Progress { .. } => "progress",
+            ProgressEvent::Completed { .. } => "completed",
+            ProgressEvent::Failed { .. } => "failed",
+            _ => "other",
+        };
+        self.0.lock().unwrap().push((event.digest().clone(), kind));
+    }
+}
+
+#[test]
+fn test_pull_progress() -> Fallible<()> {
+    let name = "my-repo/pulled-progress";
+    let layers: &[(&[u8], u64)] = &[(b"first layer", 11), (b"second layer", 12)];
+    let _mocks = mock_pulled_image(name, layers);
+
+    let addr = mockito::server_address().to_string();
+    let runtime = Runtime::new()?;
+    let dclient = dkregistry::v2::Client::configure()
+        .registry(&addr)
+        .insecure_registry(true)
+        .build()?;
+
+    let log = Arc::new(EventLog::default());
+    let progress = Arc::new(ImageProgress::with_observer(log.clone()));
+    let options = PullOptions::default().progress(progress.clone());
+    let dir = tempfile::tempdir()?;
+    runtime.block_on(dclient.pull_to_dir(name, "latest", dir.path(), &options))?;
+
+    assert_eq!(
+        progress.snapshot(),
+        ImageProgressSnapshot {
+            blobs: 2,
+            completed: 2,
+            transferred: 23,
+            total: Some(23),
+        }
+    );
+    for (blob, _) in layers {
+        let kinds = log
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(d, _)| *d == digest(blob))
+            .map(|(_, kind)| *kind)
+            .collect::<Vec<_>>();
+        assert_eq!(kinds.first(), Some(&"queued"));
+        assert_eq!(kinds.get(1), Some(&"started"));
+        assert!(kinds.contains(&"progress"));
+        assert_eq!(kinds.last(), Some(&"completed"));
+    }
+
+    mockito::reset();
+    Ok(())
+}
+
+#[test]
+fn test_pull_progress_failed() -> Fallible<()> {
+    let name = "my-repo/pulled-progress-truncated";
+    let blob: &[u8] = b"some layer";
+    let _mocks = mock_pulled_image(name, &[(blob, 42)]);
+
+    let addr = mockito::server_address().to_string();
+    let runtime = Runtime::new()?;
+    let dclient = dkregistry::v2::Client::configure()
+        .registry(&addr)
+        .insecure_registry(true)
+        .build()?;
+
+    let log = Arc::new(EventLog::default());
+    let options = PullOptions::default().progress(log.clone());
+    let dir = tempfile::tempdir()?;
+    assert!(runtime
+        .block_on(dclient.pull_to_dir(name, "latest", dir.path(), &options))
+        .is_err());
+    assert_eq!(
+        log.0.lock().unwrap().last(),
+        Some(&(digest(blob), "failed"))
+    );
+
+    mockito::reset();
+    Ok(())
+}
+
+#[test]
+fn test_directory_sink_concurrent_writers() -> Fallible<()> {
+    use dkregistry::v2::{DirectorySink, LayerDescriptor, LayerSink};
+    use tokio::io::AsyncWriteExt;
+
+    let dir = tempfile::tempdir()?;
+    let (first, second) = (
+        DirectorySink::new(dir.path()),
+        DirectorySink::new(dir.path()),
+    );
+    let layer = LayerDescriptor {
+        digest: digest(b"some layer"),
+        size: Some(10),
+        media_type: None,
+    };
+
+    let runtime = Runtime::new()?;
+    runtime.block_on(async {
+        // Each writer has its own partial file, even in the same process.
+        let mut written = first.open(&layer).await?;
+        let mut aborted = second.open(&layer).await?;
+        assert!(first.open(&layer).await.is_err());
+        written.write_all(b"some layer").await?;
+        aborted.write_all(b"some").await?;
+        drop((written, aborted));
+        second.abort(&layer).await?;
+        first.commit(&layer).await
+    })?;
+
+    let path = first.path_for(&layer.digest);
+    assert_eq!(std::fs::read(&path)?, b"some layer");
+    let entries = std::fs::read_dir(path.parent().unwrap())?.count();
+    assert_eq!(entries, 1);
+    Ok(())
+}
+
+/// Build an uncompressed layer from (path, content) entries.
+fn tar_layer(entries: &[(&str, &str)]) -> Vec<u8> {
+    let mut builder = tar::Builder::new(Vec::new());
+    for (path, content) in entries {
+        let mut header = tar::Header::new_gnu();
+        header.set_mode(0o644);
+        header.set_size(content.len() as u64);
+        builder
+            .append_data(&mut header, path, content.as_bytes())
+            .unwrap();
+    }
+    builder.into_inner().unwrap()
+}
+
+#[test]
+fn test_render_layers() -> Fallible<()> {
+    let name = "my-repo/rendered";
+    let base = tar_layer(&[("etc/hosts", "base"), ("etc/passwd", "base")]);
+    let top = tar_layer(&[("etc/.wh.passwd", ""), ("etc/hosts", "top")]);
+    let layers: &[(&[u8], u64)] = &[(&base, base.len() as u64), (&top, top.len() as u64)];
+    let mocks = mock_pulled_image(name, layers);
+
+    let addr = mockito::server_address().to_string();
+    let runtime = Runtime::new()?;
+    let dclient = dkregistry::v2::Client::configure()
+        .registry(&addr)
+        .insecure_registry(true)
+        .build()?;
+
+    let dir = tempfile::tempdir()?;
+    let renderer = LayerRenderer::new(dir.path())?;
+    runtime.block_on(async {
+        let manifest = dclient.get_manifest(name, "latest").await?;
+        let descriptors = manifest.layer_descriptors()?;
+        dclient.render_layers(name, &descriptors, &renderer).await
+    })?;
+
+    assert_eq!(
+        std::fs::read_to_string(dir.path().join("etc/hosts"))?,
+        "top"
+    );
+    assert!(!dir.path().join("etc/passwd").exists());
+    assert!(!dir.path().join("etc/.wh.passwd").exists());
+
+    for m in mocks.into_iter().skip(2) {
+        m.assert();
+    }
+    mockito::reset();
+    Ok(())
+}
@@ -0,0 +1,254 @@
+extern crate dkregistry;
+extern crate mockito;
+extern crate tokio;
+
+use self::mockito::{mock, Matcher};
+use self::tokio::runtime::Runtime;
+use crate::mock::{digest, mock_image, schema2_manifest, MANIFEST_V2};
+use dkregistry::v2::manifest::{ImageBuilder, ImageConfig, IndexBuilder, ManifestFormat, Platform};
+use dkregistry::v2::{Client, DigestAlgorithm};
+
+type Fallible<T> = Result<T, Box<dyn std::error::Error>>;
+
+const LAYER: &[u8] = b"not really a tar archive";
+
+fn image(architecture: &str) -> Fallible<dkregistry::v2::manifest::BuiltImage> {
+    let config = ImageConfig {
+        architecture: architecture.to_string(),
+        os: "linux".to_string(),
+        ..ImageConfig::default()
+    };
+    Ok(ImageBuilder::new(config)
+        .format(ManifestFormat::Oci)
+        .layer(LAYER.to_vec())?
+        .build()?)
+}
+
+#[test]
+fn test_push_index() -> Fallible<()> {
+    let addr = mockito::server_address().to_string();
+    let name = "my-repo/built";
+    let upload = format!("/v2/{}/blobs/uploads/1234", name);
+
+    let amd64 = image("amd64")?;
+    let arm64 = image("arm64")?;
+    let index = IndexBuilder::default()
+        .format(ManifestFormat::Oci)
+        .image(amd64.clone())
+        .image(arm64.clone())
+        .manifest(dkregistry::v2::manifest::ManifestObj {
+            platform: Platform {
+                architecture: "s390x".to_string(),
+                os: "linux".to_string(),
+                ..Platform::default()
+            },
+            ..amd64.descriptor()
+        })
+        .annotation("org.opencontainers.image.version", "v1")
+        .build()?;
+    let layer_digest = &amd64.spec().layers[0].digest;
+
+    let _m_v2 = mock("GET", "/v2/").with_status(200).create();
+    // The layer is shared by both images, and only uploaded once.
+    let _m_has_layer = mock(
+        "HEAD",
+        format!("/v2/{}/blobs/{}", name, layer_digest).as_str(),
+    )
+    .with_status(404)
+    .expect(1)
+    .create();
+    let _m_has_layer_again = mock(
+        "HEAD",
+        format!("/v2/{}/blobs/{}", name, layer_digest).as_str(),
+    )
+    .with_status(200)
+    .create();
+    let _m_has_config = mock("HEAD", Matcher::Regex(format!("^/v2/{}/blobs/", name)))
+        .with_status(404)
+        .create();
+    let m_post = mock("POST", format!("/v2/{}/blobs/uploads/", name).as_str())
+        .with_status(202)
+        .with_header("Location", &upload)
+        .expect(3)
+        .create();
+    let m_put = mock("PUT", upload.as_str())
+        .match_query(Matcher::Any)
+        .with_status(201)
+        .expect(3)
+        .create();
+    let m_images = [&amd64, &arm64]
+        .iter()
+        .map(|image| {
+            mock(
+                "PUT",
+                format!("/v2/{}/manifests/{}", name, image.digest()).as_str(),
+            )
+            .match_header("Content-Type", "application/vnd.oci.image.manifest.v1+json")
+            .match_body(Matcher::Exact(
+                String::from_utf8(image.manifest().bytes.clone()).unwrap(),
+            ))
+            .with_status(201)
+            .expect(1)
+            .create()
+        })
+        .collect::<Vec<_>>();
+    let m_index = mock("PUT", format!("/v2/{}/manifests/v1", name).as_str())
+        .match_header("Content-Type", "application/vnd.oci.image.index.v1+json")
+        .match_body(Matcher::Exact(String::from_utf8(
+            index.manifest().bytes.clone(),
+        )?))
+        .with_status(201)
+        .expect(1)
+        .create();
+
+    let dclient = Client::configure()
+        .registry(&addr)
+        .insecure_registry(true)
+        .build()?;
+    let runtime = Runtime::new()?;
+    let digest = runtime.block_on(dclient.push_index(name, "v1", &index))?;
+    assert_eq!(&digest, index.digest());
+
+    let index: serde_json::Value = serde_json::from_slice(&index.manifest().bytes)?;
+    assert_eq!(index["manifests"].as_array().map(Vec::len), Some(3));
+    assert_eq!(index["manifests"][1]["platform"]["architecture"], "arm64");
+    assert_eq!(
+        index["annotations"]["org.opencontainers.image.version"],
+        "v1"
+    );
+
+    m_post.assert();
+    m_put.assert();
+    m_images.iter().for_each(|m| m.assert());
+    m_index.assert();
+
+    mockito::reset();
+    Ok(())
+}
+
+/// Mock an image of the given platform under a tag, returning its descriptor.
+fn mock_platform_image(
+    name: &str,
+    tag: &str,
+    architecture: &str,
+    variant: Option<&str>,
+    created: &str,
+) -> (String, Vec<mockito::Mock>) {
+    let platform = match variant {
+        Some(variant) => format!(
+            r#""architecture":"{}","os":"linux","variant":"{}""#,
+            architecture, variant
+        ),
+        None => format!(r#""architecture":"{}","os":"linux""#, architecture),
+    };
+    let config = format!(
+        r#"{{"created":"{}",{},"rootfs":{{"type":"layers","diff_ids":[]}}}}"#,
+        created, platform
+    );
+    let manifest = schema2_manifest(config.as_bytes(), &[]);
+    let descriptor = format!(
+        r#"{{"mediaType":"{}","size":{},"digest":"{}","platform":{{{}}}}}"#,
+        MANIFEST_V2,
+        manifest.len(),
+        digest(manifest.as_bytes()),
+        platform
+    );
+    let (manifest, config) = mock_image(name, tag, config.as_bytes(), &[]);
+    let mocks = vec![manifest.create(), config.create()];
+    (descriptor, mocks)
+}
+
+fn manifest_list(descriptors: &[&str]) -> String {
+    format!(
+        r#"{{"schemaVersion":2,"mediaType":"application/vnd.docker.distribution.manifest.list.v2+json","manifests":[{}]}}"#,
+        descriptors.join(",")
+    )
+}
+
+#[test]
+fn test_push_manifest_list() -> Fallible<()> {
+    let addr = mockito::server_address().to_string();
+    let name = "my-repo/assembled";
+
+    let _m_v2 = mock("GET", "/v2/").with_status(200).create();
+    let (amd64, _m_amd64) = mock_platform_image(name, "v1-amd64", "amd64", None, "2020-02-01");
+    let (arm, _m_arm) = mock_platform_image(name, "v1-arm", "arm", Some("v7"), "2020-02-01");
+    let m_list = mock("PUT", format!("/v2/{}/manifests/v1", name).as_str())
+        .match_header(
+            "Content-Type",
+            "application/vnd.docker.distribution.manifest.list.v2+json",
+        )
+        .match_body(Matcher::Exact(manifest_list(&[&amd64, &arm])))
+        .with_status(201)
+        .expect(1)
+        .create();
+
+    let dclient = Client::configure()
+        .registry(&addr)
+        .insecure_registry(true)
+        .build()?;
+    let runtime = Runtime::new()?;
+    let digest = runtime.block_on(dclient.push_manifest_list(
+        name,
+        "v1",
+        &["v1-amd64", "v1-arm"],
+        ManifestFormat::Schema2,
+    ))?;
+    assert_eq!(
+        digest,
+        DigestAlgorithm::Sha256.digest(manifest_list(&[&amd64, &arm]).as_bytes())
+    );
+
+    m_list.assert();
+
+    mockito::reset();
+    Ok(())
+}
+
+#[test]
+fn test_update_manifest_list() -> Fallible<()> {
+    let addr = mockito::server_address().to_string();
+    let name = "my-repo/updated";
+
+    let _m_v2 = mock("GET", "/v2/").with_status(200).create();
+    let (old_amd64, _m_old) =
+        mock_platform_image(name, "v1-amd64-old", "amd64", None, "2020-01-01");
+    let (amd64, _m_amd64) = mock_platform_image(name, "v1-amd64", "amd64", None, "2020-02-01");
+    let (s390x, _m_s390x) = mock_platform_image(name, "v1-s390x", "s390x", None, "2020-02-01");
+    let (arm64, _m_arm64) = mock_platform_image(name, "v1-arm64", "arm64", None, "2020-02-01");
+    assert_ne!(old_amd64, amd64);
+
+    let _m_get = mock("GET", format!("/v2/{}/manifests/v1", name).as_str())
+        .with_status(200)
+        .with_header(
+            "Content-Type",
+            "application/vnd.docker.distribution.manifest.list.v2+json",
+        )
+        .with_body(manifest_list(&[&old_amd64, &s390x]))
+        .create();
+    // Replacing the amd64 image keeps its position, adding arm64 appends it.
+    let m_replaced = mock("PUT", format!("/v2/{}/manifests/v1", name).as_str())
+        .match_body(Matcher::Exact(manifest_list(&[&amd64, &s390x])))
+        .with_status(201)
+        .expect(1)
+        .create();
+    let m_added = mock("PUT", format!("/v2/{}/manifests/v1", name).as_str())
+        .match_body(Matcher::Exact(manifest_list(&[&old_amd64, &s390x, &arm64])))
+        .with_status(201)
+        .expect(1)
+        .create();
+
+    let dclient = Client::configure()
+        .registry(&addr)
+        .insecure_registry(true)
+        .build()?;
+    let runtime = Runtime::new()?;
+    runtime.block_on(dclient.update_manifest_list(name, "v1", "v1-amd64"))?;
+    runtime.block_on(dclient.update_manifest_list(name, "v1", "v1-arm64"))?;
+
+    m_replaced.assert();
+    m_added.assert();
+
+    mockito::reset();
+    Ok(())
+}
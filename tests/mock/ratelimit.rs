@@ -0,0 +1,47 @@
+extern crate dkregistry;
+extern crate mockito;
+extern crate tokio;
+
+use self::mockito::mock;
+use self::tokio::runtime::Runtime;
+use std::time::Duration;
+
+type Fallible<T> = Result<T, Box<dyn std::error::Error>>;
+
+#[test]
+fn test_rate_limit_status() -> Fallible<()> {
+    let name = "my-repo/rate-limited";
+    let addr = mockito::server_address().to_string();
+    let ep = format!("/v2/{}/manifests/latest", name);
+    let m = mock("HEAD", ep.as_str())
+        .with_status(200)
+        .with_header("RateLimit-Limit", "100;w=21600")
+        .with_header("RateLimit-Remaining", "76;w=21600")
+        .with_header("Docker-RateLimit-Source", "192.0.2.1")
+        .create();
+
+    let runtime = Runtime::new()?;
+    let dclient = dkregistry::v2::Client::configure()
+        .registry(&addr)
+        .insecure_registry(true)
+        .build()?;
+    assert!(dclient.rate_limit().is_none());
+
+    let status = runtime
+        .block_on(dclient.rate_limit_status(name))?
+        .expect("missing rate limit");
+    assert_eq!(status.remaining.unwrap().count, 76);
+    assert_eq!(
+        status.limit.unwrap().window,
+        Some(Duration::from_secs(21600))
+    );
+    assert_eq!(status.source.as_deref(), Some("192.0.2.1"));
+
+    let recorded = dclient.rate_limit().expect("missing recorded rate limit");
+    assert_eq!(recorded.remaining, status.remaining);
+    assert_eq!(dclient.rate_limits().len(), 1);
+
+    m.assert();
+    mockito::reset();
+    Ok(())
+}
@@ -0,0 +1,95 @@
+extern crate dkregistry;
+extern crate mockito;
+extern crate tokio;
+
+use self::mockito::mock;
+use self::tokio::runtime::Runtime;
+use dkregistry::v2::DigestAlgorithm;
+use dkregistry::v2::RetryPolicy;
+use std::time::Duration;
+
+type Fallible<T> = Result<T, Box<dyn std::error::Error>>;
+
+fn retrying_client(max_attempts: u32) -> dkregistry::v2::Client {
+    let addr = mockito::server_address().to_string();
+    dkregistry::v2::Client::configure()
+        .registry(&addr)
+        .insecure_registry(true)
+        .retry_policy(
+            RetryPolicy::default()
+                .max_attempts(max_attempts)
+                .base_backoff(Duration::from_millis(10)),
+        )
+        .build()
+        .unwrap()
+}
+
+#[test]
+fn retry_blob_after_unavailable() -> Fallible<()> {
+    let name = "my-repo/retry-ok";
+    let blob = b"hello";
+    let digest = DigestAlgorithm::Sha256.digest(blob);
+
+    let ep = format!("/v2/{}/blobs/{}", name, digest);
+    let unavailable = mock("GET", ep.as_str())
+        .with_status(503)
+        .with_header("Retry-After", "0")
+        .expect(1)
+        .create();
+    let ok = mock("GET", ep.as_str())
+        .with_status(200)
+        .with_body(blob)
+        .create();
+
+    let runtime = Runtime::new()?;
+    let dclient = retrying_client(3);
+    let result = runtime.block_on(dclient.get_blob(name, &digest))?;
+    assert_eq!(blob, result.as_slice());
+
+    unavailable.assert();
+    ok.assert();
+    mockito::reset();
+    Ok(())
+}
+
+#[test]
+fn retry_gives_up_after_max_attempts() -> Fallible<()> {
+    let name = "my-repo/retry-exhausted";
+    let ep = format!("/v2/{}/tags/list", name);
+    let m = mock("GET", ep.as_str()).with_status(429).expect(2).create();
+
+    let runtime = Runtime::new()?;
+    let dclient = retrying_client(2);
+    let res = runtime.block_on(async {
+        use futures::stream::StreamExt;
+        dclient.get_tags(name, None).collect::<Vec<_>>().await
+    });
+    assert!(res[0].is_err());
+
+    m.assert();
+    mockito::reset();
+    Ok(())
+}
+
+#[test]
+fn retry_skips_long_retry_after() -> Fallible<()> {
+    let name = "my-repo/retry-after";
+    let ep = format!("/v2/{}/tags/list", name);
+    let m = mock("GET", ep.as_str())
+        .with_status(429)
+        .with_header("Retry-After", "3600")
+        .expect(1)
+        .create();
+
+    let runtime = Runtime::new()?;
+    let dclient = retrying_client(5);
+    let res = runtime.block_on(async {
+        use futures::stream::StreamExt;
+        dclient.get_tags(name, None).collect::<Vec<_>>().await
+    });
+    assert!(res[0].is_err());
+
+    m.assert();
+    mockito::reset();
+    Ok(())
+}
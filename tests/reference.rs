@@ -133,3 +133,400 @@ fn ipv4_registry_and_library_with_tag() -> Result<(), Box<dyn std::error::Error>
 
     Ok(())
 }
+
+/// Expected registry, repository, tag and digest of a valid reference.
+type Expected<'a> = (&'a str, String, Option<&'a str>, Option<&'a str>);
+
+/// Test table from the reference Go implementation, at
+/// https://github.com/distribution/reference/blob/v0.6.0/reference_test.go
+///
+/// Names without a domain are normalized to the default registry, as done
+/// by the Docker CLI, so expectations differ from the unnormalized Go ones.
+#[test]
+fn conformance() {
+    let f64 = "f".repeat(64);
+    let f128 = "f".repeat(128);
+    let sha256 = format!("sha256:{}", f64);
+    let sha512 = format!("sha512:{}", f128);
+    let hub = dkregistry::reference::DEFAULT_REGISTRY;
+    let long_name = format!("{}a", "a/".repeat(127));
+
+    // Inputs with their expectations, or None if invalid.
+    let tcases: Vec<(String, Option<Expected>)> = vec![
+        (
+            "test_com".into(),
+            Some((hub, "library/test_com".into(), None, None)),
+        ),
+        (
+            "test.com:tag".into(),
+            Some((hub, "library/test.com".into(), Some("tag"), None)),
+        ),
+        (
+            "test.com:5000".into(),
+            Some((hub, "library/test.com".into(), Some("5000"), None)),
+        ),
+        (
+            "test.com/repo:tag".into(),
+            Some(("test.com", "repo".into(), Some("tag"), None)),
+        ),
+        (
+            "test:5000/repo".into(),
+            Some(("test:5000", "repo".into(), None, None)),
+        ),
+        (
+            "test:5000/repo:tag".into(),
+            Some(("test:5000", "repo".into(), Some("tag"), None)),
+        ),
+        (
+            format!("test:5000/repo@{}", sha256),
+            Some(("test:5000", "repo".into(), None, Some(&sha256))),
+        ),
+        (
+            format!("test:5000/repo:tag@{}", sha256),
+            Some(("test:5000", "repo".into(), Some("tag"), Some(&sha256))),
+        ),
+        ("".into(), None),
+        (":justtag".into(), None),
+        (format!("@{}", sha256), None),
+        (format!("repo@sha256:{}", "f".repeat(63)), None),
+        (format!("validname@invaliddigest:{}", f64), None),
+        ("Uppercase:tag".into(), None),
+        ("test:5000/Uppercase/lowercase:tag".into(), None),
+        (
+            "lowercase:Uppercase".into(),
+            Some((hub, "library/lowercase".into(), Some("Uppercase"), None)),
+        ),
+        (format!("{}a:tag", "a/".repeat(128)), None),
+        (
+            format!("{}:tag-puts-this-over-max", long_name),
+            Some((hub, long_name.clone(), Some("tag-puts-this-over-max"), None)),
+        ),
+        ("aa/asdf$$^/aa".into(), None),
+        (
+            "sub-dom1.foo.com/bar/baz/quux".into(),
+            Some(("sub-dom1.foo.com", "bar/baz/quux".into(), None, None)),
+        ),
+        (
+            "sub-dom1.foo.com/bar/baz/quux:some-long-tag".into(),
+            Some((
+                "sub-dom1.foo.com",
+                "bar/baz/quux".into(),
+                Some("some-long-tag"),
+                None,
+            )),
+        ),
+        (
+            "b.gcr.io/test.example.com/my-app:test.example.com".into(),
+            Some((
+                "b.gcr.io",
+                "test.example.com/my-app".into(),
+                Some("test.example.com"),
+                None,
+            )),
+        ),
+        (
+            "xn--n3h.com/myimage:xn--n3h.com".into(),
+            Some(("xn--n3h.com", "myimage".into(), Some("xn--n3h.com"), None)),
+        ),
+        (
+            format!("xn--7o8h.com/myimage:xn--7o8h.com@{}", sha512),
+            Some((
+                "xn--7o8h.com",
+                "myimage".into(),
+                Some("xn--7o8h.com"),
+                Some(&sha512),
+            )),
+        ),
+        (
+            "foo_bar.com:8080".into(),
+            Some((hub, "library/foo_bar.com".into(), Some("8080"), None)),
+        ),
+        (
+            "foo/foo_bar.com:8080".into(),
+            Some((hub, "foo/foo_bar.com".into(), Some("8080"), None)),
+        ),
+        ("[2001:db8::1]".into(), None),
+        ("[2001:db8::1]:5000".into(), None),
+        ("[2001:db8::1]:tag".into(), None),
+        (
+            "[2001:db8::1]/repo".into(),
+            Some(("[2001:db8::1]", "repo".into(), None, None)),
+        ),
+        (
+            "[2001:db8:1:2:3:4:5:6]/repo:tag".into(),
+            Some(("[2001:db8:1:2:3:4:5:6]", "repo".into(), Some("tag"), None)),
+        ),
+        (
+            "[2001:db8::1]:5000/repo".into(),
+            Some(("[2001:db8::1]:5000", "repo".into(), None, None)),
+        ),
+        (
+            "[2001:db8::1]:5000/repo:tag".into(),
+            Some(("[2001:db8::1]:5000", "repo".into(), Some("tag"), None)),
+        ),
+        (
+            format!("[2001:db8::1]:5000/repo@{}", sha256),
+            Some(("[2001:db8::1]:5000", "repo".into(), None, Some(&sha256))),
+        ),
+        (
+            format!("[2001:db8::1]:5000/repo:tag@{}", sha256),
+            Some((
+                "[2001:db8::1]:5000",
+                "repo".into(),
+                Some("tag"),
+                Some(&sha256),
+            )),
+        ),
+        (
+            "[2001:db8::]:5000/repo".into(),
+            Some(("[2001:db8::]:5000", "repo".into(), None, None)),
+        ),
+        (
+            "[::1]:5000/repo".into(),
+            Some(("[::1]:5000", "repo".into(), None, None)),
+        ),
+        ("[fe80::1%eth0]:5000/repo".into(), None),
+        ("[fe80::1%@invalidzone]:5000/repo".into(), None),
+        // Additional cases for single-label domains.
+        (
+            "localhost/repo".into(),
+            Some(("localhost", "repo".into(), None, None)),
+        ),
+        (
+            "localhost:5000/foo/bar:tag".into(),
+            Some(("localhost:5000", "foo/bar".into(), Some("tag"), None)),
+        ),
+        (
+            "registry/repo".into(),
+            Some((hub, "registry/repo".into(), None, None)),
+        ),
+    ];
+
+    for (input, expected) in &tcases {
+        let r = Reference::from_str(input);
+        let (registry, repository, tag, digest) = match expected {
+            Some(e) => e,
+            None => {
+                asserting(input).that(&r).is_err();
+                continue;
+            }
+        };
+        asserting(input).that(&r).is_ok();
+        let r = r.unwrap();
+
+        asserting(input)
+            .that(&r.registry().as_str())
+            .is_equal_to(registry);
+        asserting(input)
+            .that(&r.repository())
+            .is_equal_to(repository);
+        asserting(input).that(&r.tag()).is_equal_to(tag);
+        asserting(input)
+            .that(&r.digest().map(|d| d.to_string()))
+            .is_equal_to(digest.map(String::from));
+    }
+}
+
+#[test]
+fn tag_and_digest_display() {
+    let digest = format!("sha256:{}", "f".repeat(64));
+    let r = Reference::from_str(&format!("localhost:5000/foo:v1@{}", digest)).unwrap();
+
+    assert_eq!(r.version(), digest);
+    assert_eq!(r.to_string(), format!("localhost:5000/foo:v1@{}", digest));
+    assert_eq!(
+        r.to_url(),
+        format!("docker://localhost:5000/foo:v1@{}", digest)
+    );
+    assert_eq!(
+        Reference::from_str("busybox").unwrap().to_string(),
+        format!(
+            "{}/library/busybox:latest",
+            dkregistry::reference::DEFAULT_REGISTRY
+        )
+    );
+}
+
+#[test]
+fn familiar_names() {
+    let digest = format!("sha256:{}", "f".repeat(64));
+    let tcases = [
+        ("busybox", "busybox"),
+        ("busybox:1.36", "busybox:1.36"),
+        ("library/busybox", "busybox"),
+        ("docker.io/library/busybox", "busybox"),
+        ("index.docker.io/busybox:latest", "busybox:latest"),
+        ("registry-1.docker.io/foo/bar", "foo/bar"),
+        ("docker.io/library/foo/bar", "library/foo/bar"),
+        ("quay.io/library/busybox", "quay.io/library/busybox"),
+        ("localhost:5000/foo:tag", "localhost:5000/foo:tag"),
+    ];
+
+    for (input, familiar) in tcases.iter() {
+        let r = Reference::from_str(input).unwrap();
+        asserting(input)
+            .that(&r.to_familiar())
+            .is_equal_to(familiar.to_string());
+        // The familiar form parses back to the same reference.
+        asserting(input)
+            .that(&Reference::from_str(familiar).unwrap())
+            .is_equal_to(&r);
+    }
+
+    let r = Reference::from_str(&format!("busybox@{}", digest)).unwrap();
+    assert_eq!(r.to_familiar(), format!("busybox@{}", digest));
+}
+
+#[test]
+fn docker_hub_aliases() {
+    let canonical = "registry-1.docker.io/library/busybox:latest";
+    for input in &[
+        "busybox",
+        "busybox:latest",
+        "docker.io/busybox",
+        "index.docker.io/library/busybox",
+        "docker://registry-1.docker.io/library/busybox:latest",
+    ] {
+        let r = Reference::from_str(input).unwrap();
+        asserting(input)
+            .that(&r.registry().as_str())
+            .is_equal_to(dkregistry::reference::DEFAULT_REGISTRY);
+        asserting(input)
+            .that(&r.to_string().as_str())
+            .is_equal_to(canonical);
+    }
+
+    let r = Reference::new(Some("docker.io".into()), "library/busybox".into(), None);
+    assert_eq!(r.to_string(), canonical);
+}
+
+#[test]
+fn equality_and_ordering() {
+    use std::collections::{BTreeSet, HashSet};
+
+    let refs = [
+        "busybox",
+        "docker.io/library/busybox:latest",
+        "busybox:1.36",
+        "quay.io/busybox",
+    ]
+    .iter()
+    .map(|s| Reference::from_str(s).unwrap())
+    .collect::<Vec<_>>();
+    assert_eq!(refs[0], refs[1]);
+    assert_ne!(refs[0], refs[2]);
+
+    let hashed = refs.iter().cloned().collect::<HashSet<_>>();
+    assert_eq!(hashed.len(), 3);
+    let sorted = refs.into_iter().collect::<BTreeSet<_>>();
+    let sorted = sorted.iter().map(|r| r.to_string()).collect::<Vec<_>>();
+    assert_eq!(
+        sorted,
+        vec![
+            "quay.io/busybox:latest",
+            "registry-1.docker.io/library/busybox:1.36",
+            "registry-1.docker.io/library/busybox:latest"
+        ]
+    );
+}
+
+#[test]
+fn serde_round_trip() -> Result<(), Box<dyn std::error::Error>> {
+    let r = Reference::from_str("quay.io/foo/bar:v1")?;
+    let json = serde_json::to_string(&r)?;
+    assert_eq!(json, r#""quay.io/foo/bar:v1""#);
+    assert_eq!(serde_json::from_str::<Reference>(&json)?, r);
+
+    assert_eq!(
+        serde_json::from_str::<Reference>(r#""busybox""#)?.to_string(),
+        "registry-1.docker.io/library/busybox:latest"
+    );
+    assert!(serde_json::from_str::<Reference>(r#""Uppercase""#).is_err());
+    Ok(())
+}
+
+#[test]
+fn image_names() {
+    use dkregistry::reference::ImageName;
+    use std::path::PathBuf;
+
+    let tcases = vec![
+        (
+            "docker://busybox",
+            ImageName::Docker(Reference::from_str("busybox").unwrap()),
+            "docker://registry-1.docker.io/library/busybox:latest",
+        ),
+        (
+            "oci:/srv/layout",
+            ImageName::Oci {
+                path: PathBuf::from("/srv/layout"),
+                reference: None,
+            },
+            "oci:/srv/layout",
+        ),
+        (
+            "oci:layout:v1.0",
+            ImageName::Oci {
+                path: PathBuf::from("layout"),
+                reference: Some("v1.0".into()),
+            },
+            "oci:layout:v1.0",
+        ),
+        (
+            "oci-archive:/tmp/image.tar:quay.io/foo:bar",
+            ImageName::OciArchive {
+                path: PathBuf::from("/tmp/image.tar"),
+                reference: Some("quay.io/foo:bar".into()),
+            },
+            "oci-archive:/tmp/image.tar:quay.io/foo:bar",
+        ),
+        (
+            "docker-archive:image.tar",
+            ImageName::DockerArchive {
+                path: PathBuf::from("image.tar"),
+                reference: None,
+            },
+            "docker-archive:image.tar",
+        ),
+        (
+            "docker-archive:image.tar:docker.io/library/busybox:1.36",
+            ImageName::DockerArchive {
+                path: PathBuf::from("image.tar"),
+                reference: Some(Reference::from_str("busybox:1.36").unwrap()),
+            },
+            "docker-archive:image.tar:busybox:1.36",
+        ),
+        (
+            "dir:/srv/images/a:b",
+            ImageName::Dir(PathBuf::from("/srv/images/a:b")),
+            "dir:/srv/images/a:b",
+        ),
+    ];
+
+    for (input, expected, printed) in tcases {
+        let name = ImageName::from_str(input);
+        asserting(input).that(&name).is_ok();
+        let name = name.unwrap();
+        asserting(input).that(&name).is_equal_to(&expected);
+        asserting(input)
+            .that(&name.to_string().as_str())
+            .is_equal_to(printed);
+        asserting(input)
+            .that(&ImageName::from_str(printed).unwrap())
+            .is_equal_to(&expected);
+    }
+
+    for input in &[
+        "busybox",
+        "docker:busybox",
+        "podman://busybox",
+        "oci:",
+        "oci::ref",
+        "oci:layout:-invalid",
+        "oci-archive:image.tar:a//b",
+        "docker-archive:image.tar:Uppercase",
+        "dir:",
+    ] {
+        asserting(input).that(&ImageName::from_str(input)).is_err();
+    }
+}
@@ -0,0 +1,130 @@
+extern crate dkregistry;
+extern crate futures;
+extern crate http;
+extern crate tokio;
+
+use dkregistry::errors::Result;
+use dkregistry::v2::{Client, DigestAlgorithm, Transport};
+use futures::future::BoxFuture;
+use futures::stream::TryStreamExt;
+use reqwest::ResponseBuilderExt;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Canned response: status, headers and body.
+type Route = (u16, Vec<(&'static str, String)>, Vec<u8>);
+
+/// A registry served from memory, keyed by request path and query.
+#[derive(Debug, Default)]
+struct InMemoryRegistry {
+    routes: HashMap<String, Route>,
+    requests: Mutex<Vec<String>>,
+}
+
+impl InMemoryRegistry {
+    fn route(
+        mut self,
+        path: &str,
+        status: u16,
+        headers: &[(&'static str, &str)],
+        body: &[u8],
+    ) -> Self {
+        let headers = headers.iter().map(|(k, v)| (*k, v.to_string())).collect();
+        self.routes
+            .insert(path.to_string(), (status, headers, body.to_vec()));
+        self
+    }
+}
+
+impl Transport for InMemoryRegistry {
+    fn execute(&self, request: reqwest::Request) -> BoxFuture<'_, Result<reqwest::Response>> {
+        Box::pin(async move {
+            let url = request.url();
+            let key = match url.query() {
+                Some(q) => format!("{}?{}", url.path(), q),
+                None => url.path().to_string(),
+            };
+            self.requests
+                .lock()
+                .unwrap()
+                .push(format!("{} {}", request.method(), key));
+
+            let (status, headers, body) =
+                self.routes
+                    .get(&key)
+                    .cloned()
+                    .unwrap_or((404, vec![], vec![]));
+            let mut response = http::Response::builder().status(status).url(url.clone());
+            for (k, v) in headers {
+                response = response.header(k, v);
+            }
+            Ok(response.body(body).unwrap().into())
+        })
+    }
+}
+
+fn client(registry: Arc<InMemoryRegistry>) -> Client {
+    Client::configure()
+        .registry("registry.invalid")
+        .transport(registry)
+        .build()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_transport_v2_supported() {
+    let registry = Arc::new(InMemoryRegistry::default().route(
+        "/v2/",
+        200,
+        &[("Docker-Distribution-API-Version", "registry/2.0")],
+        b"",
+    ));
+
+    assert!(client(registry.clone()).is_v2_supported().await.unwrap());
+    assert_eq!(*registry.requests.lock().unwrap(), vec!["GET /v2/"]);
+}
+
+#[tokio::test]
+async fn test_transport_tags_paginated() {
+    let registry = Arc::new(
+        InMemoryRegistry::default()
+            .route(
+                "/v2/repo/tags/list?n=1",
+                200,
+                &[
+                    ("Content-Type", "application/json"),
+                    ("Link", "</v2/repo/tags/list?n=1&last=t1>; rel=\"next\""),
+                ],
+                br#"{"name":"repo","tags":["t1"]}"#,
+            )
+            .route(
+                "/v2/repo/tags/list?n=1&last=t1",
+                200,
+                &[("Content-Type", "application/json")],
+                br#"{"name":"repo","tags":["t2"]}"#,
+            ),
+    );
+
+    let dclient = client(registry);
+    let tags: Vec<String> = dclient
+        .get_tags("repo", Some(1))
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(tags, vec!["t1", "t2"]);
+}
+
+#[tokio::test]
+async fn test_transport_blob() {
+    let blob = b"in-memory blob";
+    let digest = DigestAlgorithm::Sha256.digest(blob);
+    let registry = Arc::new(InMemoryRegistry::default().route(
+        &format!("/v2/repo/blobs/{}", digest),
+        200,
+        &[],
+        blob,
+    ));
+
+    let dclient = client(registry);
+    assert_eq!(dclient.get_blob("repo", &digest).await.unwrap(), blob);
+}